@@ -0,0 +1,107 @@
+//! Timings of parsing and evaluating large sheets, run with `cargo bench`
+//!
+//! Every case is run a few times after a warm-up, the fastest and the median runs are printed
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use wix_sheets::data::{RawCellData, RawSheet};
+use wix_sheets::sheets::{operators, Position, Sheet};
+
+const RUNS: usize = 5;
+const ROWS: usize = 40_000;
+
+fn column(x: usize) -> String {
+    Position { x, y: 0 }.column()
+}
+
+// 26 columns: 20 numbers, 5 labels and the sum of the numbers, about a million cells
+fn arithmetic() -> RawSheet {
+    let data = (0..ROWS)
+        .map(|y| {
+            let mut row = (0..20)
+                .map(|x| match x % 2 {
+                    0 => RawCellData::Int((x * y) as i64 % 1000),
+                    _ => RawCellData::Float((x + y) as f64 / 8.0),
+                })
+                .collect::<Vec<_>>();
+            row.extend((0..5).map(|x| RawCellData::String(format!("label {}", (x + y) % 50))));
+            let refs = (0..20)
+                .map(|x| format!("{}{}", column(x), y + 1))
+                .collect::<Vec<_>>();
+            row.push(RawCellData::String(format!("=SUM({})", refs.join(", "))));
+            row
+        })
+        .collect::<Vec<_>>();
+
+    RawSheet {
+        id: "arithmetic".to_owned(),
+        data: data.into(),
+    }
+}
+
+// comparisons of numbers and text, which compare values of cells with each other
+fn comparisons() -> RawSheet {
+    let labels = (0..20)
+        .map(|i| format!("\"label {}\"", i))
+        .collect::<Vec<_>>()
+        .join(",");
+    let data = (0..ROWS)
+        .map(|y| {
+            let r = y + 1;
+            vec![
+                RawCellData::Int((y % 100) as i64),
+                RawCellData::String(format!("label {}", y % 30)),
+                RawCellData::String(format!("=GT(A{}, 50)", r)),
+                RawCellData::String(format!("=EQ(B{}, \"label 7\")", r)),
+                RawCellData::String(format!("=IF(EQ(A{}, 3), B{}, A{})", r, r, r)),
+                RawCellData::String(format!("=MATCH(B{}, {{{}}})", r, labels)),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    RawSheet {
+        id: "comparisons".to_owned(),
+        data: data.into(),
+    }
+}
+
+fn measure(name: &str, mut run: impl FnMut()) {
+    run();
+    let mut times = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .collect::<Vec<Duration>>();
+    times.sort();
+
+    println!(
+        "{:<24} fastest {:>8.1} ms   median {:>8.1} ms",
+        name,
+        times[0].as_secs_f64() * 1000.0,
+        times[RUNS / 2].as_secs_f64() * 1000.0
+    );
+}
+
+fn main() {
+    let ops = operators::get_default_op_map();
+
+    for raw in [arithmetic(), comparisons()] {
+        let cells = raw.data.iter().count();
+        println!("{} ({} cells)", raw.id, cells);
+
+        measure("  parse", || {
+            black_box(Sheet::from(raw.clone()));
+        });
+        let sheet = Sheet::from(raw.clone());
+        measure("  resolve_refs", || {
+            black_box(sheet.clone().resolve_refs(&ops));
+        });
+        let resolved = sheet.clone().resolve_refs(&ops);
+        measure("  compare resolved", || {
+            black_box(resolved == resolved.clone());
+        });
+    }
+}
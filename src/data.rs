@@ -1,26 +1,259 @@
 //! This module contains ADTs used for deserialization
 //! for data received from wix
 
-use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufReader, Read};
 
-#[derive(Debug, PartialEq, Deserialize)]
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::sheets::Position;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct RawData {
-    #[serde(rename = "submissionUrl")]
+    /// Missing in sheets which are not received from wix
+    #[serde(rename = "submissionUrl", default)]
     pub submission_url: String,
     pub sheets: Vec<RawSheet>,
+    /// Definitions of functions which can be used in all sheets,
+    /// e.g. `MARKUP(cost, price) = DIVIDE(price, cost)`
+    #[serde(default)]
+    pub functions: Vec<String>,
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct RawSheet {
     pub id: String,
-    pub data: Vec<Vec<RawCellData>>,
+    pub data: RawCells,
+}
+
+/// Cells of a [RawSheet], either rows of all cells, e.g. `[[1, null, "=A1"]]`,
+/// or only cells which are not blank by their names, e.g. `{"A1": 1, "C1": "=A1"}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawCells {
+    Rows(Vec<Vec<RawCellData>>),
+    /// Ordered by rows, then by columns, without repeated positions
+    Named(Vec<(Position, RawCellData)>),
+}
+
+impl RawCells {
+    pub fn get(&self, pos: Position) -> Option<&RawCellData> {
+        match self {
+            RawCells::Rows(rows) => rows.get(pos.y).and_then(|row| row.get(pos.x)),
+            RawCells::Named(cells) => cells
+                .binary_search_by_key(&(pos.y, pos.x), |(p, _)| (p.y, p.x))
+                .ok()
+                .map(|i| &cells[i].1),
+        }
+    }
+
+    /// All cells of rows, only named cells otherwise, ordered by rows
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (Position, &RawCellData)> + '_> {
+        match self {
+            RawCells::Rows(rows) => Box::new(rows.iter().enumerate().flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .map(move |(x, cell)| (Position { x, y }, cell))
+            })),
+            RawCells::Named(cells) => Box::new(cells.iter().map(|(pos, cell)| (*pos, cell))),
+        }
+    }
+}
+
+impl From<Vec<Vec<RawCellData>>> for RawCells {
+    fn from(rows: Vec<Vec<RawCellData>>) -> Self {
+        RawCells::Rows(rows)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawCells {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RawCellsVisitor)
+    }
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+struct RawCellsVisitor;
+
+impl<'de> Visitor<'de> for RawCellsVisitor {
+    type Value = RawCells;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of rows or an object of cells by their names")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RawCells, A::Error> {
+        let mut rows = Vec::new();
+        while let Some(row) = seq.next_element()? {
+            rows.push(row);
+        }
+        Ok(RawCells::Rows(rows))
+    }
+
+    // a repeated name takes the last of its cells
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RawCells, A::Error> {
+        let mut cells = BTreeMap::new();
+        while let Some(name) = map.next_key::<String>()? {
+            let pos = Position::from_name(&name)
+                .ok_or_else(|| de::Error::custom(format!("`{}` is not a name of a cell", name)))?;
+            // sheets are written with all of their rows and columns up to the last cell
+            if pos.x > Position::LAST.x || pos.y > Position::LAST.y {
+                return Err(de::Error::custom(format!(
+                    "`{}` is beyond the last cell of a sheet, {}",
+                    name,
+                    Position::LAST.name()
+                )));
+            }
+            cells.insert((pos.y, pos.x), map.next_value::<RawCellData>()?);
+        }
+        Ok(RawCells::Named(
+            cells
+                .into_iter()
+                .map(|((y, x), cell)| (Position { x, y }, cell))
+                .collect(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub enum RawCellData {
     String(String),
     Int(i64),
     Float(f64),
-    Bool(bool)
+    Bool(bool),
+    /// `null`, i.e. a blank cell
+    Empty,
+}
+
+/// A part of a [RawData] document, see [read_parts]
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawPart {
+    SubmissionUrl(String),
+    Functions(Vec<String>),
+    Sheet(RawSheet),
+}
+
+/// Errors of handlers of [read_parts]
+pub type HandlerError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("Could not read the data: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Handler(HandlerError),
+    /// Sheets are passed on as they are read, before functions which follow them are known
+    #[error("`functions` have to precede `sheets`, which are read before them")]
+    FunctionsAfterSheets,
+}
+
+/// Reads a [RawData] document from `reader` and passes its parts to `handle` in the order
+/// they are read, sheets one at a time, so only a single sheet is held in memory at once
+///
+/// `functions` have to precede `sheets`, since they can be used by all sheets,
+/// otherwise [ReadError::FunctionsAfterSheets] is returned.
+/// Reading stops at the first error returned by `handle`
+pub fn read_parts(
+    reader: impl Read,
+    mut handle: impl FnMut(RawPart) -> Result<(), HandlerError>,
+) -> Result<(), ReadError> {
+    let mut error = None;
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    let result = deserializer.deserialize_map(Parts {
+        handle: &mut handle,
+        error: &mut error,
+    });
+
+    // errors besides those of JSON are only described by the error of serde
+    if let Some(e) = error {
+        return Err(e);
+    }
+    result?;
+    Ok(deserializer.end()?)
+}
+
+struct Parts<'a, F> {
+    handle: &'a mut F,
+    error: &'a mut Option<ReadError>,
+}
+
+impl<F: FnMut(RawPart) -> Result<(), HandlerError>> Parts<'_, F> {
+    fn emit<E: de::Error>(&mut self, part: RawPart) -> Result<(), E> {
+        (self.handle)(part).map_err(|e| self.fail(ReadError::Handler(e)))
+    }
+
+    fn fail<E: de::Error>(&mut self, error: ReadError) -> E {
+        let message = error.to_string();
+        *self.error = Some(error);
+        E::custom(message)
+    }
+}
+
+impl<'de, F: FnMut(RawPart) -> Result<(), HandlerError>> Visitor<'de> for Parts<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object with sheets")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        let mut sheets = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "submissionUrl" => {
+                    let url = map.next_value()?;
+                    self.emit(RawPart::SubmissionUrl(url))?
+                }
+                "functions" => {
+                    let functions: Vec<String> = map.next_value()?;
+                    if sheets && !functions.is_empty() {
+                        return Err(self.fail(ReadError::FunctionsAfterSheets));
+                    }
+                    self.emit(RawPart::Functions(functions))?
+                }
+                "sheets" => {
+                    map.next_value_seed(Sheets(&mut self))?;
+                    sheets = true;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        match sheets {
+            true => Ok(()),
+            false => Err(de::Error::missing_field("sheets")),
+        }
+    }
+}
+
+struct Sheets<'a, 'b, F>(&'a mut Parts<'b, F>);
+
+impl<'de, F: FnMut(RawPart) -> Result<(), HandlerError>> DeserializeSeed<'de>
+    for Sheets<'_, '_, F>
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(RawPart) -> Result<(), HandlerError>> Visitor<'de> for Sheets<'_, '_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of sheets")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(sheet) = seq.next_element::<RawSheet>()? {
+            self.0.emit(RawPart::Sheet(sheet))?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,53 @@
+//! This module contains formats which sheets can be read from and written to,
+//! besides the JSON received from wix
+
+pub mod csv;
+pub mod formula;
+#[cfg(feature = "ods")]
+pub mod ods;
+pub mod render;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+#[cfg(any(feature = "xlsx", feature = "ods"))]
+use crate::data::RawCellData;
+use crate::types::{array::Array, box_value::BoxValue};
+
+// booleans are written the way they are read, an anchor of a spilled array
+// as the top left value of it, the rest of the array is in the cells it spilled into
+fn value_text(value: &BoxValue) -> String {
+    if let Some(b) = value.downcast_ref::<bool>() {
+        if *b { "TRUE" } else { "FALSE" }.to_owned()
+    } else if let Some(array) = value.downcast_ref::<Array>() {
+        value_text(array.top_left())
+    } else {
+        value.to_string()
+    }
+}
+
+// spreadsheets keep all numbers as floats, whole ones are read as integers
+#[cfg(any(feature = "xlsx", feature = "ods"))]
+fn number(f: f64) -> RawCellData {
+    if f.fract() == 0.0 && f.abs() < (1u64 << 53) as f64 {
+        RawCellData::Int(f as i64)
+    } else {
+        RawCellData::Float(f)
+    }
+}
+
+// value of the attribute with the local name `name`, e.g. `id` of `r:id`
+#[cfg(any(feature = "xlsx", feature = "ods"))]
+fn attribute(
+    e: &quick_xml::events::BytesStart,
+    name: &[u8],
+) -> Result<Option<String>, quick_xml::Error> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key.local_name().as_ref() == name {
+            let value = String::from_utf8_lossy(&attr.value);
+            return Ok(Some(quick_xml::escape::unescape(&value)?.into_owned()));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,254 @@
+//! Contains reading of sheets from CSV and writing of evaluated sheets to CSV
+//!
+//! Types of read cells are inferred: integers become [RawCellData::Int],
+//! decimals [RawCellData::Float], `TRUE` and `FALSE` [RawCellData::Bool],
+//! empty fields blank cells and anything else strings, so `=`-prefixed fields are formulas
+
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+use super::value_text;
+use crate::data::{RawCellData, RawSheet};
+use crate::sheets::{expr::Expr, CellError, Position, Sheet};
+
+#[derive(Debug, Error)]
+pub enum CsvError {
+    #[error("Invalid CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Could not write CSV: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Cell {0} is not evaluated")]
+    Unresolved(Position),
+}
+
+/// Which fields are quoted when writing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quoting {
+    /// Only fields containing the delimiter, quotes or line breaks
+    #[default]
+    Necessary,
+    Always,
+    /// All fields except numbers
+    NonNumeric,
+    /// No fields, which can produce CSV that can't be read back
+    Never,
+}
+
+/// How cells which evaluated to errors are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorRendering {
+    /// The whole message, e.g. `#ERROR: Division by zero`
+    #[default]
+    Message,
+    /// Only the code, e.g. `#ERROR`, see [CellError::code]
+    Code,
+    /// A blank field
+    Empty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Only used when writing
+    pub quoting: Quoting,
+    /// Only used when writing
+    pub errors: ErrorRendering,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quoting: Quoting::default(),
+            errors: ErrorRendering::default(),
+        }
+    }
+}
+
+/// Reads a sheet with the given `id`, rows can have different lengths
+pub fn read_sheet(
+    id: impl Into<String>,
+    reader: impl Read,
+    options: &CsvOptions,
+) -> Result<RawSheet, CsvError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+
+    let data = reader
+        .records()
+        .map(|record| Ok(record?.iter().map(infer).collect()))
+        .collect::<Result<Vec<_>, CsvError>>()?;
+
+    Ok(RawSheet {
+        id: id.into(),
+        data: data.into(),
+    })
+}
+
+fn infer(field: &str) -> RawCellData {
+    // `f64` also parses e.g. `inf` and `NaN`, which are strings here
+    let is_decimal = |f: &str| {
+        f.bytes()
+            .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b))
+            && f.bytes().any(|b| b.is_ascii_digit())
+    };
+
+    if field.is_empty() {
+        RawCellData::Empty
+    } else if field.eq_ignore_ascii_case("TRUE") {
+        RawCellData::Bool(true)
+    } else if field.eq_ignore_ascii_case("FALSE") {
+        RawCellData::Bool(false)
+    } else if let Ok(i) = field.parse() {
+        RawCellData::Int(i)
+    } else if let Some(f) = Some(field)
+        .filter(|f| is_decimal(f))
+        .and_then(|f| f.parse().ok())
+    {
+        RawCellData::Float(f)
+    } else {
+        RawCellData::String(field.to_owned())
+    }
+}
+
+/// Writes an evaluated sheet, shorter rows are padded with blank fields
+///
+/// An anchor of a spilled array is written as the top left value of it,
+/// the rest of the array is in the cells it spilled into
+pub fn write_sheet(
+    sheet: &Sheet,
+    writer: impl Write,
+    options: &CsvOptions,
+) -> Result<(), CsvError> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .quote_style(match options.quoting {
+            Quoting::Necessary => csv::QuoteStyle::Necessary,
+            Quoting::Always => csv::QuoteStyle::Always,
+            Quoting::NonNumeric => csv::QuoteStyle::NonNumeric,
+            Quoting::Never => csv::QuoteStyle::Never,
+        })
+        .from_writer(writer);
+
+    let width = sheet.width();
+    for (y, row) in sheet.cells.rows().enumerate() {
+        let mut fields = row
+            .into_iter()
+            .enumerate()
+            .map(|(x, cell)| field(cell, (x, y).into(), options))
+            .collect::<Result<Vec<_>, _>>()?;
+        fields.resize(width, String::new());
+        writer.write_record(&fields)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn field(cell: &Expr, pos: Position, options: &CsvOptions) -> Result<String, CsvError> {
+    match cell {
+        Expr::Value(v) => Ok(value_text(v)),
+        Expr::Err(e) => Ok(error_field(e, options.errors)),
+        _ => Err(CsvError::Unresolved(pos)),
+    }
+}
+
+fn error_field(e: &CellError, errors: ErrorRendering) -> String {
+    match errors {
+        ErrorRendering::Message => e.to_string(),
+        ErrorRendering::Code => e.code().to_owned(),
+        ErrorRendering::Empty => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheets::operators;
+
+    fn write(sheet: &Sheet, options: &CsvOptions) -> String {
+        let mut out = Vec::new();
+        write_sheet(sheet, &mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn infers_types() {
+        let sheet = read_sheet(
+            "sheet-csv",
+            "1,-2.5,true,FALSE,,text\n1e3,inf,\"=SUM(A1, B1)\"\n".as_bytes(),
+            &CsvOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(sheet.id, "sheet-csv");
+        assert_eq!(
+            sheet.data,
+            vec![
+                vec![
+                    RawCellData::Int(1),
+                    RawCellData::Float(-2.5),
+                    RawCellData::Bool(true),
+                    RawCellData::Bool(false),
+                    RawCellData::Empty,
+                    RawCellData::String("text".to_owned()),
+                ],
+                vec![
+                    RawCellData::Float(1000.0),
+                    RawCellData::String("inf".to_owned()),
+                    RawCellData::String("=SUM(A1, B1)".to_owned()),
+                ],
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn writes_evaluated_sheets() {
+        let ops = operators::get_default_op_map();
+        let options = CsvOptions {
+            delimiter: b';',
+            ..Default::default()
+        };
+        let raw = read_sheet(
+            "sheet-csv",
+            "2;3;=SUM(A1, B1)\n=GT(A1, B1);=DIVIDE(A1, 0);\"a;b\"\n=SEQUENCE(2)\n".as_bytes(),
+            &options,
+        );
+        let sheet = Sheet::from(raw.unwrap()).resolve_refs(&ops);
+
+        assert_eq!(
+            write(&sheet, &options),
+            "2;3;5\nFALSE;#ERROR: These errors have occurred in this formula: [DivByZero];\"a;b\"\n1;;\n2;;\n"
+        );
+
+        let options = CsvOptions {
+            quoting: Quoting::NonNumeric,
+            errors: ErrorRendering::Code,
+            ..Default::default()
+        };
+        assert_eq!(
+            write(&sheet, &options),
+            "2,3,5\n\"FALSE\",\"#ERROR\",\"a;b\"\n1,\"\",\"\"\n2,\"\",\"\"\n"
+        );
+
+        let unresolved = Sheet::from(RawSheet {
+            id: "sheet-csv".to_owned(),
+            data: vec![vec![RawCellData::String("=A2".to_owned())]].into(),
+        });
+        assert!(matches!(
+            write_sheet(&unresolved, Vec::new(), &options),
+            Err(CsvError::Unresolved(pos)) if pos == (0, 0).into()
+        ));
+        assert_eq!(
+            write_sheet(&unresolved, Vec::new(), &options)
+                .unwrap_err()
+                .to_string(),
+            "Cell A1 is not evaluated"
+        );
+    }
+}
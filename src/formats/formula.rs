@@ -0,0 +1,369 @@
+//! Contains translation of formulas of other spreadsheets into formulas of this crate and back
+//!
+//! Only the differences in syntax are translated, formulas this crate can't parse,
+//! e.g. `A1+B1` or references to other worksheets, evaluate to [CellError::ParseError]
+//! and functions this crate doesn't have to [CellError::NoOpFound]
+//!
+//! The parser ignores anything after a formula, e.g. `+B1` of `=A1+B1`,
+//! so such formulas are translated with a space after the `=`, which makes them malformed
+//!
+//! [CellError::ParseError]: crate::sheets::CellError::ParseError
+//! [CellError::NoOpFound]: crate::sheets::CellError::NoOpFound
+
+use crate::sheets::parse::parse_entry;
+
+/// Most cells a range, e.g. `A1:B3`, is expanded into, larger ranges are not translated
+pub const MAX_RANGE_CELLS: usize = 10_000;
+
+// prefixes Excel stores newer functions and lambda parameters with
+const PREFIXES: [&str; 3] = ["_xlfn.", "_xlws.", "_xlpm."];
+
+/// Translates a formula of Excel, without the leading `=`, into a formula of this crate
+///
+/// Absolute references become relative ones, `TRUE` and `FALSE` become `true` and `false`,
+/// `ANCHORARRAY(A1)` becomes `A1#` and a range becomes its cells as separate arguments,
+/// row by row, e.g. `SUM(A1:B2)` becomes `SUM(A1, B1, A2, B2)`.
+/// The latter only works for functions which take any number of values, not tables
+pub fn from_excel(formula: &str) -> String {
+    // absolute references, e.g. `$A$1`
+    let mut in_str = false;
+    let formula = formula
+        .chars()
+        .filter(|c| {
+            in_str ^= *c == '"';
+            in_str || *c != '$'
+        })
+        .collect::<String>();
+    let mut out = String::from("=");
+    let mut rest = formula.as_str();
+
+    while let Some(c) = rest.chars().next() {
+        if c == '"' {
+            let end = rest[1..].find('"').map_or(rest.len(), |i| i + 2);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            let (ident, after) = rest.split_at(end);
+            let ident = PREFIXES
+                .iter()
+                .find_map(|p| ident.strip_prefix(p))
+                .unwrap_or(ident);
+            rest = after;
+
+            if let Some((range, after)) = range(ident, rest) {
+                out.push_str(&range);
+                rest = after;
+            } else if let Some((anchor, after)) = anchor_array(ident, rest) {
+                out.push_str(anchor);
+                out.push('#');
+                rest = after;
+            } else if !rest.starts_with('(')
+                && (ident.eq_ignore_ascii_case("TRUE") || ident.eq_ignore_ascii_case("FALSE"))
+            {
+                out.push_str(&ident.to_ascii_lowercase());
+            } else {
+                out.push_str(ident);
+            }
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    match parse_entry(&out) {
+        Ok((rest, _)) if rest.trim().is_empty() => out,
+        _ => format!("= {}", &out[1..]),
+    }
+}
+
+/// Translates a formula of this crate, with the leading `=`, into a formula of Excel without it
+///
+/// `A1#` becomes `ANCHORARRAY(A1)` and names bound by `LET` and `LAMBDA` get the prefix Excel needs
+pub fn to_excel(formula: &str) -> String {
+    let formula = formula.strip_prefix('=').unwrap_or(formula).trim_start();
+    let tokens = tokens(formula);
+    let next = |i: usize| match tokens.get(i + 1) {
+        Some(Token::Char(c)) => Some(*c),
+        _ => None,
+    };
+
+    // names are identifiers which are not called, refs or booleans,
+    // a name bound to a lambda can be called too
+    let names = tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(i, t)| match t {
+            Token::Ident(ident)
+                if next(i) != Some('(')
+                    && cell(ident).is_none()
+                    && !ident.starts_with(|c: char| c.is_ascii_digit())
+                    && !["true", "false"].contains(&&*ident.to_ascii_lowercase()) =>
+            {
+                Some(*ident)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    let mut skip_hash = false;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Str(s) => out.push_str(s),
+            Token::Ident(ident) if next(i) == Some('#') && cell(ident).is_some() => {
+                out.push_str(&format!("ANCHORARRAY({})", ident));
+                skip_hash = true;
+            }
+            Token::Ident(ident) if names.contains(ident) => {
+                out.push_str(PREFIXES[2]);
+                out.push_str(ident);
+            }
+            Token::Ident(ident) => out.push_str(ident),
+            Token::Char('#') if skip_hash => skip_hash = false,
+            Token::Char(c) => out.push(*c),
+        }
+    }
+
+    out
+}
+
+// prefixes OpenDocument stores functions of other spreadsheets with
+const ODF_PREFIXES: [&str; 3] = ["COM.MICROSOFT.", "ORG.LIBREOFFICE.", "ORG.OPENOFFICE."];
+
+/// Translates a formula of OpenDocument, e.g. `of:=SUM([.A1:.A3])`, into a formula of this crate
+///
+/// References lose their brackets, arguments are separated by `;` instead of `,`,
+/// rows of arrays by `|` instead of `;`, and `TRUE()` and `FALSE()` are booleans,
+/// the rest is translated the same way as by [from_excel]
+pub fn from_odf(formula: &str) -> String {
+    // the namespace, e.g. `of:`
+    let formula = match formula.strip_prefix('=') {
+        Some(formula) => formula,
+        None => formula.split_once(":=").map_or(formula, |(_, f)| f),
+    };
+
+    let mut in_array = false;
+    let excel = outside_strings(formula, |part| {
+        let mut part = part.replace("TRUE()", "TRUE").replace("FALSE()", "FALSE");
+        for prefix in ODF_PREFIXES {
+            part = part.replace(prefix, "");
+        }
+
+        let mut out = String::new();
+        let mut rest = part.as_str();
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '[' => {
+                    let end = rest.find(']').unwrap_or(rest.len());
+                    out.push_str(&odf_reference(&rest[1..end]));
+                    rest = rest.get(end + 1..).unwrap_or_default();
+                    continue;
+                }
+                '{' | '}' => {
+                    in_array = c == '{';
+                    out.push(c);
+                }
+                ';' if in_array => out.push(','),
+                ';' => out.push_str(", "),
+                '|' if in_array => out.push(';'),
+                c => out.push(c),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+        out
+    });
+
+    from_excel(&excel)
+}
+
+// `.A1:.B2` into `A1:B2`, references to other tables keep their names, e.g. `Sheet2!A1`
+fn odf_reference(reference: &str) -> String {
+    reference
+        .split(':')
+        .map(|part| match part.strip_prefix('.') {
+            Some(cell) => cell.to_owned(),
+            None => match part.rsplit_once('.') {
+                Some((table, cell)) => format!("{}!{}", table, cell),
+                None => part.to_owned(),
+            },
+        })
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Translates a formula of this crate, with the leading `=`, into a formula of OpenDocument
+///
+/// The opposite of [from_odf], except that spilled references, e.g. `A1#`, stay the same
+pub fn to_odf(formula: &str) -> String {
+    let formula = formula.strip_prefix('=').unwrap_or(formula).trim_start();
+    let tokens = tokens(formula);
+    let mut out = String::from("of:=");
+    let mut in_array = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let called = matches!(tokens.get(i + 1), Some(Token::Char('(')));
+        let separated = i > 0 && matches!(tokens[i - 1], Token::Char(','));
+        match token {
+            Token::Str(s) => out.push_str(s),
+            Token::Ident(ident) if cell(ident).is_some() => {
+                out.push_str(&format!("[.{}]", ident));
+            }
+            Token::Ident(ident) if !called && ["true", "false"].contains(ident) => {
+                out.push_str(&format!("{}()", ident.to_ascii_uppercase()));
+            }
+            Token::Ident(ident) => out.push_str(ident),
+            Token::Char(c @ ('{' | '}')) => {
+                in_array = *c == '{';
+                out.push(*c);
+            }
+            Token::Char(',') => out.push(';'),
+            Token::Char(' ') if separated => {}
+            Token::Char(';') if in_array => out.push('|'),
+            Token::Char(c) => out.push(*c),
+        }
+    }
+
+    out
+}
+
+// applies `f` to the parts of `formula` which are not strings
+fn outside_strings(formula: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let mut out = String::new();
+    let mut rest = formula;
+
+    while !rest.is_empty() {
+        let start = rest.find('"').unwrap_or(rest.len());
+        out.push_str(&f(&rest[..start]));
+        rest = &rest[start..];
+
+        if !rest.is_empty() {
+            let end = rest[1..].find('"').map_or(rest.len(), |i| i + 2);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+
+    out
+}
+
+enum Token<'a> {
+    /// With the quotes
+    Str(&'a str),
+    /// Names of functions, refs and numbers
+    Ident(&'a str),
+    Char(char),
+}
+
+fn tokens(formula: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = formula;
+
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = if c == '"' {
+            let end = rest[1..].find('"').map_or(rest.len(), |i| i + 2);
+            (Token::Str(&rest[..end]), end)
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            (Token::Ident(&rest[..end]), end)
+        } else {
+            (Token::Char(c), c.len_utf8())
+        };
+
+        tokens.push(token);
+        rest = &rest[len..];
+    }
+
+    tokens
+}
+
+// column and row of a reference this crate can parse, i.e. with a single letter column
+fn cell(s: &str) -> Option<(u8, usize)> {
+    let column = *s.as_bytes().first().filter(|c| c.is_ascii_uppercase())?;
+    let row = Some(&s[1..])
+        .filter(|r| !r.is_empty() && r.bytes().all(|b| b.is_ascii_digit()))?
+        .parse()
+        .ok()
+        .filter(|r| *r > 0)?;
+
+    Some((column, row))
+}
+
+// expands `start:end`, where `start` was already read
+fn range<'a>(start: &str, rest: &'a str) -> Option<(String, &'a str)> {
+    let (x1, y1) = cell(start)?;
+    let rest = rest.strip_prefix(':')?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(rest.len());
+    let (x2, y2) = cell(&rest[..end])?;
+
+    let (xs, ys) = (x1.min(x2)..=x1.max(x2), y1.min(y2)..=y1.max(y2));
+    if xs.len() * ys.clone().count() > MAX_RANGE_CELLS {
+        return None;
+    }
+
+    let cells = ys
+        .flat_map(|y| xs.clone().map(move |x| format!("{}{}", x as char, y)))
+        .collect::<Vec<_>>();
+    Some((cells.join(", "), &rest[end..]))
+}
+
+// `ANCHORARRAY(A1)`, where `ANCHORARRAY` was already read
+fn anchor_array<'a>(ident: &str, rest: &'a str) -> Option<(&'a str, &'a str)> {
+    if !ident.eq_ignore_ascii_case("ANCHORARRAY") {
+        return None;
+    }
+
+    let rest = rest.strip_prefix('(')?;
+    let (anchor, rest) = rest.split_once(')')?;
+    let anchor = anchor.trim();
+    cell(anchor).map(|_| (anchor, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excel_formulas() {
+        assert_eq!(from_excel("SUM($A$1:B2, 3)"), "=SUM(A1, B1, A2, B2, 3)");
+        assert_eq!(
+            from_excel("_xlfn.LET(_xlpm.x, TRUE, IF(x, \"TRUE:$A1\", FALSE))"),
+            "=LET(x, true, IF(x, \"TRUE:$A1\", false))"
+        );
+        assert_eq!(from_excel("SUM(_xlfn.ANCHORARRAY(A1))"), "=SUM(A1#)");
+        // left for the parser to reject
+        assert_eq!(from_excel("A1+Sheet2!B1"), "= A1+Sheet2!B1");
+        assert_eq!(from_excel("SUM(A1:A20000)"), "= SUM(A1:A20000)");
+        assert_eq!(to_excel("= A1+B1"), "A1+B1");
+
+        assert_eq!(
+            to_excel("=SUM(A1#, \"B1#\", 1.5)"),
+            "SUM(ANCHORARRAY(A1), \"B1#\", 1.5)"
+        );
+        assert_eq!(
+            to_excel("=LET(f, LAMBDA(x, SUM(x, A1)), f(true))"),
+            "LET(_xlpm.f, LAMBDA(_xlpm.x, SUM(_xlpm.x, A1)), _xlpm.f(true))"
+        );
+    }
+
+    #[test]
+    fn odf_formulas() {
+        assert_eq!(from_odf("of:=SUM([.A1:.A2];3)"), "=SUM(A1, A2, 3)");
+        assert_eq!(
+            from_odf("of:=IF(TRUE();COM.MICROSOFT.CONCAT(\"a;[.b]\";{1;2|3;4}))"),
+            "=IF(true, CONCAT(\"a;[.b]\", {1,2;3,4}))"
+        );
+        assert_eq!(from_odf("of:=[$Sheet2.$A$1]"), "= Sheet2!A1");
+
+        assert_eq!(
+            to_odf("=IF(true, CONCAT(\"a,b\", {1,2;3,4}), A1)"),
+            "of:=IF(TRUE();CONCAT(\"a,b\";{1;2|3;4});[.A1])"
+        );
+    }
+}
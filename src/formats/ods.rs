@@ -0,0 +1,625 @@
+//! Contains reading of sheets from OpenDocument `.ods` spreadsheets, e.g. of LibreOffice,
+//! and writing of evaluated sheets to them, available with the `ods` feature
+//!
+//! Every table is a sheet with the name of the table as its id.
+//! Formulas are translated by [formula::from_odf] and [formula::to_odf]
+
+use std::io::{BufReader, Cursor, Read, Write};
+
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use thiserror::Error;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{attribute, formula, number};
+use crate::data::{RawCellData, RawSheet};
+use crate::sheets::{expr::Expr, Position, Sheet};
+use crate::types::{
+    box_value::BoxValue,
+    date::{Date, DateTime, Duration},
+    empty::Empty,
+    num::Num,
+};
+
+/// Most cells a table is read into, repeated rows and columns take almost no space in a file
+pub const MAX_CELLS: usize = 10_000_000;
+
+const MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+ <manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+ <manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+const CONTENT_START: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:of="urn:oasis:names:tc:opendocument:xmlns:of:1.2" office:version="1.2"><office:body><office:spreadsheet>"#;
+
+const CONTENT_END: &str = "</office:spreadsheet></office:body></office:document-content>";
+
+#[derive(Debug, Error)]
+pub enum OdsError {
+    #[error("Could not read or write the spreadsheet: {0}")]
+    Archive(#[from] zip::result::ZipError),
+    #[error("Could not read the spreadsheet: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("Could not read or write the spreadsheet: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("A table has more than {} cells", MAX_CELLS)]
+    TooLarge,
+    #[error("Cell {0} is not evaluated")]
+    Unresolved(Position),
+}
+
+/// Reads all tables of a spreadsheet, comments of cells are not a part of their text
+///
+/// Values which a matrix formula has spilled into are left blank,
+/// so that the formula can spill into them again
+pub fn read_workbook(mut reader: impl Read) -> Result<Vec<RawSheet>, OdsError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let content = BufReader::new(archive.by_name("content.xml")?);
+    let mut xml = quick_xml::Reader::from_reader(content);
+
+    let mut sheets = Vec::new();
+    let mut table = Table::default();
+    let mut cell: Option<Cell> = None;
+    let mut in_annotation = false;
+    let mut buf = Vec::new();
+    loop {
+        let event = xml.read_event_into(&mut buf)?;
+        let empty = matches!(event, Event::Empty(_));
+        match &event {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"table" => {
+                    table = Table::new(attribute(e, b"name")?.unwrap_or_default());
+                    if empty {
+                        sheets.push(std::mem::take(&mut table).finish());
+                    }
+                }
+                b"table-row" => {
+                    table.start_row(repeated(e, b"number-rows-repeated")?);
+                    if empty {
+                        table.end_row()?;
+                    }
+                }
+                b"table-cell" | b"covered-table-cell" => {
+                    let new = Cell::new(e)?;
+                    match empty {
+                        true => table.push(new)?,
+                        false => cell = Some(new),
+                    }
+                }
+                b"annotation" => in_annotation = !empty,
+                _ if in_annotation => {}
+                name => {
+                    if let Some(cell) = &mut cell {
+                        cell.start_text(name, e, empty)?;
+                    }
+                }
+            },
+            Event::Text(e) if !in_annotation => {
+                if let Some(cell) = cell.as_mut().filter(|c| c.in_paragraph) {
+                    cell.text.push_str(&e.unescape()?);
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"table" => sheets.push(std::mem::take(&mut table).finish()),
+                b"table-row" => table.end_row()?,
+                b"table-cell" | b"covered-table-cell" => {
+                    if let Some(cell) = cell.take() {
+                        table.push(cell)?;
+                    }
+                }
+                b"annotation" => in_annotation = false,
+                b"p" if !in_annotation => {
+                    if let Some(cell) = &mut cell {
+                        cell.in_paragraph = false;
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => return Ok(sheets),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+// e.g. `number-rows-repeated`, which is 1 when missing
+fn repeated(e: &BytesStart, name: &[u8]) -> Result<usize, OdsError> {
+    Ok(attribute(e, name)?
+        .and_then(|r| r.parse().ok())
+        .unwrap_or(1))
+}
+
+// a cell being read
+struct Cell {
+    formula: Option<String>,
+    value_type: Option<String>,
+    /// The attribute of the value type, e.g. `boolean-value` of `boolean`
+    value: Option<String>,
+    repeated: usize,
+    /// Columns and rows a matrix formula spans
+    matrix: Option<(usize, usize)>,
+    text: String,
+    paragraphs: usize,
+    in_paragraph: bool,
+}
+
+impl Cell {
+    fn new(e: &BytesStart) -> Result<Self, OdsError> {
+        let value_type = attribute(e, b"value-type")?;
+        let value: &[u8] = match value_type.as_deref() {
+            Some("boolean") => b"boolean-value",
+            Some("date") => b"date-value",
+            Some("time") => b"time-value",
+            Some("string") => b"string-value",
+            _ => b"value",
+        };
+        let spanned =
+            |name: &[u8]| attribute(e, name).map(|s| s.and_then(|s| s.parse::<usize>().ok()));
+        let matrix = match (
+            spanned(b"number-matrix-columns-spanned")?,
+            spanned(b"number-matrix-rows-spanned")?,
+        ) {
+            (None, None) => None,
+            (columns, rows) => Some((columns.unwrap_or(1), rows.unwrap_or(1))),
+        };
+
+        Ok(Self {
+            formula: attribute(e, b"formula")?,
+            value: attribute(e, value)?,
+            value_type,
+            repeated: repeated(e, b"number-columns-repeated")?,
+            matrix,
+            text: String::new(),
+            paragraphs: 0,
+            in_paragraph: false,
+        })
+    }
+
+    // paragraphs are lines of the text, spaces and tabs are elements of their own
+    fn start_text(&mut self, name: &[u8], e: &BytesStart, empty: bool) -> Result<(), OdsError> {
+        match name {
+            b"p" => {
+                if self.paragraphs > 0 {
+                    self.text.push('\n');
+                }
+                self.paragraphs += 1;
+                self.in_paragraph = !empty;
+            }
+            b"s" if self.in_paragraph => {
+                let spaces = attribute(e, b"c")?.and_then(|c| c.parse().ok());
+                self.text.push_str(&" ".repeat(spaces.unwrap_or(1)));
+            }
+            b"tab" if self.in_paragraph => self.text.push('\t'),
+            b"line-break" if self.in_paragraph => self.text.push('\n'),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // dates and times are ISO 8601, so they are read as text which is parsed into dates
+    fn raw(self) -> RawCellData {
+        if let Some(f) = self.formula {
+            return RawCellData::String(formula::from_odf(&f));
+        }
+
+        match self.value_type.as_deref() {
+            Some("float" | "percentage" | "currency") => self
+                .value
+                .and_then(|v| v.parse().ok())
+                .map_or(RawCellData::String(self.text), number),
+            Some("boolean") => RawCellData::Bool(self.value.as_deref() == Some("true")),
+            Some("date" | "time" | "string") => {
+                RawCellData::String(self.value.unwrap_or(self.text))
+            }
+            _ if self.text.is_empty() => RawCellData::Empty,
+            _ => RawCellData::String(self.text),
+        }
+    }
+}
+
+// a table being read, a row is added when it ends as it can be repeated
+#[derive(Default)]
+struct Table {
+    name: String,
+    data: Vec<Vec<RawCellData>>,
+    y: usize,
+    row: Vec<RawCellData>,
+    rows_repeated: usize,
+    /// Blank cells after the last one of `row`, trailing ones are never added
+    blanks: usize,
+    cells: usize,
+    /// Top left and bottom right cells of matrix formulas
+    areas: Vec<(Position, Position)>,
+}
+
+impl Table {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    fn start_row(&mut self, repeated: usize) {
+        self.rows_repeated = repeated;
+    }
+
+    fn push(&mut self, cell: Cell) -> Result<(), OdsError> {
+        let (repeated, matrix) = (cell.repeated, cell.matrix);
+        let raw = cell.raw();
+        if raw == RawCellData::Empty {
+            self.blanks = self.blanks.saturating_add(repeated);
+            return Ok(());
+        }
+
+        let len = self.row.len() + self.blanks.saturating_add(repeated);
+        if len > MAX_CELLS {
+            return Err(OdsError::TooLarge);
+        }
+        self.row
+            .resize(self.row.len() + self.blanks, RawCellData::Empty);
+        self.blanks = 0;
+
+        if let Some((columns, rows)) = matrix {
+            let from = Position {
+                x: self.row.len(),
+                y: self.y,
+            };
+            let to = Position {
+                x: from.x + columns.max(1) - 1,
+                y: from.y.saturating_add(rows.max(1) - 1),
+            };
+            self.areas.push((from, to));
+        }
+        self.row.resize(len, raw);
+        Ok(())
+    }
+
+    fn end_row(&mut self) -> Result<(), OdsError> {
+        let row = std::mem::take(&mut self.row);
+        self.blanks = 0;
+
+        if !row.is_empty() {
+            self.cells = self
+                .cells
+                .saturating_add(row.len().saturating_mul(self.rows_repeated));
+            if self.cells > MAX_CELLS {
+                return Err(OdsError::TooLarge);
+            }
+            self.data.resize_with(self.y, Vec::new);
+            self.data.resize(self.y + self.rows_repeated, row);
+        }
+        self.y = self.y.saturating_add(self.rows_repeated);
+        Ok(())
+    }
+
+    fn finish(mut self) -> RawSheet {
+        for (from, to) in &self.areas {
+            for y in from.y..=to.y.min(self.data.len().saturating_sub(1)) {
+                let row = &mut self.data[y];
+                for x in from.x..=to.x.min(row.len().saturating_sub(1)) {
+                    if (x, y) != (from.x, from.y) {
+                        row[x] = RawCellData::Empty;
+                    }
+                }
+                while row.last() == Some(&RawCellData::Empty) {
+                    row.pop();
+                }
+            }
+        }
+
+        RawSheet {
+            id: self.name,
+            data: self.data.into(),
+        }
+    }
+}
+
+/// Writes evaluated sheets as tables named by their ids
+///
+/// Formulas of cells are written too if there is a sheet with the same id in `sources`,
+/// their results are the evaluated values. A formula which spilled is written as a matrix formula
+/// over the values it spilled into, which are told apart from values of the sheet by being blank
+/// in the source, so a matrix ends before blank values at the edges of an array
+pub fn write_workbook(
+    writer: &mut impl Write,
+    sheets: &[Sheet],
+    sources: &[RawSheet],
+) -> Result<(), OdsError> {
+    let mut content = String::from(CONTENT_START);
+    for sheet in sheets {
+        let source = sources.iter().find(|s| s.id == sheet.id);
+        write_table(&mut content, sheet, source)?;
+    }
+    content.push_str(CONTENT_END);
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // the media type is the first file and is not compressed, so it can be recognized
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(MIMETYPE.as_bytes())?;
+    zip.start_file("META-INF/manifest.xml", SimpleFileOptions::default())?;
+    zip.write_all(MANIFEST.as_bytes())?;
+    zip.start_file("content.xml", SimpleFileOptions::default())?;
+    zip.write_all(content.as_bytes())?;
+
+    writer.write_all(&zip.finish()?.into_inner())?;
+    Ok(())
+}
+
+fn write_table(xml: &mut String, sheet: &Sheet, source: Option<&RawSheet>) -> Result<(), OdsError> {
+    let areas = source.map_or_else(Vec::new, |s| spill_areas(sheet, s));
+
+    xml.push_str(&format!(
+        r#"<table:table table:name="{}">"#,
+        escape(&sheet.id)
+    ));
+    xml.push_str(&format!(
+        r#"<table:table-column table:number-columns-repeated="{}"/>"#,
+        sheet.width().max(1)
+    ));
+    for (y, row) in sheet.cells.rows().enumerate() {
+        xml.push_str("<table:table-row>");
+        // a row has at least one cell
+        let mut row = row.peekable();
+        if row.peek().is_none() {
+            xml.push_str("<table:table-cell/>");
+        }
+
+        for (x, cell) in row.enumerate() {
+            let pos = Position { x, y };
+            let (mut attributes, text) = match cell {
+                Expr::Value(v) => value_xml(v),
+                Expr::Err(e) => (r#" office:value-type="string""#.to_owned(), e.to_string()),
+                _ => return Err(OdsError::Unresolved(pos)),
+            };
+
+            let raw = source.and_then(|s| s.data.get(pos));
+            if let Some(RawCellData::String(f)) = raw.filter(|r| is_formula(r)) {
+                let f = escape(&formula::to_odf(f)).into_owned();
+                attributes.insert_str(0, &format!(r#" table:formula="{}""#, f));
+                if let Some((_, to)) = areas.iter().find(|(from, _)| *from == pos) {
+                    attributes.push_str(&format!(
+                        r#" table:number-matrix-columns-spanned="{}" table:number-matrix-rows-spanned="{}""#,
+                        to.x - x + 1,
+                        to.y - y + 1
+                    ));
+                }
+            }
+
+            match text.is_empty() {
+                true => xml.push_str(&format!("<table:table-cell{}/>", attributes)),
+                false => {
+                    xml.push_str(&format!("<table:table-cell{}>", attributes));
+                    for line in text.split('\n') {
+                        xml.push_str(&format!("<text:p>{}</text:p>", escape(line)));
+                    }
+                    xml.push_str("</table:table-cell>");
+                }
+            }
+        }
+        xml.push_str("</table:table-row>");
+    }
+    xml.push_str("</table:table>");
+
+    Ok(())
+}
+
+fn is_formula(raw: &RawCellData) -> bool {
+    matches!(raw, RawCellData::String(f) if f.starts_with('='))
+}
+
+// attributes and text of a cell holding `value`
+fn value_xml(value: &BoxValue) -> (String, String) {
+    let text = value.to_string();
+    let attributes = if let Some(num) = value.downcast_ref::<Num>() {
+        format!(
+            r#" office:value-type="float" office:value="{}""#,
+            f64::from(num)
+        )
+    } else if let Some(b) = value.downcast_ref::<bool>() {
+        let attributes = format!(
+            r#" office:value-type="boolean" office:boolean-value="{}""#,
+            b
+        );
+        return (attributes, text.to_ascii_uppercase());
+    } else if value.downcast_ref::<Date>().is_some() || value.downcast_ref::<DateTime>().is_some() {
+        format!(r#" office:value-type="date" office:date-value="{}""#, text)
+    } else if value.downcast_ref::<Duration>().is_some() {
+        format!(r#" office:value-type="time" office:time-value="{}""#, text)
+    } else if value.downcast_ref::<Empty>().is_some() {
+        return (String::new(), String::new());
+    } else {
+        r#" office:value-type="string""#.to_owned()
+    };
+
+    (attributes, text)
+}
+
+// top left and bottom right cells of formulas which spilled, each formula takes
+// the largest area of values right and below of it which are blank in the source
+// and not taken by formulas before it
+fn spill_areas(sheet: &Sheet, source: &RawSheet) -> Vec<(Position, Position)> {
+    let mut areas: Vec<(Position, Position)> = Vec::new();
+
+    for (from, raw) in source.data.iter() {
+        if !is_formula(raw) {
+            continue;
+        }
+
+        let spilled = |p: Position| {
+            let blank = matches!(source.data.get(p), None | Some(RawCellData::Empty));
+            let filled = matches!(
+                sheet.get(p),
+                Some(Expr::Value(v)) if v.downcast_ref::<Empty>().is_none()
+            );
+            let taken = areas
+                .iter()
+                .any(|(from, to)| (from.x..=to.x).contains(&p.x) && (from.y..=to.y).contains(&p.y));
+            blank && filled && !taken
+        };
+
+        let Position { x, y } = from;
+        let mut to = from;
+        while spilled(Position { x: to.x + 1, y }) {
+            to.x += 1;
+        }
+        while (x..=to.x).all(|x| spilled(Position { x, y: to.y + 1 })) {
+            to.y += 1;
+        }
+        if to != from {
+            areas.push((from, to));
+        }
+    }
+
+    areas
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::data::RawCells;
+    use crate::sheets::operators;
+
+    // a spreadsheet as LibreOffice would write it
+    fn libreoffice_spreadsheet(tables: &str) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(MIMETYPE.as_bytes()).unwrap();
+        zip.start_file("content.xml", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(format!("{}{}{}", CONTENT_START, tables, CONTENT_END).as_bytes())
+            .unwrap();
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn reads_spreadsheets() {
+        let spreadsheet = libreoffice_spreadsheet(concat!(
+            r#"<table:table table:name="prices">"#,
+            r#"<table:table-column table:number-columns-repeated="1024"/>"#,
+            r#"<table:table-row><table:table-cell table:number-columns-repeated="2"/>"#,
+            r#"<table:table-cell office:value-type="float" office:value="2"><text:p>2</text:p></table:table-cell>"#,
+            r#"<table:table-cell office:value-type="float" office:value="2.5"><text:p>2.5</text:p></table:table-cell>"#,
+            r#"<table:table-cell table:number-columns-repeated="1020"/></table:table-row>"#,
+            r#"<table:table-row table:number-rows-repeated="2">"#,
+            r#"<table:table-cell office:value-type="boolean" office:boolean-value="true"><text:p>TRUE</text:p></table:table-cell>"#,
+            r#"<table:table-cell office:value-type="string"><office:annotation><text:p>note</text:p></office:annotation>"#,
+            r#"<text:p>a<text:s text:c="2"/>&lt;b&gt;</text:p><text:p>c</text:p></table:table-cell></table:table-row>"#,
+            r#"<table:table-row>"#,
+            r#"<table:table-cell table:formula="of:=SUM([.C1:.D1])" office:value-type="float" office:value="4.5"><text:p>4.5</text:p></table:table-cell>"#,
+            r#"<table:table-cell table:formula="of:=SEQUENCE(2)" table:number-matrix-columns-spanned="1" table:number-matrix-rows-spanned="2" office:value-type="float" office:value="1"><text:p>1</text:p></table:table-cell>"#,
+            r#"<table:table-cell office:value-type="date" office:date-value="2023-07-17"><text:p>07/17/23</text:p></table:table-cell>"#,
+            r#"</table:table-row>"#,
+            r#"<table:table-row><table:covered-table-cell/>"#,
+            r#"<table:table-cell office:value-type="float" office:value="2"><text:p>2</text:p></table:table-cell></table:table-row>"#,
+            r#"<table:table-row table:number-rows-repeated="1048570"><table:table-cell table:number-columns-repeated="1024"/></table:table-row>"#,
+            r#"</table:table>"#,
+            r#"<table:table table:name="empty"><table:table-column/><table:table-row><table:table-cell/></table:table-row></table:table>"#,
+        ));
+        let sheets = read_workbook(Cursor::new(spreadsheet)).unwrap();
+
+        assert_eq!(sheets.len(), 2);
+        assert_eq!(sheets[0].id, "prices");
+        let text = RawCellData::String("a  <b>\nc".to_owned());
+        assert_eq!(
+            sheets[0].data,
+            vec![
+                vec![
+                    RawCellData::Empty,
+                    RawCellData::Empty,
+                    RawCellData::Int(2),
+                    RawCellData::Float(2.5),
+                ],
+                vec![RawCellData::Bool(true), text.clone()],
+                vec![RawCellData::Bool(true), text],
+                vec![
+                    RawCellData::String("=SUM(C1, D1)".to_owned()),
+                    RawCellData::String("=SEQUENCE(2)".to_owned()),
+                    RawCellData::String("2023-07-17".to_owned()),
+                ],
+                vec![],
+            ]
+            .into()
+        );
+        assert_eq!(sheets[1].data, RawCells::Rows(vec![]));
+
+        let ops = operators::get_default_op_map();
+        let sheet = Sheet::from(sheets[0].clone()).resolve_refs(&ops);
+        assert_eq!(sheet.get((0, 3)), Some(&Num::F(4.5).into()));
+        assert_eq!(sheet.get((1, 4)), Some(&Num::I(2).into()));
+
+        let huge = libreoffice_spreadsheet(concat!(
+            r#"<table:table table:name="huge"><table:table-row table:number-rows-repeated="1048576">"#,
+            r#"<table:table-cell table:number-columns-repeated="16384" office:value-type="float" office:value="1"/>"#,
+            r#"</table:table-row></table:table>"#,
+        ));
+        assert!(matches!(
+            read_workbook(Cursor::new(huge)),
+            Err(OdsError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn writes_spreadsheets() {
+        let source = RawSheet {
+            id: "sheet-ods".to_owned(),
+            data: vec![
+                vec![
+                    RawCellData::Int(2),
+                    RawCellData::String("=SUM(A1, 1)".to_owned()),
+                    RawCellData::String("=SEQUENCE(2)".to_owned()),
+                ],
+                vec![
+                    RawCellData::String("=SUM(C1#)".to_owned()),
+                    RawCellData::Empty,
+                    RawCellData::Empty,
+                    RawCellData::String("a & b\nc".to_owned()),
+                ],
+            ]
+            .into(),
+        };
+        let ops = operators::get_default_op_map();
+        let sheet = Sheet::from(source.clone()).resolve_refs(&ops);
+
+        let read = |sources: &[RawSheet]| {
+            let mut out = Vec::new();
+            write_workbook(&mut out, std::slice::from_ref(&sheet), sources).unwrap();
+            read_workbook(Cursor::new(out)).unwrap().remove(0)
+        };
+
+        let values = read(&[]);
+        assert_eq!(values.id, "sheet-ods");
+        assert_eq!(
+            values.data,
+            vec![
+                vec![
+                    RawCellData::Int(2),
+                    RawCellData::Int(3),
+                    RawCellData::Int(1)
+                ],
+                vec![
+                    RawCellData::Int(3),
+                    RawCellData::Empty,
+                    RawCellData::Int(2),
+                    RawCellData::String("a & b\nc".to_owned()),
+                ],
+            ]
+            .into()
+        );
+
+        // formulas are read back the way they were written, without spilled values
+        let formulas = read(std::slice::from_ref(&source));
+        assert_eq!(formulas.data, source.data);
+    }
+}
@@ -0,0 +1,273 @@
+//! Contains rendering of evaluated sheets for reading by people:
+//! GitHub Markdown tables, HTML tables and tables drawn with box characters for terminals
+//!
+//! Values are rendered the same way as they are written to CSV
+
+use std::io::Write;
+
+use thiserror::Error;
+
+use super::value_text;
+use crate::sheets::{expr::Expr, CellError, Position, Sheet};
+use crate::types::num::Num;
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Could not write the table: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Cell {0} is not evaluated")]
+    Unresolved(Position),
+}
+
+enum Cell<'a> {
+    /// The text and whether it is a number, numbers are aligned to the right
+    Value(String, bool),
+    Error(&'a CellError),
+}
+
+// rows padded to the width of the sheet
+fn cells(sheet: &Sheet) -> Result<Vec<Vec<Cell<'_>>>, RenderError> {
+    let width = sheet.width();
+    sheet
+        .cells
+        .rows()
+        .enumerate()
+        .map(|(y, row)| {
+            let mut cells = row
+                .into_iter()
+                .enumerate()
+                .map(|(x, cell)| match cell {
+                    Expr::Value(v) => Ok(Cell::Value(
+                        value_text(v),
+                        v.downcast_ref::<Num>().is_some(),
+                    )),
+                    Expr::Err(e) => Ok(Cell::Error(e)),
+                    _ => Err(RenderError::Unresolved(Position { x, y })),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            cells.resize_with(width, || Cell::Value(String::new(), false));
+            Ok(cells)
+        })
+        .collect()
+}
+
+fn column_name(x: usize) -> String {
+    Position { x, y: 0 }.column()
+}
+
+/// Writes a sheet as a GitHub Markdown table with letters of the columns as its header,
+/// cells which evaluated to errors hold the messages of the errors
+pub fn write_markdown(sheet: &Sheet, mut writer: impl Write) -> Result<(), RenderError> {
+    let cells = cells(sheet)?;
+    let width = sheet.width();
+    if width == 0 {
+        return Ok(());
+    }
+
+    let escape = |s: &str| s.replace('|', "\\|").replace('\n', "<br>");
+    let header = (0..width).map(column_name).collect::<Vec<_>>();
+    writeln!(writer, "| {} |", header.join(" | "))?;
+    writeln!(writer, "|{}", " --- |".repeat(width))?;
+    for row in cells {
+        let fields = row
+            .iter()
+            .map(|cell| match cell {
+                Cell::Value(text, _) => escape(text),
+                Cell::Error(e) => escape(&e.to_string()),
+            })
+            .collect::<Vec<_>>();
+        writeln!(writer, "| {} |", fields.join(" | "))?;
+    }
+
+    Ok(())
+}
+
+/// Writes a sheet as an HTML table with its id as the caption and letters of the columns
+/// and numbers of the rows as headers
+///
+/// Cells which evaluated to errors have the `error` class and hold the code of the error,
+/// e.g. `#SPILL!`, with the message as their title, which is shown as a tooltip
+pub fn write_html(sheet: &Sheet, mut writer: impl Write) -> Result<(), RenderError> {
+    let cells = cells(sheet)?;
+
+    writeln!(writer, "<table>")?;
+    writeln!(writer, "  <caption>{}</caption>", escape_html(&sheet.id))?;
+    write!(writer, "  <thead><tr><th></th>")?;
+    for x in 0..sheet.width() {
+        write!(writer, "<th>{}</th>", column_name(x))?;
+    }
+    writeln!(writer, "</tr></thead>")?;
+
+    writeln!(writer, "  <tbody>")?;
+    for (y, row) in cells.iter().enumerate() {
+        write!(writer, "    <tr><th>{}</th>", y + 1)?;
+        for cell in row {
+            match cell {
+                Cell::Value(text, true) => write!(writer, "<td class=\"number\">{}</td>", text)?,
+                Cell::Value(text, false) => {
+                    let text = escape_html(text).replace('\n', "<br>");
+                    write!(writer, "<td>{}</td>", text)?
+                }
+                Cell::Error(e) => write!(
+                    writer,
+                    "<td class=\"error\" title=\"{}\">{}</td>",
+                    escape_html(&e.to_string()),
+                    escape_html(e.code())
+                )?,
+            }
+        }
+        writeln!(writer, "</tr>")?;
+    }
+    writeln!(writer, "  </tbody>")?;
+    writeln!(writer, "</table>")?;
+
+    Ok(())
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Writes a sheet as a table drawn with box characters, with letters of the columns
+/// and numbers of the rows as headers, to be printed in a terminal
+///
+/// Cells which evaluated to errors hold the messages of the errors, line breaks are spaces
+pub fn write_table(sheet: &Sheet, mut writer: impl Write) -> Result<(), RenderError> {
+    let mut rows = vec![std::iter::once(String::new())
+        .chain((0..sheet.width()).map(column_name))
+        .map(|name| (name, false))
+        .collect::<Vec<_>>()];
+    for (y, row) in cells(sheet)?.into_iter().enumerate() {
+        let cells = row.into_iter().map(|cell| match cell {
+            Cell::Value(text, number) => (text.replace('\n', " "), number),
+            Cell::Error(e) => (e.to_string(), false),
+        });
+        rows.push(
+            std::iter::once(((y + 1).to_string(), true))
+                .chain(cells)
+                .collect(),
+        );
+    }
+
+    let widths = (0..rows[0].len())
+        .map(|x| {
+            rows.iter()
+                .map(|row| row[x].0.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let line = |left: &str, middle: &str, right: &str| {
+        let lines = widths.iter().map(|w| "─".repeat(w + 2)).collect::<Vec<_>>();
+        format!("{}{}{}", left, lines.join(middle), right)
+    };
+
+    writeln!(writer, "{}", line("┌", "┬", "┐"))?;
+    for (y, row) in rows.iter().enumerate() {
+        if y == 1 {
+            writeln!(writer, "{}", line("├", "┼", "┤"))?;
+        }
+        write!(writer, "│")?;
+        for ((text, number), width) in row.iter().zip(&widths) {
+            match number {
+                true => write!(writer, " {:>width$} │", text, width = width)?,
+                false => write!(writer, " {:<width$} │", text, width = width)?,
+            }
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "{}", line("└", "┴", "┘"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{RawCellData, RawSheet};
+    use crate::sheets::operators;
+
+    fn sheet() -> Sheet {
+        let raw = RawSheet {
+            id: "sheet-<1>".to_owned(),
+            data: vec![
+                vec![
+                    RawCellData::Int(2),
+                    RawCellData::String("a|b".to_owned()),
+                    RawCellData::String("=GT(A1, 1)".to_owned()),
+                ],
+                vec![
+                    RawCellData::String("=DIVIDE(A1, 0)".to_owned()),
+                    RawCellData::String("=SUM(A3, B3)".to_owned()),
+                ],
+                vec![RawCellData::Float(2.5), RawCellData::Int(1)],
+            ]
+            .into(),
+        };
+        Sheet::from(raw).resolve_refs(&operators::get_default_op_map())
+    }
+
+    fn render(write: fn(&Sheet, &mut Vec<u8>) -> Result<(), RenderError>) -> String {
+        let mut out = Vec::new();
+        write(&sheet(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn renders_markdown() {
+        assert_eq!(
+            render(|s, w| write_markdown(s, w)),
+            "| A | B | C |\n\
+             | --- | --- | --- |\n\
+             | 2 | a\\|b | TRUE |\n\
+             | #ERROR: These errors have occurred in this formula: [DivByZero] | 3.5 |  |\n\
+             | 2.5 | 1 |  |\n"
+        );
+    }
+
+    #[test]
+    fn renders_html() {
+        assert_eq!(
+            render(|s, w| write_html(s, w)),
+            "<table>\n  \
+             <caption>sheet-&lt;1&gt;</caption>\n  \
+             <thead><tr><th></th><th>A</th><th>B</th><th>C</th></tr></thead>\n  \
+             <tbody>\n    \
+             <tr><th>1</th><td class=\"number\">2</td><td>a|b</td><td>TRUE</td></tr>\n    \
+             <tr><th>2</th><td class=\"error\" title=\"#ERROR: These errors have occurred in this formula: [DivByZero]\">#ERROR</td>\
+             <td class=\"number\">3.5</td><td></td></tr>\n    \
+             <tr><th>3</th><td class=\"number\">2.5</td><td class=\"number\">1</td><td></td></tr>\n  \
+             </tbody>\n\
+             </table>\n"
+        );
+    }
+
+    #[test]
+    fn renders_terminal_tables() {
+        let sheet = Sheet::from(RawSheet {
+            id: "sheet".to_owned(),
+            data: vec![
+                vec![RawCellData::Int(10), RawCellData::String("a\nb".to_owned())],
+                vec![RawCellData::Int(2)],
+            ]
+            .into(),
+        })
+        .resolve_refs(&operators::get_default_op_map());
+        let mut out = Vec::new();
+        write_table(&sheet, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "┌───┬────┬─────┐\n\
+             │   │ A  │ B   │\n\
+             ├───┼────┼─────┤\n\
+             │ 1 │ 10 │ a b │\n\
+             │ 2 │  2 │     │\n\
+             └───┴────┴─────┘\n"
+        );
+    }
+}
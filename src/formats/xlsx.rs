@@ -0,0 +1,417 @@
+//! Contains reading of sheets from `.xlsx` workbooks and writing of evaluated sheets to them,
+//! available with the `xlsx` feature
+//!
+//! Every worksheet is a sheet with the name of the worksheet as its id.
+//! Formulas are translated by [formula::from_excel], so a formula this crate can't parse
+//! or a function it doesn't have is an error of that cell, not of the whole workbook
+
+use std::collections::HashMap;
+use std::io::{BufReader, Cursor, Read, Write};
+
+use calamine::{Data, Reader, Xlsx};
+use quick_xml::events::{BytesStart, Event};
+use rust_xlsxwriter::{Formula, Workbook, Worksheet};
+use thiserror::Error;
+use zip::ZipArchive;
+
+use super::{attribute, formula, number, value_text};
+use crate::data::{RawCellData, RawSheet};
+use crate::sheets::{expr::Expr, Position, Sheet};
+use crate::types::{
+    box_value::BoxValue,
+    date::{Date, DateTime, Duration},
+    empty::Empty,
+    num::Num,
+};
+
+#[derive(Debug, Error)]
+pub enum XlsxError {
+    #[error("Could not read the workbook: {0}")]
+    Read(#[from] calamine::XlsxError),
+    #[error("Could not read the workbook: {0}")]
+    Archive(#[from] zip::result::ZipError),
+    #[error("Could not read the workbook: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("Could not write the workbook: {0}")]
+    Write(#[from] rust_xlsxwriter::XlsxError),
+    #[error("Could not write the workbook: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Cell {0} is not evaluated")]
+    Unresolved(Position),
+}
+
+/// Reads all worksheets of a workbook, cells before the first used row and column are blank
+///
+/// Values which an array formula has spilled into are left blank,
+/// so that the formula can spill into them again
+pub fn read_workbook(mut reader: impl Read) -> Result<Vec<RawSheet>, XlsxError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut areas = array_areas(&bytes)?;
+    let mut workbook: Xlsx<_> = Xlsx::new(Cursor::new(bytes))?;
+
+    workbook
+        .sheet_names()
+        .into_iter()
+        .map(|name| {
+            let mut data = Vec::new();
+
+            let values = workbook.worksheet_range(&name)?;
+            let (top, left) = values.start().unwrap_or_default();
+            for (y, x, value) in values.used_cells() {
+                let pos = (left as usize + x, top as usize + y);
+                set(&mut data, pos.into(), raw_cell(value));
+            }
+
+            for (from, to) in areas.remove(&name).unwrap_or_default() {
+                for y in from.y..=to.y.min(data.len().saturating_sub(1)) {
+                    let row = &mut data[y];
+                    for x in from.x..=to.x.min(row.len().saturating_sub(1)) {
+                        row[x] = RawCellData::Empty;
+                    }
+                }
+            }
+
+            // a formula replaces its cached result
+            let formulas = workbook.worksheet_formula(&name)?;
+            let (top, left) = formulas.start().unwrap_or_default();
+            for (y, x, f) in formulas.used_cells() {
+                let pos = (left as usize + x, top as usize + y);
+                set(
+                    &mut data,
+                    pos.into(),
+                    RawCellData::String(formula::from_excel(f)),
+                );
+            }
+
+            Ok(RawSheet {
+                id: name,
+                data: data.into(),
+            })
+        })
+        .collect()
+}
+
+// top left and bottom right cells of array formulas by names of worksheets,
+// the formulas themselves are read by calamine
+fn array_areas(bytes: &[u8]) -> Result<HashMap<String, Vec<(Position, Position)>>, XlsxError> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+
+    // worksheets are found by their relationship ids
+    let mut sheets = Vec::new();
+    read_xml(&mut archive, "xl/workbook.xml", |e| {
+        if e.local_name().as_ref() == b"sheet" {
+            let (name, id) = (attribute(e, b"name")?, attribute(e, b"id")?);
+            sheets.extend(name.zip(id));
+        }
+        Ok(())
+    })?;
+    let mut paths = HashMap::new();
+    read_xml(&mut archive, "xl/_rels/workbook.xml.rels", |e| {
+        if e.local_name().as_ref() == b"Relationship" {
+            let (id, target) = (attribute(e, b"Id")?, attribute(e, b"Target")?);
+            if let Some((id, target)) = id.zip(target) {
+                let path = match target.strip_prefix('/') {
+                    Some(path) => path.to_owned(),
+                    None => format!("xl/{}", target),
+                };
+                paths.insert(id, path);
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut areas = HashMap::new();
+    for (name, id) in sheets {
+        let Some(path) = paths.get(&id) else {
+            continue;
+        };
+
+        let mut sheet_areas = Vec::new();
+        read_xml(&mut archive, path, |e| {
+            if e.local_name().as_ref() == b"f" && attribute(e, b"t")?.as_deref() == Some("array") {
+                sheet_areas.extend(attribute(e, b"ref")?.as_deref().and_then(area));
+            }
+            Ok(())
+        })?;
+        areas.insert(name, sheet_areas);
+    }
+
+    Ok(areas)
+}
+
+// calls `f` with every element of the file at `path`, a missing file has no elements
+fn read_xml(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    path: &str,
+    mut f: impl FnMut(&BytesStart) -> Result<(), XlsxError>,
+) -> Result<(), XlsxError> {
+    let file = match archive.by_name(path) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut reader = quick_xml::Reader::from_reader(BufReader::new(file));
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e) => f(e)?,
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+// e.g. `B2:C10` or `B2`
+fn area(s: &str) -> Option<(Position, Position)> {
+    let position = |s: &str| {
+        let digits = s.find(|c: char| c.is_ascii_digit())?;
+        let (column, row) = s.split_at(digits);
+        let x = column
+            .bytes()
+            .try_fold(0usize, |x, c| {
+                c.is_ascii_uppercase()
+                    .then(|| x * 26 + (c - b'A') as usize + 1)
+            })?
+            .checked_sub(1)?;
+        let y = row.parse::<usize>().ok()?.checked_sub(1)?;
+        Some(Position { x, y })
+    };
+
+    match s.split_once(':') {
+        Some((from, to)) => Some((position(from)?, position(to)?)),
+        None => position(s).map(|p| (p, p)),
+    }
+}
+
+fn set(data: &mut Vec<Vec<RawCellData>>, pos: Position, cell: RawCellData) {
+    if data.len() <= pos.y {
+        data.resize_with(pos.y + 1, Vec::new);
+    }
+    let row = &mut data[pos.y];
+    if row.len() <= pos.x {
+        row.resize_with(pos.x + 1, || RawCellData::Empty);
+    }
+    row[pos.x] = cell;
+}
+
+// Dates and errors are read as text, which is parsed into dates or kept as a string
+fn raw_cell(value: &Data) -> RawCellData {
+    match value {
+        Data::Int(i) => RawCellData::Int(*i),
+        Data::Float(f) => number(*f),
+        Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => {
+            RawCellData::String(s.clone())
+        }
+        Data::Bool(b) => RawCellData::Bool(*b),
+        Data::DateTime(d) => {
+            let text = if d.is_duration() {
+                d.as_duration().map(|d| Duration(d).to_string())
+            } else {
+                d.as_datetime()
+                    .map(|dt| match dt.time() == Default::default() {
+                        true => Date(dt.date()).to_string(),
+                        false => DateTime(dt).to_string(),
+                    })
+            };
+            text.map_or(RawCellData::Float(d.as_f64()), RawCellData::String)
+        }
+        Data::Error(e) => RawCellData::String(e.to_string()),
+        Data::Empty => RawCellData::Empty,
+    }
+}
+
+/// Writes evaluated sheets as worksheets named by their ids
+///
+/// Formulas of cells are written too if there is a sheet with the same id in `sources`,
+/// their results are the evaluated values. Values spilled by formulas are not written then,
+/// otherwise they would block spilling of the formulas
+pub fn write_workbook(
+    writer: &mut impl Write,
+    sheets: &[Sheet],
+    sources: &[RawSheet],
+) -> Result<(), XlsxError> {
+    let mut workbook = Workbook::new();
+
+    for sheet in sheets {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(&sheet.id)?;
+        let source = sources.iter().find(|s| s.id == sheet.id);
+        write_worksheet(worksheet, sheet, source)?;
+    }
+
+    writer.write_all(&workbook.save_to_buffer()?)?;
+    Ok(())
+}
+
+fn write_worksheet(
+    worksheet: &mut Worksheet,
+    sheet: &Sheet,
+    source: Option<&RawSheet>,
+) -> Result<(), XlsxError> {
+    // blank cells of sparse sheets are not stored
+    for (pos, cell) in sheet.cells.iter() {
+        let (row, col) = (pos.y as u32, pos.x as u16);
+        let result = match cell {
+            Expr::Value(v) => Ok(v),
+            Expr::Err(e) => Err(e.to_string()),
+            _ => return Err(XlsxError::Unresolved(pos)),
+        };
+
+        let raw = source.map(|s| s.data.get(pos));
+        match (raw, result) {
+            (Some(Some(RawCellData::String(f))), result) if f.starts_with('=') => {
+                let result = result.map_or_else(|e| e, value_text);
+                let formula = Formula::new(formula::to_excel(f)).set_result(result);
+                worksheet.write_formula(row, col, formula)?;
+            }
+            // only spilling fills a blank cell
+            (Some(None | Some(RawCellData::Empty)), _) => {}
+            (_, Ok(v)) => write_value(worksheet, pos, v)?,
+            (_, Err(e)) => {
+                worksheet.write_string(row, col, e)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_value(
+    worksheet: &mut Worksheet,
+    pos: Position,
+    value: &BoxValue,
+) -> Result<(), XlsxError> {
+    let (row, col) = (pos.y as u32, pos.x as u16);
+
+    if let Some(num) = value.downcast_ref::<Num>() {
+        worksheet.write_number(row, col, f64::from(num))?;
+    } else if let Some(b) = value.downcast_ref::<bool>() {
+        worksheet.write_boolean(row, col, *b)?;
+    } else if value.downcast_ref::<Empty>().is_none() {
+        worksheet.write_string(row, col, value_text(value))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::data::RawCells;
+    use crate::sheets::{operators, CellError};
+
+    // a workbook as Excel would write it
+    fn excel_workbook() -> Vec<u8> {
+        let mut workbook = Workbook::new();
+        let worksheet = workbook.add_worksheet().set_name("prices").unwrap();
+        worksheet.write_number(1, 1, 2.0).unwrap();
+        worksheet.write_number(1, 2, 2.5).unwrap();
+        worksheet.write_string(1, 3, "text").unwrap();
+        worksheet.write_boolean(2, 1, true).unwrap();
+        worksheet.write_formula(2, 2, "=SUM($B$2:C2)").unwrap();
+        worksheet.write_formula(2, 3, "=B2+C2").unwrap();
+        worksheet
+            .write_formula(3, 1, "=XLOOKUP(1, B2:C2, B2:C2)")
+            .unwrap();
+        // Excel keeps values an array formula has spilled into
+        worksheet
+            .write_dynamic_array_formula(3, 2, 4, 2, "=SEQUENCE(2)")
+            .unwrap();
+        worksheet.write_number(4, 2, 2.0).unwrap();
+        workbook.add_worksheet().set_name("empty").unwrap();
+
+        workbook.save_to_buffer().unwrap()
+    }
+
+    #[test]
+    fn reads_workbooks() {
+        let sheets = read_workbook(Cursor::new(excel_workbook())).unwrap();
+
+        assert_eq!(sheets.len(), 2);
+        assert_eq!(sheets[0].id, "prices");
+        assert_eq!(
+            sheets[0].data,
+            vec![
+                vec![],
+                vec![
+                    RawCellData::Empty,
+                    RawCellData::Int(2),
+                    RawCellData::Float(2.5),
+                    RawCellData::String("text".to_owned()),
+                ],
+                vec![
+                    RawCellData::Empty,
+                    RawCellData::Bool(true),
+                    RawCellData::String("=SUM(B2, C2)".to_owned()),
+                    RawCellData::String("= B2+C2".to_owned()),
+                ],
+                vec![
+                    RawCellData::Empty,
+                    RawCellData::String("=XLOOKUP(1, B2, C2, B2, C2)".to_owned()),
+                    RawCellData::String("=SEQUENCE(2)".to_owned()),
+                ],
+                vec![RawCellData::Empty, RawCellData::Empty, RawCellData::Empty],
+            ]
+            .into()
+        );
+        assert_eq!(sheets[1].data, RawCells::Rows(vec![]));
+
+        let ops = operators::get_default_op_map();
+        let sheet = Sheet::from(sheets[0].clone()).resolve_refs(&ops);
+        assert_eq!(sheet.get((2, 2)), Some(&Num::F(4.5).into()));
+        assert_eq!(sheet.get((3, 2)), Some(&CellError::ParseError.into()));
+        assert!(matches!(
+            sheet.get((1, 3)),
+            Some(Expr::Err(CellError::NoOpFound(name, _))) if name == "XLOOKUP"
+        ));
+        assert_eq!(sheet.get((2, 4)), Some(&Num::I(2).into()));
+    }
+
+    #[test]
+    fn writes_workbooks() {
+        let source = RawSheet {
+            id: "sheet-xlsx".to_owned(),
+            data: vec![
+                vec![
+                    RawCellData::Int(2),
+                    RawCellData::String("=SUM(A1, 1)".to_owned()),
+                    RawCellData::String("=SEQUENCE(2)".to_owned()),
+                ],
+                vec![RawCellData::String("=SUM(C1#)".to_owned())],
+            ]
+            .into(),
+        };
+        let ops = operators::get_default_op_map();
+        let sheet = Sheet::from(source.clone()).resolve_refs(&ops);
+
+        let read = |sources: &[RawSheet]| {
+            let mut out = Vec::new();
+            write_workbook(&mut out, std::slice::from_ref(&sheet), sources).unwrap();
+            read_workbook(Cursor::new(out)).unwrap().remove(0)
+        };
+
+        let values = read(&[]);
+        assert_eq!(values.id, "sheet-xlsx");
+        assert_eq!(
+            values.data,
+            vec![
+                vec![
+                    RawCellData::Int(2),
+                    RawCellData::Int(3),
+                    RawCellData::Int(1)
+                ],
+                vec![RawCellData::Int(3), RawCellData::Empty, RawCellData::Int(2)],
+            ]
+            .into()
+        );
+
+        // formulas are read back the way they were written, without spilled values
+        let formulas = read(std::slice::from_ref(&source));
+        assert_eq!(formulas.data, source.data);
+    }
+}
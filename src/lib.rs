@@ -1,8 +1,5 @@
-#![feature(trait_upcasting)]
-#![allow(incomplete_features)]
-#![feature(generic_const_exprs)]
-
 pub mod data;
+pub mod formats;
 pub mod sheets;
 pub mod types;
 // this file is needed in order for tests in '/tests' directory to work
@@ -1,54 +1,489 @@
-#![feature(trait_upcasting)]
-#![allow(incomplete_features)]
-#![feature(generic_const_exprs)]
-
 pub mod data;
+pub mod formats;
 pub mod sheets;
 pub mod types;
 
-use std::{convert::Into, env, error::Error};
+use std::{
+    convert::Into,
+    env,
+    error::Error,
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
 
-use jsonway::{ObjectBuilder, Serializer};
-use reqwest;
-use serde_json;
-use wix_sheets::{data::RawData, sheets::Sheet};
+use serde::Serialize;
+use serde_json::{value::RawValue, Value as SerdeValue};
+#[cfg(feature = "ods")]
+use wix_sheets::formats::ods;
+#[cfg(feature = "xlsx")]
+use wix_sheets::formats::xlsx;
+use wix_sheets::{
+    data::{read_parts, HandlerError, RawData, RawPart, RawSheet},
+    formats::{
+        csv::{self, CsvOptions, ErrorRendering, Quoting},
+        render,
+    },
+    sheets::{operators::OpRegistry, resolve_sheets, EvalOptions, Sheet},
+    types::text::Interner,
+};
 
-const HUB_URL_GET: &'static str =
+const HUB_URL_GET: &str =
     "https://www.wix.com/_serverless/hiring-task-spreadsheet-evaluator/sheets?tag=circular_reference";
 
+#[derive(Serialize)]
 struct Results {
     email: String,
-    results: Vec<Sheet>,
+    /// Sheets in the format of the submission, see `impl From<Sheet> for SerdeValue`,
+    /// kept as JSON text, which is much smaller than evaluated sheets
+    results: Vec<Box<RawValue>>,
 }
-impl jsonway::Serializer for Results {
-    fn build(&self, json: &mut ObjectBuilder) {
-        json.set("email", self.email.clone());
-        json.array("results", |j_array| {
-            self.results
-                .iter()
-                .for_each(|s| j_array.push_json(s.clone().into()))
-        });
+
+/// Prints help of the operator named `filter` if there is one,
+/// otherwise lists all operators whose name or description contain `filter`
+fn print_functions(ops: &OpRegistry, filter: Option<&str>) {
+    if let Some(help) = filter.and_then(|f| ops.help(f)) {
+        print!("{}", help);
+        return;
     }
+
+    let helps = match filter {
+        Some(f) => ops.search(f),
+        None => ops.helps(),
+    };
+
+    if helps.is_empty() {
+        let filter = filter.unwrap_or_default();
+        match ops.suggest(filter) {
+            Some(name) => println!("No functions match `{}`, did you mean {}?", filter, name),
+            None => println!("No functions match `{}`", filter),
+        }
+        return;
+    }
+
+    helps
+        .iter()
+        .for_each(|h| println!("{:<50} {}", h.signature(), h.description));
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let data_str = reqwest::blocking::get(HUB_URL_GET)?.text()?;
+/// Registers operators from scripts listed in `WIX_SHEETS_SCRIPTS`,
+/// paths are separated the same way as in `PATH`
+#[cfg(feature = "scripting")]
+fn register_scripts(ops: &mut OpRegistry) -> Result<(), Box<dyn Error>> {
+    use wix_sheets::sheets::operators::script::{register_script_file, ScriptLimits};
+
+    let Some(paths) = env::var_os("WIX_SHEETS_SCRIPTS") else {
+        return Ok(());
+    };
+    for path in env::split_paths(&paths) {
+        register_script_file(ops, &path, &ScriptLimits::default())?;
+    }
 
-    // deserialize data
-    let data_raw: RawData = serde_json::from_str(&data_str)?;
-    let url = data_raw.submission_url.clone();
+    Ok(())
+}
 
-    // parse & compute fields
-    let data: Vec<Sheet> = data_raw.sheets.into_iter().map(Into::<_>::into).collect();
+/// Formats which `eval` reads and writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// The same shape as received from wix, `submissionUrl` is optional
+    Json,
+    /// A single sheet, see [csv]
+    Csv,
+    /// All worksheets of a workbook, see [xlsx]
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+    /// All tables of an OpenDocument spreadsheet, see [ods]
+    #[cfg(feature = "ods")]
+    Ods,
+    /// A GitHub Markdown table of every sheet, only written, see [render]
+    Markdown,
+    /// An HTML table of every sheet, only written
+    Html,
+    /// A table drawn with box characters of every sheet, only written
+    Table,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            #[cfg(feature = "xlsx")]
+            "xlsx" => Ok(Format::Xlsx),
+            #[cfg(feature = "ods")]
+            "ods" => Ok(Format::Ods),
+            "markdown" | "md" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "table" => Ok(Format::Table),
+            _ => Err(format!("unknown format `{}`", name).into()),
+        }
+    }
+
+    /// Whether sheets can be written one at a time, as soon as they are evaluated
+    fn streams(self) -> bool {
+        matches!(
+            self,
+            Format::Json | Format::Markdown | Format::Html | Format::Table
+        )
+    }
 
+    // JSON unless the extension says otherwise
+    fn from_path(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("csv") => Format::Csv,
+            #[cfg(feature = "xlsx")]
+            Some("xlsx") => Format::Xlsx,
+            #[cfg(feature = "ods")]
+            Some("ods") => Format::Ods,
+            Some("md") => Format::Markdown,
+            Some("html" | "htm") => Format::Html,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// Arguments of `eval INPUT [OPTIONS]`, `-` reads the input from stdin
+struct EvalArgs {
+    input: String,
+    /// Written to stdout if there is none
+    output: Option<String>,
+    from: Format,
+    to: Format,
+    /// Only this sheet of the input is evaluated
+    sheet: Option<String>,
+    /// Formulas are written besides their values, in formats which can hold both
+    formulas: bool,
+    csv: CsvOptions,
+}
+
+impl EvalArgs {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut args = args.iter();
+        let input = args.next().ok_or("no input provided")?.clone();
+        let (mut output, mut from, mut to, mut sheet) = (None, None, None, None);
+        let mut formulas = false;
+        let mut csv = CsvOptions::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or(format!("no value provided for `{}`", arg))
+            };
+            match arg.as_str() {
+                "--output" | "-o" => output = Some(value()?.clone()),
+                "--from" => from = Some(Format::from_name(value()?)?),
+                "--to" => to = Some(Format::from_name(value()?)?),
+                "--sheet" => sheet = Some(value()?.clone()),
+                "--formulas" => formulas = true,
+                "--delimiter" => {
+                    csv.delimiter = match value()?.as_str() {
+                        "tab" | "\\t" => b'\t',
+                        d if d.len() == 1 => d.as_bytes()[0],
+                        d => return Err(format!("invalid delimiter `{}`", d).into()),
+                    }
+                }
+                "--quoting" => {
+                    csv.quoting = match value()?.as_str() {
+                        "necessary" => Quoting::Necessary,
+                        "always" => Quoting::Always,
+                        "non-numeric" => Quoting::NonNumeric,
+                        "never" => Quoting::Never,
+                        q => return Err(format!("unknown quoting `{}`", q).into()),
+                    }
+                }
+                "--errors" => {
+                    csv.errors = match value()?.as_str() {
+                        "message" => ErrorRendering::Message,
+                        "code" => ErrorRendering::Code,
+                        "empty" => ErrorRendering::Empty,
+                        e => return Err(format!("unknown error rendering `{}`", e).into()),
+                    }
+                }
+                _ => return Err(format!("unknown option `{}`", arg).into()),
+            }
+        }
+
+        Ok(Self {
+            from: from.unwrap_or_else(|| Format::from_path(&input)),
+            to: to.unwrap_or_else(|| output.as_deref().map_or(Format::Json, Format::from_path)),
+            input,
+            output,
+            sheet,
+            formulas,
+            csv,
+        })
+    }
+
+    fn reader(&self) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        Ok(match self.input.as_str() {
+            "-" => Box::new(io::stdin().lock()),
+            path => Box::new(File::open(path)?),
+        })
+    }
+
+    fn writer(&self) -> Result<Box<dyn Write>, Box<dyn Error>> {
+        Ok(match &self.output {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout().lock()),
+        })
+    }
+
+    fn can_hold_formulas(&self) -> bool {
+        !matches!(
+            self.to,
+            Format::Csv | Format::Markdown | Format::Html | Format::Table
+        )
+    }
+
+    fn read_input(&self) -> Result<RawData, Box<dyn Error>> {
+        let reader = self.reader()?;
+
+        match self.from {
+            Format::Json => Ok(serde_json::from_reader(reader)?),
+            Format::Csv => {
+                let id = Path::new(&self.input)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .filter(|_| self.input != "-")
+                    .unwrap_or("sheet");
+                Ok(RawData {
+                    submission_url: String::new(),
+                    sheets: vec![csv::read_sheet(id, reader, &self.csv)?],
+                    functions: Vec::new(),
+                })
+            }
+            #[cfg(feature = "xlsx")]
+            Format::Xlsx => Ok(RawData {
+                submission_url: String::new(),
+                sheets: xlsx::read_workbook(reader)?,
+                functions: Vec::new(),
+            }),
+            #[cfg(feature = "ods")]
+            Format::Ods => Ok(RawData {
+                submission_url: String::new(),
+                sheets: ods::read_workbook(reader)?,
+                functions: Vec::new(),
+            }),
+            Format::Markdown | Format::Html | Format::Table => {
+                Err(format!("{:?} can only be written", self.from).into())
+            }
+        }
+    }
+
+    /// `sources` are the sheets before evaluation, which hold the formulas
+    fn write_output(&self, sheets: Vec<Sheet>, sources: &[RawSheet]) -> Result<(), Box<dyn Error>> {
+        if !sources.is_empty() && !self.can_hold_formulas() {
+            return Err(format!("{:?} can not hold formulas", self.to).into());
+        }
+
+        let mut writer = self.writer()?;
+        match self.to {
+            Format::Csv => match &sheets[..] {
+                [sheet] => csv::write_sheet(sheet, writer, &self.csv)?,
+                _ => {
+                    return Err(format!(
+                        "CSV holds a single sheet, but there are {}, select one with `--sheet`",
+                        sheets.len()
+                    )
+                    .into())
+                }
+            },
+            #[cfg(feature = "xlsx")]
+            Format::Xlsx => xlsx::write_workbook(&mut writer, &sheets, sources)?,
+            #[cfg(feature = "ods")]
+            Format::Ods => ods::write_workbook(&mut writer, &sheets, sources)?,
+            _ => {
+                let count = sheets.len();
+                for (i, sheet) in sheets.into_iter().enumerate() {
+                    let source = sources.iter().find(|s| s.id == sheet.id);
+                    self.write_sheet(&mut writer, i, sheet, source)
+                        .map_err(|e| e as Box<dyn Error>)?;
+                }
+                self.finish_sheets(&mut writer, count)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the `index`th sheet of formats which [Format::streams]
+    fn write_sheet(
+        &self,
+        writer: &mut dyn Write,
+        index: usize,
+        sheet: Sheet,
+        source: Option<&RawSheet>,
+    ) -> Result<(), HandlerError> {
+        match self.to {
+            // the same layout as a pretty printed array,
+            // cells with formulas become `{"formula": ..., "value": ...}`
+            Format::Json => {
+                let sheet: SerdeValue = match source {
+                    Some(source) => sheet.to_serde_value_with_formulas(source),
+                    None => sheet.into(),
+                };
+                write!(writer, "{}\n  ", if index == 0 { "[" } else { "," })?;
+                serde_json::to_writer_pretty(Indented(writer), &sheet)?;
+            }
+            // ids of sheets are captions of HTML tables
+            Format::Html => render::write_html(&sheet, writer)?,
+            Format::Markdown => {
+                if index > 0 {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "## {}\n", sheet.id)?;
+                render::write_markdown(&sheet, writer)?
+            }
+            Format::Table => {
+                if index > 0 {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "{}", sheet.id)?;
+                render::write_table(&sheet, writer)?
+            }
+            _ => {
+                return Err(format!("{:?} can not be written one sheet at a time", self.to).into())
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes whatever follows `count` sheets written by [EvalArgs::write_sheet]
+    fn finish_sheets(&self, writer: &mut dyn Write, count: usize) -> io::Result<()> {
+        match (self.to, count) {
+            (Format::Json, 0) => writeln!(writer, "[]"),
+            (Format::Json, _) => writeln!(writer, "\n]"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates sheets of JSON input one at a time, each is written as soon as it is evaluated,
+    /// so only a single sheet is held in memory at once, regardless of the size of the input
+    ///
+    /// Texts are interned for every sheet on its own, so texts of written sheets are not kept
+    fn eval_streaming(&self, ops: &mut OpRegistry) -> Result<(), Box<dyn Error>> {
+        if self.formulas && !self.can_hold_formulas() {
+            return Err(format!("{:?} can not hold formulas", self.to).into());
+        }
+
+        let mut writer = self.writer()?;
+        let mut count = 0;
+        read_parts(self.reader()?, |part| {
+            match part {
+                RawPart::Functions(functions) => ops.define_all(&functions)?,
+                RawPart::Sheet(raw) if self.sheet.as_ref().is_none_or(|id| id == &raw.id) => {
+                    let source = self.formulas.then(|| raw.clone());
+                    let sheet = Sheet::from(raw).resolve_refs_with(ops, &EvalOptions::default());
+                    self.write_sheet(&mut writer, count, sheet, source.as_ref())?;
+                    count += 1;
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        if let (Some(id), 0) = (&self.sheet, count) {
+            return Err(format!("no sheet with id `{}`", id).into());
+        }
+        self.finish_sheets(&mut writer, count)?;
+        Ok(())
+    }
+}
+
+/// Indents every line but the first by two spaces, which nests pretty printed JSON
+/// in a pretty printed array, line breaks in JSON strings are escaped so only the layout is indented
+struct Indented<W>(W);
+
+impl<W: Write> Write for Indented<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            self.0.write_all(line)?;
+            if line.ends_with(b"\n") {
+                self.0.write_all(b"  ")?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Evaluates sheets from a file instead of the ones received from wix
+fn eval(ops: &mut OpRegistry, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = EvalArgs::parse(args)?;
+    if args.from == Format::Json && args.to.streams() {
+        return args.eval_streaming(ops);
+    }
+
+    let mut data = args.read_input()?;
+    ops.define_all(&data.functions)?;
+
+    if let Some(id) = &args.sheet {
+        data.sheets.retain(|s| &s.id == id);
+        if data.sheets.is_empty() {
+            return Err(format!("no sheet with id `{}`", id).into());
+        }
+    }
+
+    let sources = match args.formulas {
+        true => data.sheets.clone(),
+        false => Vec::new(),
+    };
+    let mut interner = Interner::new();
+    let sheets = data
+        .sheets
+        .into_iter()
+        .map(|raw| Sheet::from_raw(raw, &mut interner))
+        .collect();
+    args.write_output(
+        resolve_sheets(sheets, ops, &EvalOptions::default()),
+        &sources,
+    )
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = env::args().collect::<Vec<_>>();
     let mut ops = wix_sheets::sheets::operators::get_default_op_map();
+    #[cfg(feature = "scripting")]
+    register_scripts(&mut ops)?;
+
+    // usage: `functions [NAME | FILTER]`
+    if args.get(1).map(String::as_str) == Some("functions") {
+        print_functions(&ops, args.get(2).map(String::as_str));
+        return Ok(());
+    }
+
+    // usage: `eval INPUT [--output PATH] [--from FORMAT] [--to FORMAT] [--sheet ID]
+    //              [--formulas] [--delimiter CHAR] [--quoting QUOTING] [--errors RENDERING]`
+    if args.get(1).map(String::as_str) == Some("eval") {
+        return eval(&mut ops, &args[2..]);
+    }
 
-    let data = data.into_iter().map(|s| s.resolve_refs(&mut ops)).collect::<Vec<_>>();
+    // deserialize, parse & compute sheets one at a time
+    let mut url = String::new();
+    let mut data = Vec::new();
+    read_parts(reqwest::blocking::get(HUB_URL_GET)?, |part| {
+        match part {
+            RawPart::SubmissionUrl(submission_url) => url = submission_url,
+            RawPart::Functions(functions) => ops.define_all(&functions)?,
+            RawPart::Sheet(raw) => {
+                let sheet = Sheet::from(raw).resolve_refs_with(&ops, &EvalOptions::default());
+                data.push(serde_json::value::to_raw_value(&SerdeValue::from(sheet))?);
+            }
+        }
+        Ok(())
+    })?;
 
     // serialize and send
-    let mut results = Results {
-        email: env::args()
-            .collect::<Vec<_>>()
+    let results = Results {
+        email: args
             .get(1)
             .expect("no provided email")
             .clone(),
@@ -59,12 +494,12 @@ fn main() -> Result<(), Box<dyn Error>> {
     let req = client
         .post(url)
         .header("content-type", "application/json")
-        .body(results.serialize(true).to_string());
+        .body(serde_json::to_string(&results)?);
 
     let res = req.send()?;
 
     // response
-    println!("{:#?}", &res);
+    println!("{:#?}", res);
     println!("{}", res.text()?);
 
     Ok(())
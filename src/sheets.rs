@@ -1,49 +1,127 @@
-#![allow(incomplete_features)]
-
+pub mod cells;
+pub mod defined;
 pub mod expr;
+pub mod lambda;
+mod levels;
+pub mod limits;
 pub mod operators;
+pub mod options;
 pub mod parse;
-pub mod tests;
+mod spill;
+#[cfg(test)]
+mod tests;
 
 use derive_more::Display;
+use serde::{Deserialize, Serialize};
 use serde_json::map::Map as SerdeMap;
 use serde_json::value::Value as SerdeValue;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::convert::Into;
-use std::{collections::HashMap, fmt::Debug};
+use std::fmt::Debug;
 use thiserror::Error;
 
+pub use self::cells::Cells;
 use self::expr::*;
-use crate::data::{RawCellData, RawSheet};
-use crate::{types::num::Num, types::value::Value};
+use self::lambda::{Lambda, Scope};
+pub use self::limits::Limits;
+use self::limits::{check_parse_depth, Budget};
+use self::operators::{OpRegistry, Operator};
+pub use self::options::{DecimalMode, EvalOptions};
+use crate::data::{RawCellData, RawCells, RawSheet};
+use crate::types::text::{Interner, Text};
+use crate::types::{array::Array, box_value::BoxValue, empty::Empty, num::Num, value::Value};
 
 /// Contains all cells of a sheet
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Sheets can be serialized before and after evaluation, e.g. to cache parsed sheets,
+/// see [crate::types::registry] for values in them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Sheet {
     pub id: String,
-    pub cells: Vec<Vec<Expr>>,
+    pub cells: Cells,
 }
 
 impl Sheet {
-    fn get(&self, index: impl Into<Position>) -> Option<&Expr> {
-        let index = index.into();
-        self.cells
-            .get(index.y)
-            .and_then(|row: &Vec<_>| row.get(index.x))
+    /// The cell at `index`, blank cells may be missing, see [Sheet::in_bounds]
+    pub fn get(&self, index: impl Into<Position>) -> Option<&Expr> {
+        self.cells.get(index.into())
     }
 
+    // extends the sheet if `index` is outside of it
     fn set_unchecked(&mut self, index: impl Into<Position>, expr: Expr) {
-        let index = index.into();
-        self.cells[index.y][index.x] = expr;
+        self.cells.set(index.into(), expr);
+    }
+
+    /// Width of the widest row, rows of a sheet can have different lengths
+    pub fn width(&self) -> usize {
+        self.cells.width()
+    }
+
+    /// Checks if `pos` is inside the bounding box of the sheet
+    pub fn in_bounds(&self, pos: Position) -> bool {
+        pos.y < self.cells.height() && pos.x < self.width()
+    }
+
+    // a reference to a missing cell inside the bounding box is a blank cell,
+    // i.e. it's a missing trailing cell of a row
+    fn missing_ref(&self, pos: Position) -> Expr {
+        if self.in_bounds(pos) {
+            Empty.into()
+        } else {
+            CellError::InvalidReference(pos).into()
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Display)]
-#[display(fmt = "{}{}", "x + (b'A' as usize)", y)]
+/// Displayed by its name, see [Position::name]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Hash, Display, Serialize, Deserialize)]
+#[display(fmt = "{}", "self.name()")]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+impl Position {
+    /// The last cell of the largest sheet which is read by name, `XFD1048576` as in Excel
+    pub const LAST: Position = Position {
+        x: 16_383,
+        y: 1_048_575,
+    };
+
+    /// Letters of the column, `A` to `Z`, then `AA`, `AB` and so on
+    pub fn column(&self) -> String {
+        let mut letters = Vec::new();
+        let mut x = self.x + 1;
+        while x > 0 {
+            letters.push(b'A' + ((x - 1) % 26) as u8);
+            x = (x - 1) / 26;
+        }
+        letters.iter().rev().map(|&c| c as char).collect()
+    }
+
+    /// The name of the cell as it is referenced in formulas, e.g. `B3`
+    pub fn name(&self) -> String {
+        format!("{}{}", self.column(), self.y + 1)
+    }
+
+    /// The position named by `name`, e.g. `B3`, the reverse of [Position::name]
+    pub fn from_name(name: &str) -> Option<Position> {
+        let digits = name.find(|c: char| c.is_ascii_digit())?;
+        let (letters, digits) = name.split_at(digits);
+        if letters.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let x = letters.bytes().try_fold(0usize, |x, b| match b {
+            b'A'..=b'Z' => x.checked_mul(26)?.checked_add((b - b'A') as usize + 1),
+            _ => None,
+        })?;
+        let y = digits.parse::<usize>().ok()?.checked_sub(1)?;
+        Some(Position { x: x - 1, y })
+    }
+}
+
 impl From<(usize, usize)> for Position {
     fn from(value: (usize, usize)) -> Self {
         Self {
@@ -53,21 +131,22 @@ impl From<(usize, usize)> for Position {
     }
 }
 
-#[derive(Error, Debug, PartialEq, Eq, Clone)]
+#[derive(Error, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum CellError {
     #[error("#ERROR: Malformed formula")]
     ParseError,
     #[error("#ERROR: Incompatible types, expected {0}")]
-    TypeMismatch(&'static str),
+    TypeMismatch(Cow<'static, str>),
     #[error("#ERROR: Incompatible types")]
     BinaryTypeMismatch,
     #[error("#ERROR: This cell references non-existent cell at {0}")]
     InvalidReference(Position),
     #[error("#ERROR: This operation takes {0:?} args, but {1} were supplied")]
     InvalidArgCount(std::ops::RangeInclusive<usize>, usize),
-    #[error("#ERROR: Could not find an operation named {0}")]
-    NoOpFound(String),
-    #[error("#ERROR: Referenced cell {0} has errors {1:?}")]
+    #[error("#ERROR: Could not find an operation named {0}{}", suggestion(.1))]
+    // String - name of the operation, Option<String> - closest existing name
+    NoOpFound(String, Option<String>),
+    #[error("#ERROR: Referenced cell {1} has errors {0:?}")]
     RefError(Box<CellError>, Position),
     #[error("{0} [problem with an argument at position: {1}]")]
     ArgError(usize, Box<CellError>),
@@ -76,84 +155,304 @@ pub enum CellError {
     FormError(Vec<CellError>),
     #[error("#ERROR: Division by zero")]
     DivByZero,
+    #[error("#ERROR: Invalid value, expected {0}")]
+    InvalidValue(Cow<'static, str>),
     #[error("#ERROR: ref error")]
     CircularRef,
+    #[error("#SPILL!: The array can not spill over non-blank cell {0}")]
+    Spill(Position),
+    #[error("#ERROR: Cell {0} does not contain a spilled array")]
+    NoSpill(Position),
+    #[error("#ERROR: The result is an empty array")]
+    EmptyArray,
+    #[error("#N/A: The value was not found")]
+    NotFound,
+    #[error("#NAME?: Unknown name {0}")]
+    UnknownName(String),
+    #[error("#CALC!: A cell can not hold a lambda, it has to be called")]
+    UncalledLambda,
+    #[error("#CALC!: Functions can not be nested deeper than {0} calls")]
+    CallDepth(usize),
+    #[error("#ERROR: The script failed: {0}")]
+    ScriptError(String),
+    #[error("#LIMIT!: The formula exceeds the limit of {0}")]
+    Limit(Cow<'static, str>),
+    #[error("#NUM!: The result is not a finite number")]
+    NotFinite,
+    #[error("#NUM!: The number is too large for this function")]
+    NumTooLarge,
+}
+
+impl CellError {
+    /// The leading code of the message, e.g. `#SPILL!`
+    pub fn code(&self) -> &'static str {
+        match self {
+            CellError::ArgError(_, e) => e.code(),
+            CellError::Spill(_) => "#SPILL!",
+            CellError::NotFound => "#N/A",
+            CellError::UnknownName(_) => "#NAME?",
+            CellError::UncalledLambda | CellError::CallDepth(_) => "#CALC!",
+            CellError::Limit(_) => "#LIMIT!",
+            CellError::NotFinite | CellError::NumTooLarge => "#NUM!",
+            _ => "#ERROR",
+        }
+    }
+
+    // such errors are returned as they are instead of being wrapped
+    // into errors of every enclosing formula
+    fn is_fatal(&self) -> bool {
+        matches!(self, CellError::CallDepth(_) | CellError::Limit(_))
+    }
+}
+
+fn fatal_error(args: &[Expr]) -> Option<Expr> {
+    args.iter()
+        .find(|e| matches!(e, Expr::Err(e) if e.is_fatal()))
+        .cloned()
+}
+
+/// What evaluation of a sheet needs besides the sheet itself
+struct Env<'a> {
+    ops: &'a OpRegistry,
+    budget: Budget,
+    // evaluated cells which are not written into the sheet yet
+    resolved: HashMap<Position, Expr>,
+    // cells whose formulas are being evaluated, referencing those is circular
+    evaluating: HashSet<Position>,
+    // operators by addresses of interned names, the names are kept so that
+    // their addresses are not reused by other names, see [Env::op]
+    found: HashMap<usize, (Text, Option<&'a Operator>)>,
+    // whether independent cells are evaluated in parallel
+    #[cfg(feature = "parallel")]
+    parallel: bool,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl<'a> Env<'a> {
+    fn new(ops: &'a OpRegistry, budget: Budget) -> Self {
+        Self {
+            ops,
+            budget,
+            resolved: HashMap::new(),
+            evaluating: HashSet::new(),
+            found: HashMap::new(),
+            #[cfg(feature = "parallel")]
+            parallel: true,
+        }
+    }
+
+    // names are looked up in the registry once, which hashes their uppercase texts,
+    // equal names of a workbook share the same address, see [Sheet::from_raw]
+    fn op(&mut self, name: &Text) -> Option<&'a Operator> {
+        let ops = self.ops;
+        self.found
+            .entry(name.addr())
+            .or_insert_with(|| (name.clone(), ops.get(name)))
+            .1
+    }
+}
+
+fn suggestion(name: &Option<String>) -> String {
+    name.as_ref()
+        .map(|n| format!(", did you mean {}?", n))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct OpInfo {
-    // This could be a `&str` but then `RawCellData` needs to
-    // to take a reference to `str` too instead holding a `String`
-    // in order to take over the reference
-    // instead of dropping the string then moving RawSheet in [Sheet::from]
-    pub name: String,
+    /// The name as it is written, names of a workbook are interned, see [Sheet::from_raw],
+    /// so operators are looked up once for every distinct name
+    pub name: Text,
     pub args: Vec<Expr>,
+    /// Limits of the evaluation, set before the operator is called,
+    /// e.g. to bound the arrays it creates
+    #[serde(skip)]
+    pub limits: Limits,
+}
+
+/// Resolves all of `sheets`, in parallel with the `parallel` feature
+pub fn resolve_sheets(sheets: Vec<Sheet>, ops: &OpRegistry, options: &EvalOptions) -> Vec<Sheet> {
+    #[cfg(feature = "parallel")]
+    use rayon::prelude::*;
+    #[cfg(feature = "parallel")]
+    let sheets = sheets.into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let sheets = sheets.into_iter();
+
+    sheets.map(|s| s.resolve_refs_with(ops, options)).collect()
 }
 
 impl Sheet {
-    // TODO: in case of reference cycles this implementation will cycle till the stack blows up, fix this
     /// Computes all fields, i.e. turns all values into constant values
     /// by computing formulas
-    pub fn resolve_refs(mut self, ops: &mut HashMap<&'static str, operators::Operator>) -> Self {
-        for iy in 0..(&self).cells.len() {
-            for jx in 0..(&self).cells[iy].len() {
-                (&mut self).resolve_on_pos((jx, iy).into(), (jx, iy).into(), ops);
-            }
+    ///
+    /// Cells on reference cycles evaluate to [CellError::CircularRef]
+    pub fn resolve_refs(self, ops: &OpRegistry) -> Self {
+        self.resolve_refs_with(ops, &EvalOptions::default())
+    }
+
+    /// Same as [Sheet::resolve_refs], but evaluation is configured by `options`
+    pub fn resolve_refs_with(mut self, ops: &OpRegistry, options: &EvalOptions) -> Self {
+        if let Some(decimal) = options.decimal {
+            decimal.to_decimals(&mut self);
+        }
+
+        let mut env = Env::new(ops, Budget::new(options.limits));
+        #[cfg(feature = "parallel")]
+        {
+            env.parallel = !options.sequential;
+        }
+        self.resolve_spilling(&mut env);
+
+        if let Some(decimal) = options.decimal {
+            decimal.round(&mut self);
         }
+
         self
     }
 
-    // TODO: move `resolve_on_pos` to `resolve_refs`
-    // in order to avoid passing `&mut self` around
-    // this will enable to copy less data and
-    // possibly get around using recursion
-    // thus removing the possibility of a stack overflow
-    fn resolve_on_pos(
-        &mut self,
-        pos: Position,
-        origin: Position,
-        ops: &mut HashMap<&'static str, operators::Operator>,
-    ) -> Option<&Expr> {
-        let expr = self.get(pos)?;
+    /// Writes cells evaluated since the last call into the sheet
+    fn write_resolved(&mut self, env: &mut Env) {
+        for (pos, expr) in env.resolved.drain() {
+            self.set_unchecked(pos, expr);
+        }
+    }
+
+    // the sheet itself is not changed during evaluation,
+    // evaluated cells are kept in `env` until [Sheet::write_resolved]
+    fn resolve_on_pos(&self, pos: Position, origin: Position, env: &mut Env) -> Option<Expr> {
+        let expr = env.resolved.get(&pos).or_else(|| self.get(pos))?;
 
         let new_expr: Expr = match expr.clone() {
-            Expr::Ref(r) => {
-                if r == origin {
-                    Expr::Err(CellError::CircularRef)
-                } else {
-                    self.resolve_on_pos(r, origin, ops)
-                        .map(|e| {
-                            if e.is_err() {
-                                let e = e.unwrap_err_ref();
-
-                                if let CellError::CircularRef = e {
-                                    Expr::Err(CellError::CircularRef)
-                                } else {
-                                    Expr::Err(CellError::RefError(Box::new(e.clone()), r))
-                                }
-                            } else {
-                                e.clone()
-                            }
-                        })
-                        .unwrap_or(Expr::Err(CellError::InvalidReference(r)))
-                }
+            e @ (Expr::Value(_) | Expr::Err(_)) => return Some(e),
+            expr => match env.budget.cell(&expr) {
+                Ok(()) => self.eval_cell(pos, expr, origin, env),
+                Err(e) => e.into(),
+            },
+        };
+
+        env.resolved.insert(pos, new_expr.clone());
+        Some(new_expr)
+    }
+
+    // evaluates the formula `expr` of the cell at `pos`, the depth of which is counted
+    // on it's own, so that references don't count towards the depth
+    fn eval_cell(&self, pos: Position, expr: Expr, origin: Position, env: &mut Env) -> Expr {
+        let depth = match env.budget.enter_cell() {
+            Ok(depth) => depth,
+            Err(e) => return e.into(),
+        };
+
+        env.evaluating.insert(pos);
+        let result = match self.eval(expr, origin, env, &Scope::default()) {
+            Expr::Value(v) if v.is::<Lambda>() => CellError::UncalledLambda.into(),
+            e => e,
+        };
+        env.evaluating.remove(&pos);
+        env.budget.exit_cell(depth);
+        result
+    }
+
+    // evaluates an expression of a formula, names are looked up in `scope`
+    fn eval(&self, expr: Expr, origin: Position, env: &mut Env, scope: &Scope) -> Expr {
+        if let Err(e) = env.budget.enter() {
+            return e.into();
+        }
+
+        let result = self.eval_unlimited(expr, origin, env, scope);
+        env.budget.exit();
+        result
+    }
+
+    fn eval_unlimited(&self, expr: Expr, origin: Position, env: &mut Env, scope: &Scope) -> Expr {
+        match expr {
+            Expr::Ref(r) => self.resolve_ref(r, origin, env, false),
+            Expr::SpillRef(r) => self.resolve_ref(r, origin, env, true),
+            Expr::Name(name) => scope
+                .get(&name)
+                .cloned()
+                .unwrap_or(Expr::Err(CellError::UnknownName(name))),
+            Expr::Form(op_info) => self.eval_form(op_info, origin, env, scope),
+            Expr::Call(callee, mut args) => {
+                let callee = self.eval(*callee, origin, env, scope);
+                args = args
+                    .into_iter()
+                    .map(|e| self.eval(e, origin, env, scope))
+                    .collect();
+                self.call(&callee, args, origin, env, scope)
             }
-            Expr::Form(mut op_info) => {
-                op_info.resolve_with_sheet(self, origin, ops);
-
-                ops.get_mut(&op_info.name[..])
-                    .map(|o| match o(self, &mut op_info) {
-                        Ok(e) => e.into(),
-                        Err(ve) => Expr::Err(CellError::FormError(ve)),
-                    })
-                    .unwrap_or(CellError::NoOpFound(op_info.name.clone()).into())
+            e => e,
+        }
+    }
+
+    fn eval_form(
+        &self,
+        mut op_info: OpInfo,
+        origin: Position,
+        env: &mut Env,
+        scope: &Scope,
+    ) -> Expr {
+        // a bound name shadows operators
+        if let Some(callee) = scope.get(&op_info.name).cloned() {
+            op_info.resolve_with_sheet(self, origin, env, scope);
+            return self.call(&callee, op_info.args, origin, env, scope);
+        }
+
+        if let Some(e) = self.eval_special_form(&op_info, origin, env, scope) {
+            return e;
+        }
+
+        op_info.resolve_with_sheet(self, origin, env, scope);
+        if let Some(e) = fatal_error(&op_info.args) {
+            return e;
+        }
+
+        op_info.limits = env.budget.limits();
+        // an operator can return an expression which still has to be evaluated,
+        // e.g. a call of a defined function
+        match env.op(&op_info.name) {
+            Some(o) => match o(self, &mut op_info) {
+                Ok(Expr::Value(BoxValue::Num(Num::F(f)))) if !f.is_finite() => {
+                    CellError::NotFinite.into()
+                }
+                Ok(e) => self.eval(e, origin, env, scope),
+                // fatal and #NUM! errors are the results of formulas, they are not wrapped
+                Err(mut ve) if matches!(&ve[..], [e] if e.is_fatal() || e.code() == "#NUM!") => {
+                    ve.remove(0).into()
+                }
+                Err(ve) => Expr::Err(CellError::FormError(ve)),
+            },
+            None => env.ops.no_op_found(&op_info.name).into(),
+        }
+    }
+
+    // resolves the cell referenced by `r`, a reference to a spilled array
+    // evaluates to the whole array, otherwise only to the top left value of it
+    fn resolve_ref(&self, r: Position, origin: Position, env: &mut Env, spill: bool) -> Expr {
+        if r == origin || env.evaluating.contains(&r) {
+            return Expr::Err(CellError::CircularRef);
+        }
+
+        let expr = match self.resolve_on_pos(r, origin, env) {
+            Some(e) => e,
+            None => {
+                return match self.missing_ref(r) {
+                    Expr::Value(_) if spill => Expr::Err(CellError::NoSpill(r)),
+                    e => e,
+                }
             }
-            Expr::Value(v) => v.into(),
-            Expr::Err(e) => Expr::Err(e),
         };
 
-        self.set_unchecked(pos, new_expr);
-        self.get(pos)
+        match expr {
+            Expr::Err(CellError::CircularRef) => Expr::Err(CellError::CircularRef),
+            Expr::Err(e) if e.is_fatal() => Expr::Err(e),
+            Expr::Err(e) => Expr::Err(CellError::RefError(Box::new(e), r)),
+            Expr::Value(v) => match (v.downcast_ref::<Array>(), spill) {
+                (Some(array), false) => Expr::Value(array.top_left().clone()),
+                (None, true) => Expr::Err(CellError::NoSpill(r)),
+                _ => Expr::Value(v),
+            },
+            e => e,
+        }
     }
 }
 
@@ -162,66 +461,42 @@ impl OpInfo {
     // `Expr`s which are either `Err` or `Value`
     fn resolve_with_sheet(
         &mut self,
-        sheet: &mut Sheet,
+        sheet: &Sheet,
         origin: Position,
-        ops: &mut HashMap<&'static str, operators::Operator>,
+        env: &mut Env,
+        scope: &Scope,
     ) {
-        let mut self_ = self.clone();
-        self_.args.iter_mut().for_each(|e: &mut Expr| {
-            if e.is_err() || e.is_value() {
-                return;
-            }
-
-            let e_ = match e {
-                Expr::Ref(r) => {
-                    let r = *r;
-                    if r == origin {
-                        Expr::Err(CellError::CircularRef)
-                    } else {
-                        sheet.resolve_on_pos(r, origin, ops)
-                            .map(|e| {
-                                if e.is_err() {
-                                    let e = e.unwrap_err_ref();
-
-                                    if let CellError::CircularRef = e {
-                                        Expr::Err(CellError::CircularRef)
-                                    } else {
-                                        Expr::Err(CellError::RefError(Box::new(e.clone()), r))
-                                    }
-                                } else {
-                                    e.clone()
-                                }
-                            })
-                            .unwrap_or(Expr::Err(CellError::InvalidReference(r)))
-                    }
-                }
-                Expr::Form(op_info) => {
-                    op_info.resolve_with_sheet(sheet, origin, ops);
-
-                    ops.get_mut(&op_info.name[..])
-                        .map(|o| match o(sheet, op_info) {
-                            Ok(e) => e.into(),
-                            Err(ve) => Expr::Err(CellError::FormError(ve)),
-                        })
-                        .unwrap_or(CellError::NoOpFound(op_info.name.clone()).into())
-                }
-                _ => unreachable!(),
-            };
-
-            *e = e_;
-        });
-
-        *self = self_;
+        let args = std::mem::take(&mut self.args);
+        self.args = args
+            .into_iter()
+            .map(|e| sheet.eval(e, origin, env, scope))
+            .collect();
     }
 }
 
-impl<'a> From<RawSheet> for Sheet {
+/// Sheets are stored sparsely if most of their cells are blank, see [Cells]
+/// Texts are interned by an interner of the sheet alone, see [Sheet::from_raw]
+impl From<RawSheet> for Sheet {
     fn from(value: RawSheet) -> Self {
-        let cells = value
-            .data
-            .into_iter()
-            .map(|raw_row| raw_row.into_iter().map(RawCellData::into).collect())
-            .collect();
+        Sheet::from_raw(value, &mut Interner::new())
+    }
+}
+
+impl Sheet {
+    /// Parses a sheet, texts of cells and names of functions are interned by `interner`,
+    /// which is shared by all sheets of a workbook
+    pub fn from_raw(value: RawSheet, interner: &mut Interner) -> Self {
+        let mut cells = match value.data {
+            RawCells::Rows(rows) => Cells::from_rows(
+                rows.into_iter()
+                    .map(|raw_row| raw_row.into_iter().map(RawCellData::into).collect())
+                    .collect(),
+            ),
+            RawCells::Named(cells) => {
+                Cells::from_cells(cells.into_iter().map(|(pos, raw)| (pos, raw.into())))
+            }
+        };
+        cells.values_mut().for_each(|e| e.intern(interner));
 
         Self {
             id: value.id,
@@ -230,35 +505,76 @@ impl<'a> From<RawSheet> for Sheet {
     }
 }
 
-impl<'a> From<RawCellData> for Expr {
+impl From<RawCellData> for Expr {
     fn from(value: RawCellData) -> Self {
         match value {
             RawCellData::Int(i) => Expr::Value(Num::I(i).into()),
             RawCellData::Float(f) => Expr::Value(Num::F(f).into()),
             RawCellData::Bool(b) => Expr::Value(b.into()),
-            RawCellData::String(s) => match parse::parse_entry(&s[..]) {
-                Ok((_, expr)) => expr,
-                Err(_) => Expr::Err(CellError::ParseError),
-            },
+            RawCellData::Empty => Expr::Value(Empty.into()),
+            RawCellData::String(s) => {
+                if let Err(e) = check_parse_depth(&s) {
+                    return e.into();
+                }
+
+                match parse::parse_entry(&s[..]) {
+                    Ok((_, expr)) => expr,
+                    Err(_) => Expr::Err(CellError::ParseError),
+                }
+            }
         }
     }
 }
 
-/// This impl is used for serialization
-impl Into<SerdeValue> for Sheet {
-    fn into(self) -> SerdeValue {
-        let data = self
+/// This impl is used for serialization, sheets are serialized as rows,
+/// see [Cells::rows]
+impl From<Sheet> for SerdeValue {
+    fn from(value: Sheet) -> Self {
+        let data = value
             .cells
-            .iter()
+            .rows()
             .map(|row| {
                 SerdeValue::Array(
-                    row.iter()
+                    row.into_iter()
                         .map(|cell| cell.clone().into())
                         .collect::<Vec<SerdeValue>>(),
                 )
             })
             .collect::<Vec<_>>();
 
+        SerdeValue::Object(SerdeMap::from_iter([
+            ("id".to_owned(), SerdeValue::String(value.id.clone())),
+            ("data".to_owned(), SerdeValue::Array(data)),
+        ]))
+    }
+}
+
+impl Sheet {
+    /// Serializes the sheet together with the formulas of `source`, the sheet before evaluation,
+    /// a cell with a formula becomes `{"formula": "=SUM(A1, B1)", "value": 7}`
+    /// and any other cell its value only, the same as without formulas
+    pub fn to_serde_value_with_formulas(&self, source: &RawSheet) -> SerdeValue {
+        let data = self
+            .cells
+            .rows()
+            .enumerate()
+            .map(|(y, row)| {
+                let cells = row.into_iter().enumerate().map(|(x, cell)| {
+                    let value = SerdeValue::from(cell.clone());
+                    match source.data.get(Position { x, y }) {
+                        Some(RawCellData::String(f)) if f.starts_with('=') => {
+                            SerdeValue::Object(SerdeMap::from_iter([
+                                ("formula".to_owned(), SerdeValue::String(f.clone())),
+                                ("value".to_owned(), value),
+                            ]))
+                        }
+                        _ => value,
+                    }
+                });
+                SerdeValue::Array(cells.collect())
+            })
+            .collect();
+
         SerdeValue::Object(SerdeMap::from_iter([
             ("id".to_owned(), SerdeValue::String(self.id.clone())),
             ("data".to_owned(), SerdeValue::Array(data)),
@@ -0,0 +1,287 @@
+//! Contains storage of cells of a sheet
+//!
+//! Most sheets are stored as rows of all their cells. Sheets whose bounding box is large
+//! but mostly blank, e.g. with a single value at `ZZ100000`, only store cells
+//! which are not blank, by their positions. The storage is chosen by the share of cells
+//! which are not blank when a sheet is created, see [Cells::from_rows] and [Cells::from_cells]
+
+use std::collections::BTreeMap;
+use std::iter;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Expr, Position};
+use crate::types::empty::Empty;
+
+/// Cells of a row of [Cells::rows]
+pub type Row<'a> = Box<dyn Iterator<Item = &'a Expr> + 'a>;
+
+/// Sheets whose bounding box has fewer cells are always stored as rows
+const SPARSE_MIN_AREA: usize = 4096;
+/// Sheets are stored sparsely when at most one in this many cells is not blank
+const SPARSE_MAX_SHARE: usize = 8;
+
+/// Cells of a [super::Sheet], cells of rows are ordered by columns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Cells {
+    /// Rows of all cells, rows can have different lengths
+    Dense(Vec<Vec<Expr>>),
+    Sparse(Sparse),
+}
+
+/// Cells which are not blank with the size of the bounding box,
+/// blank cells inside of it are missing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sparse {
+    // keyed by `(y, x)`, so that cells are ordered by rows
+    #[serde(with = "entries")]
+    cells: BTreeMap<(usize, usize), Expr>,
+    width: usize,
+    height: usize,
+}
+
+// the keys are not strings, so the map is serialized as a list of positions with their cells
+mod entries {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{Expr, Position};
+
+    pub fn serialize<S: Serializer>(
+        cells: &BTreeMap<(usize, usize), Expr>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(cells.iter().map(|(&(y, x), e)| (Position { x, y }, e)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<(usize, usize), Expr>, D::Error> {
+        let entries = Vec::<(Position, Expr)>::deserialize(deserializer)?;
+        Ok(entries.into_iter().map(|(p, e)| ((p.y, p.x), e)).collect())
+    }
+}
+
+fn is_blank(expr: &Expr) -> bool {
+    matches!(expr, Expr::Value(v) if v.downcast_ref::<Empty>().is_some())
+}
+
+// fills rows of sparse cells up to their last cell
+fn blank() -> &'static Expr {
+    static BLANK: OnceLock<Expr> = OnceLock::new();
+    BLANK.get_or_init(|| Empty.into())
+}
+
+fn is_sparse(area: usize, filled: usize) -> bool {
+    area >= SPARSE_MIN_AREA && filled.saturating_mul(SPARSE_MAX_SHARE) <= area
+}
+
+impl Cells {
+    /// Stores `rows` as they are, unless most of their cells are blank
+    pub fn from_rows(rows: Vec<Vec<Expr>>) -> Self {
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let filled = rows.iter().flatten().filter(|e| !is_blank(e)).count();
+        if !is_sparse(width * rows.len(), filled) {
+            return Cells::Dense(rows);
+        }
+
+        let height = rows.len();
+        let cells = rows
+            .into_iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.into_iter()
+                    .enumerate()
+                    .filter(|(_, e)| !is_blank(e))
+                    .map(move |(x, e)| ((y, x), e))
+            })
+            .collect();
+        Cells::Sparse(Sparse {
+            cells,
+            width,
+            height,
+        })
+    }
+
+    /// Stores cells at their positions, the bounding box ends with the last of them,
+    /// the other cells are blank, a repeated position takes the last of its cells
+    pub fn from_cells(cells: impl IntoIterator<Item = (Position, Expr)>) -> Self {
+        let cells = cells
+            .into_iter()
+            .filter(|(_, e)| !is_blank(e))
+            .map(|(p, e)| ((p.y, p.x), e))
+            .collect::<BTreeMap<_, _>>();
+        let width = cells.keys().map(|&(_, x)| x + 1).max().unwrap_or(0);
+        let height = cells.keys().next_back().map_or(0, |&(y, _)| y + 1);
+        if is_sparse(width.saturating_mul(height), cells.len()) {
+            return Cells::Sparse(Sparse {
+                cells,
+                width,
+                height,
+            });
+        }
+
+        let mut rows = vec![vec![]; height];
+        for ((y, x), expr) in cells {
+            rows[y].resize(x, Empty.into());
+            rows[y].push(expr);
+        }
+        Cells::Dense(rows)
+    }
+
+    pub fn get(&self, pos: Position) -> Option<&Expr> {
+        match self {
+            Cells::Dense(rows) => rows.get(pos.y).and_then(|row| row.get(pos.x)),
+            Cells::Sparse(sparse) => sparse.cells.get(&(pos.y, pos.x)),
+        }
+    }
+
+    /// Sets the cell at `pos`, the sheet is extended if `pos` is outside of it
+    pub fn set(&mut self, pos: Position, expr: Expr) {
+        match self {
+            Cells::Dense(rows) => {
+                if rows.len() <= pos.y {
+                    rows.resize(pos.y + 1, vec![]);
+                }
+
+                let row = &mut rows[pos.y];
+                if row.len() <= pos.x {
+                    row.resize(pos.x + 1, Empty.into());
+                }
+
+                row[pos.x] = expr;
+            }
+            Cells::Sparse(sparse) => {
+                sparse.width = sparse.width.max(pos.x + 1);
+                sparse.height = sparse.height.max(pos.y + 1);
+                match is_blank(&expr) {
+                    true => sparse.cells.remove(&(pos.y, pos.x)),
+                    false => sparse.cells.insert((pos.y, pos.x), expr),
+                };
+            }
+        }
+    }
+
+    /// Width of the widest row
+    pub fn width(&self) -> usize {
+        match self {
+            Cells::Dense(rows) => rows.iter().map(Vec::len).max().unwrap_or(0),
+            Cells::Sparse(sparse) => sparse.width,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Cells::Dense(rows) => rows.len(),
+            Cells::Sparse(sparse) => sparse.height,
+        }
+    }
+
+    /// Stored cells ordered by rows, blank cells of sparse sheets are not stored
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (Position, &Expr)> + '_> {
+        match self {
+            Cells::Dense(rows) => Box::new(rows.iter().enumerate().flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .map(move |(x, e)| (Position { x, y }, e))
+            })),
+            Cells::Sparse(sparse) => Box::new(
+                sparse
+                    .cells
+                    .iter()
+                    .map(|(&(y, x), e)| (Position { x, y }, e)),
+            ),
+        }
+    }
+
+    /// Stored cells, see [Cells::iter]
+    pub fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut Expr> + '_> {
+        match self {
+            Cells::Dense(rows) => Box::new(rows.iter_mut().flatten()),
+            Cells::Sparse(sparse) => Box::new(sparse.cells.values_mut()),
+        }
+    }
+
+    /// All rows, rows of sparse sheets end with their last cell which is not blank,
+    /// their blank cells are not stored, they are yielded as they are reached
+    pub fn rows(&self) -> Box<dyn Iterator<Item = Row<'_>> + '_> {
+        match self {
+            Cells::Dense(rows) => Box::new(rows.iter().map(|row| Box::new(row.iter()) as Row)),
+            Cells::Sparse(sparse) => Box::new((0..sparse.height).map(|y| {
+                let mut next = 0;
+                let cells = sparse.cells.range((y, 0)..(y + 1, 0));
+                Box::new(cells.flat_map(move |(&(_, x), expr)| {
+                    let blanks = iter::repeat_n(blank(), x - next);
+                    next = x + 1;
+                    blanks.chain(iter::once(expr))
+                })) as Row
+            })),
+        }
+    }
+
+    /// All rows, see [Cells::rows]
+    pub fn into_rows(self) -> Vec<Vec<Expr>> {
+        match self {
+            Cells::Dense(rows) => rows,
+            Cells::Sparse(sparse) => {
+                let mut rows = vec![vec![]; sparse.height];
+                for ((y, x), expr) in sparse.cells {
+                    rows[y].resize(x, Empty.into());
+                    rows[y].push(expr);
+                }
+                rows
+            }
+        }
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self, Cells::Sparse(_))
+    }
+}
+
+/// Chooses the storage the same way as [Cells::from_rows]
+impl From<Vec<Vec<Expr>>> for Cells {
+    fn from(rows: Vec<Vec<Expr>>) -> Self {
+        Cells::from_rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::num::Num;
+
+    #[test]
+    fn chooses_storage_by_density() {
+        let far = Position { x: 701, y: 99_999 };
+        let cells = Cells::from_cells([(far, Num::I(1).into()), ((0, 0).into(), Empty.into())]);
+        assert!(cells.is_sparse());
+        assert_eq!((cells.width(), cells.height()), (702, 100_000));
+        assert_eq!(cells.get(far), Some(&Num::I(1).into()));
+        assert_eq!(cells.get((0, 0).into()), None);
+        assert_eq!(cells.rows().nth(99_999).unwrap().count(), 702);
+        assert_eq!(cells.rows().next().unwrap().count(), 0);
+
+        let near = Cells::from_cells([((1, 1).into(), Num::I(1).into())]);
+        assert_eq!(
+            near,
+            Cells::Dense(vec![vec![], vec![Empty.into(), Num::I(1).into()]])
+        );
+
+        let mut rows = vec![vec![Expr::from(Empty); 100]; 100];
+        rows[50][50] = Num::I(2).into();
+        let mut cells = Cells::from_rows(rows.clone());
+        assert!(cells.is_sparse());
+        assert_eq!(cells.clone().into_rows()[50][..51], rows[50][..51]);
+
+        cells.set((120, 3).into(), Num::I(3).into());
+        cells.set((50, 50).into(), Empty.into());
+        assert_eq!((cells.width(), cells.height()), (121, 100));
+        assert_eq!(
+            cells.iter().collect::<Vec<_>>(),
+            vec![((120, 3).into(), &Num::I(3).into())]
+        );
+    }
+}
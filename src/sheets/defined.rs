@@ -0,0 +1,170 @@
+//! Contains functions defined by a workbook as parameterised formulas, e.g.
+//! `MARKUP(cost, price) = DIVIDE(price, cost)`
+//!
+//! A definition is compiled into an operator which calls a lambda made of its body,
+//! so defined functions are called like built-in ones and can call each other
+//! or themselves, up to [super::lambda::MAX_CALL_DEPTH] nested calls
+//!
+//! Defined functions can not shadow operators which are already registered
+
+use thiserror::Error;
+
+use super::lambda::Lambda;
+use super::operators::{help::OpHelp, ParamKind};
+use super::parse::parse_definition;
+use super::{Expr, OpRegistry};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DefinitionError {
+    #[error("Malformed definition of a function: {0}")]
+    Malformed(String),
+    #[error("Function {0} is already defined")]
+    AlreadyDefined(String),
+    #[error("Function {0} has more than one parameter named {1}")]
+    DuplicateParam(String, String),
+}
+
+impl OpRegistry {
+    /// Compiles `definition` into an operator,
+    /// the parameters of which can be described with [OpHelp::params]
+    pub fn define(&mut self, definition: &str) -> Result<&mut OpHelp, DefinitionError> {
+        let (name, params, body) = parse_definition(definition)
+            .map(|(_, parsed)| parsed)
+            .map_err(|_| DefinitionError::Malformed(definition.to_owned()))?;
+
+        if self.contains(&name) {
+            return Err(DefinitionError::AlreadyDefined(name));
+        }
+
+        let duplicate = params
+            .iter()
+            .enumerate()
+            .find(|(i, p)| params[..*i].iter().any(|q| q.eq_ignore_ascii_case(p)));
+        if let Some((_, param)) = duplicate {
+            return Err(DefinitionError::DuplicateParam(name, param.clone()));
+        }
+
+        // the returned call is evaluated by the sheet, which also checks the arity
+        let lambda: Expr = Lambda::new(params.clone(), body).into();
+        let help = self.register_raw(
+            &name,
+            Box::new(move |_, info| {
+                Ok(Expr::Call(
+                    Box::new(lambda.clone()),
+                    std::mem::take(&mut info.args),
+                ))
+            }),
+        );
+        for param in &params {
+            help.param(param, "Any", ParamKind::Required);
+        }
+
+        Ok(help.description(&format!("Defined as `{}`", definition.trim())))
+    }
+
+    /// Defines all of `definitions` in order, see [OpRegistry::define]
+    pub fn define_all<'a>(
+        &mut self,
+        definitions: impl IntoIterator<Item = &'a String>,
+    ) -> Result<(), DefinitionError> {
+        for definition in definitions {
+            self.define(definition)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheets::lambda::MAX_CALL_DEPTH;
+    use crate::sheets::tests::resolve_row;
+    use crate::sheets::{operators, CellError, EvalOptions};
+    use crate::types::num::Num;
+
+    fn resolve(ops: &OpRegistry, formulas: &[&str]) -> Vec<Expr> {
+        resolve_row(vec![], formulas, ops, &EvalOptions::default())
+    }
+
+    #[test]
+    fn defined_functions() {
+        let mut ops = operators::get_default_op_map();
+        ops.define_all(&[
+            "MARKUP(cost, price) = DIVIDE(price, cost)".to_owned(),
+            "DOUBLE_MARKUP(cost, price) = MULTIPLY(2, markup(cost, price))".to_owned(),
+            "ANSWER() = 42".to_owned(),
+            "LOOP(x) = LOOP(x)".to_owned(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            resolve(
+                &ops,
+                &[
+                    "=MARKUP(4, 1)",
+                    "=DOUBLE_MARKUP(4, 1)",
+                    "=SUM(ANSWER(), 1)",
+                    "=MARKUP(1)",
+                    "=LOOP(1)",
+                    "=MAP({1,2}, LAMBDA(x, LOOP(x)))",
+                ]
+            ),
+            vec![
+                Num::F(0.25).into(),
+                Num::F(0.5).into(),
+                Num::I(43).into(),
+                CellError::FormError(vec![CellError::InvalidArgCount(2..=2, 1)]).into(),
+                CellError::CallDepth(MAX_CALL_DEPTH).into(),
+                CellError::CallDepth(MAX_CALL_DEPTH).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn cycles_through_defined_functions() {
+        let mut ops = operators::get_default_op_map();
+        ops.define_all(&["TO_B() = B1".to_owned(), "TO_C() = C1".to_owned()])
+            .unwrap();
+
+        let circular = Expr::from(CellError::CircularRef);
+        assert_eq!(
+            resolve(&ops, &["=TO_B()", "=TO_C()", "=TO_B()"]),
+            vec![circular.clone(), circular.clone(), circular]
+        );
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        let mut ops = operators::get_default_op_map();
+
+        assert_eq!(
+            ops.define("SUM(x) = x").unwrap_err(),
+            DefinitionError::AlreadyDefined("SUM".to_owned())
+        );
+        assert_eq!(
+            ops.define("mul(x) = x").unwrap_err(),
+            DefinitionError::AlreadyDefined("mul".to_owned())
+        );
+        assert_eq!(
+            ops.define("LET(x) = x").unwrap_err(),
+            DefinitionError::AlreadyDefined("LET".to_owned())
+        );
+        assert_eq!(
+            ops.define("F(x, X) = x").unwrap_err(),
+            DefinitionError::DuplicateParam("F".to_owned(), "X".to_owned())
+        );
+        for malformed in ["F(x)", "F(A1) = A1", "F(x) = x)", "= x"] {
+            assert_eq!(
+                ops.define(malformed).unwrap_err(),
+                DefinitionError::Malformed(malformed.to_owned())
+            );
+        }
+
+        ops.define("F(x) = x").unwrap();
+        assert_eq!(
+            ops.define("f(y) = y").unwrap_err(),
+            DefinitionError::AlreadyDefined("f".to_owned())
+        );
+    }
+}
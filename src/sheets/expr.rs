@@ -1,17 +1,25 @@
 use derive_more::{IsVariant, Unwrap};
+use serde::{Deserialize, Serialize};
 
-use crate::types::box_value::BoxValue;
+use crate::types::text::{Interner, Text};
+use crate::types::{array::Array, box_value::BoxValue, empty::Empty};
 
 use super::*;
 use serde_json::value::Value as SerdeValue;
 
 /// `Expr`, short for expression, which represents
 /// all possible values which a cell can contain
-#[derive(Debug, Clone, IsVariant, Unwrap, PartialEq)]
+#[derive(Debug, Clone, IsVariant, Unwrap, PartialEq, Serialize, Deserialize)]
 pub enum Expr {
     Value(BoxValue),
     Ref(Position),
+    /// `A1#`, references the whole array spilled from a cell
+    SpillRef(Position),
+    /// A name bound by `LET` or a lambda parameter
+    Name(String),
     Form(OpInfo),
+    /// Calls the result of an expression, e.g. `LAMBDA(x, SUM(x, 1))(2)`
+    Call(Box<Expr>, Vec<Expr>),
     Err(CellError),
 }
 
@@ -30,6 +38,12 @@ impl From<Box<dyn Value>> for Expr {
     }
 }
 
+impl From<String> for Expr {
+    fn from(value: String) -> Self {
+        Expr::Value(value.into())
+    }
+}
+
 impl From<CellError> for Expr {
     fn from(value: CellError) -> Self {
         Expr::Err(value)
@@ -62,19 +76,88 @@ impl Expr {
     }
 
     pub fn map_value_mut(&mut self, f: impl FnOnce(&mut BoxValue)) {
+        if let Expr::Value(v) = self {
+            f(v)
+        }
+    }
+
+    /// Replaces texts and names of functions with the ones interned by `interner`,
+    /// including texts in arrays
+    pub fn intern(&mut self, interner: &mut Interner) {
         match self {
-            Expr::Value(v) => f(v),
+            Expr::Value(v) => intern_value(v, interner),
+            Expr::Form(op) => {
+                op.name = interner.intern_text(&op.name);
+                op.args.iter_mut().for_each(|e| e.intern(interner));
+            }
+            Expr::Call(callee, args) => {
+                callee.intern(interner);
+                args.iter_mut().for_each(|e| e.intern(interner));
+            }
             _ => {}
         }
     }
 }
 
-/// This impl is used for serialization
-impl Into<SerdeValue> for Expr {
-    fn into(self) -> SerdeValue {
+fn intern_value(value: &mut BoxValue, interner: &mut Interner) {
+    match value {
+        BoxValue::String(s) => *s = interner.intern_text(s),
+        BoxValue::Other(v) => {
+            if let Some(array) = v.downcast_mut::<Array>() {
+                array.values_mut().for_each(|v| intern_value(v, interner));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Displayed as a formula without the leading `=`, e.g. `SUM(A1, "a")`
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args = |args: &[Expr]| {
+            args.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
         match self {
+            Expr::Value(v) => write!(f, "{}", literal(v)),
+            Expr::Ref(pos) => write!(f, "{}", pos.name()),
+            Expr::SpillRef(pos) => write!(f, "{}#", pos.name()),
+            Expr::Name(name) => write!(f, "{}", name),
+            Expr::Form(op) => write!(f, "{}({})", op.name, args(&op.args)),
+            Expr::Call(callee, call_args) => write!(f, "{}({})", callee, args(call_args)),
+            Expr::Err(e) => write!(f, "{}", e.code()),
+        }
+    }
+}
+
+// values the way they are written in formulas, strings are quoted
+fn literal(value: &BoxValue) -> String {
+    if let Some(s) = value.downcast_ref::<Text>() {
+        format!("\"{}\"", s)
+    } else if let Some(array) = value.downcast_ref::<Array>() {
+        let rows = array
+            .rows()
+            .iter()
+            .map(|row| row.iter().map(literal).collect::<Vec<_>>().join(","))
+            .collect::<Vec<_>>();
+        format!("{{{}}}", rows.join(";"))
+    } else if value.downcast_ref::<Empty>().is_some() {
+        String::new()
+    } else {
+        value.to_string()
+    }
+}
+
+/// This impl is used for serialization, cells which are not evaluated are serialized
+/// as their formulas
+impl From<Expr> for SerdeValue {
+    fn from(value: Expr) -> Self {
+        match value {
             Expr::Value(v) => {
-                v.move_inner().into_serde_value()
+                v.into_serde_value()
                 // if let Some(b) = v.downcast_ref::<bool>() {
                 //     return SerdeValue::Bool(*b);
                 // }
@@ -84,7 +167,7 @@ impl Into<SerdeValue> for Expr {
                 // SerdeValue::String(v.to_string())
             }
             Expr::Err(e) => serde_json::value::Value::String(e.to_string()),
-            _ => unreachable!("Assumed resolved"),
+            expr => SerdeValue::String(format!("={}", expr)),
         }
     }
 }
@@ -0,0 +1,475 @@
+//! Contains `LET`, `LAMBDA` and functions which call lambdas:
+//! `MAP`, `REDUCE`, `SCAN`, `BYROW` and `BYCOL`
+//!
+//! These are special forms evaluated by the sheet itself instead of
+//! [OpRegistry] operators, because arguments of `LET` and `LAMBDA`
+//! can't be evaluated before the names they bind are known
+//!
+//! Names are scoped lexically, i.e. a lambda sees the names
+//! which were bound where it was created, not where it is called
+
+use serde::{Deserialize, Serialize};
+use serde_json::value::Value as SerdeValue;
+
+use super::operators::registry::{arg_errors, FromArg};
+use super::operators::{ParamKind, MAX_ARGS};
+use super::{fatal_error, CellError, Env, Expr, OpInfo, OpRegistry, Position, Sheet};
+use crate::types::{array::Array, box_value::BoxValue, value::Value};
+
+/// Most nested calls of lambdas and defined functions, bounds recursion
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// Names bound by `LET` and lambda parameters, later bindings shadow earlier ones
+///
+/// Names are case-insensitive
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scope {
+    names: Vec<(String, Expr)>,
+    // calls of lambdas which are being evaluated
+    depth: usize,
+}
+
+impl Scope {
+    pub fn get(&self, name: &str) -> Option<&Expr> {
+        self.names
+            .iter()
+            .rev()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, e)| e)
+    }
+
+    fn with(&self, name: &str, expr: Expr) -> Self {
+        let mut scope = self.clone();
+        scope.names.push((name.to_owned(), expr));
+        scope
+    }
+}
+
+/// A function created by `LAMBDA`, it captures the [Scope] it was created in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lambda {
+    params: Vec<String>,
+    body: Expr,
+    scope: Scope,
+}
+
+impl Eq for Lambda {}
+
+/// Lambdas are not ordered, only equal ones are comparable
+impl PartialOrd for Lambda {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (self == other).then_some(std::cmp::Ordering::Equal)
+    }
+}
+
+impl Value for Lambda {}
+
+impl Lambda {
+    /// Creates a lambda which doesn't capture any names
+    pub(super) fn new(params: Vec<String>, body: Expr) -> Self {
+        Self {
+            params,
+            body,
+            scope: Scope::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LAMBDA({})", self.params.join(", "))
+    }
+}
+
+impl From<Lambda> for SerdeValue {
+    fn from(value: Lambda) -> Self {
+        SerdeValue::String(value.to_string())
+    }
+}
+
+impl FromArg for Lambda {
+    const TYPE_NAME: &'static str = "Lambda";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        value.downcast_ref::<Lambda>().cloned()
+    }
+}
+
+// names of `LET` bindings and lambda parameters
+fn name(args: &[Expr], i: usize) -> Result<String, CellError> {
+    match &args[i] {
+        Expr::Name(name) => Ok(name.clone()),
+        _ => Err(CellError::ArgError(
+            i,
+            Box::new(CellError::InvalidValue("a name".into())),
+        )),
+    }
+}
+
+// a result of a lambda which is put into an array
+fn single_value(expr: Expr) -> Result<BoxValue, CellError> {
+    match expr {
+        Expr::Value(v) => match v.downcast_ref::<Array>() {
+            Some(array) if array.height() == 1 && array.width() == 1 => {
+                Ok(array.top_left().clone())
+            }
+            Some(_) => Err(CellError::InvalidValue("a single value".into())),
+            None => Ok(v),
+        },
+        Expr::Err(e) => Err(e),
+        _ => unreachable!("Assumed evaluated"),
+    }
+}
+
+fn arg<T: FromArg>(args: &[Expr], i: usize) -> Result<T, CellError> {
+    T::from_arg(args[i].unwrap_value_ref()).ok_or_else(|| {
+        CellError::ArgError(i, Box::new(CellError::TypeMismatch(T::TYPE_NAME.into())))
+    })
+}
+
+fn check_arity(args: &[Expr], min: usize, max: usize) -> Result<(), CellError> {
+    if (min..=max).contains(&args.len()) {
+        Ok(())
+    } else {
+        Err(CellError::InvalidArgCount(min..=max, args.len()))
+    }
+}
+
+// forms whose arguments are not evaluated before they are called, in uppercase
+const SPECIAL_FORMS: [&str; 7] = ["LET", "LAMBDA", "MAP", "REDUCE", "SCAN", "BYROW", "BYCOL"];
+
+impl Sheet {
+    /// Returns `None` if `op_info` is not a special form
+    pub(super) fn eval_special_form(
+        &self,
+        op_info: &OpInfo,
+        origin: Position,
+        env: &mut Env,
+        scope: &Scope,
+    ) -> Option<Expr> {
+        let name = *SPECIAL_FORMS
+            .iter()
+            .find(|f| f.eq_ignore_ascii_case(&op_info.name))?;
+        let args = &op_info.args;
+        let result = match name {
+            "LET" => self.eval_let(args, origin, env, scope),
+            "LAMBDA" => create_lambda(args, scope),
+            _ => {
+                let mut op_info = op_info.clone();
+                op_info.resolve_with_sheet(self, origin, env, scope);
+
+                if let Some(e) = fatal_error(&op_info.args) {
+                    return Some(e);
+                }
+
+                let errs = arg_errors(&op_info);
+                if !errs.is_empty() {
+                    return Some(CellError::FormError(errs).into());
+                }
+
+                let args = &op_info.args;
+                match name {
+                    "MAP" => self.map(args, origin, env, scope),
+                    "REDUCE" => self.reduce(args, origin, env, scope, false),
+                    "SCAN" => self.reduce(args, origin, env, scope, true),
+                    "BYROW" => self.by_row(args, origin, env, scope, false),
+                    _ => self.by_row(args, origin, env, scope, true),
+                }
+            }
+        };
+
+        Some(match result {
+            Ok(e) => e,
+            Err(e) if e.is_fatal() => e.into(),
+            Err(e) => CellError::FormError(vec![e]).into(),
+        })
+    }
+
+    /// Calls `callee` with already evaluated `args`, `caller` is the scope it's called from
+    pub(super) fn call(
+        &self,
+        callee: &Expr,
+        args: Vec<Expr>,
+        origin: Position,
+        env: &mut Env,
+        caller: &Scope,
+    ) -> Expr {
+        if let Some(e) = fatal_error(&args) {
+            return e;
+        }
+
+        let lambda = match callee {
+            Expr::Value(v) => v.downcast_ref::<Lambda>(),
+            Expr::Err(e) => return Expr::Err(e.clone()),
+            _ => None,
+        };
+        let Some(lambda) = lambda else {
+            return CellError::FormError(vec![CellError::TypeMismatch(Lambda::TYPE_NAME.into())])
+                .into();
+        };
+
+        let arity = lambda.params.len();
+        if args.len() != arity {
+            return CellError::FormError(vec![CellError::InvalidArgCount(
+                arity..=arity,
+                args.len(),
+            )])
+            .into();
+        }
+
+        if caller.depth >= MAX_CALL_DEPTH {
+            return CellError::CallDepth(MAX_CALL_DEPTH).into();
+        }
+
+        let mut scope = lambda
+            .params
+            .iter()
+            .zip(args)
+            .fold(lambda.scope.clone(), |scope, (param, arg)| {
+                scope.with(param, arg)
+            });
+        scope.depth = caller.depth + 1;
+        let body = lambda.body.clone();
+        self.eval(body, origin, env, &scope)
+    }
+
+    // `LET(name1, value1, name2, value2, ..., calculation)`,
+    // a value can use names bound before it
+    fn eval_let(
+        &self,
+        args: &[Expr],
+        origin: Position,
+        env: &mut Env,
+        scope: &Scope,
+    ) -> Result<Expr, CellError> {
+        check_arity(args, 3, MAX_ARGS)?;
+        if args.len() % 2 == 0 {
+            return Err(CellError::InvalidValue(
+                "names with values followed by a calculation".into(),
+            ));
+        }
+
+        let mut scope = scope.clone();
+        for i in (0..args.len() - 1).step_by(2) {
+            let name = name(args, i)?;
+            let value = self.eval(args[i + 1].clone(), origin, env, &scope);
+            scope = scope.with(&name, value);
+        }
+
+        Ok(self.eval(args[args.len() - 1].clone(), origin, env, &scope))
+    }
+
+    // `MAP(array1, array2, ..., lambda)`, arrays have to be of the same size
+    fn map(
+        &self,
+        args: &[Expr],
+        origin: Position,
+        env: &mut Env,
+        scope: &Scope,
+    ) -> Result<Expr, CellError> {
+        check_arity(args, 2, MAX_ARGS)?;
+        let (lambda, arrays) = args.split_last().unwrap();
+        arg::<Lambda>(args, args.len() - 1)?;
+
+        let arrays = (0..arrays.len())
+            .map(|i| arg::<Array>(args, i))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (height, width) = (arrays[0].height(), arrays[0].width());
+        if arrays
+            .iter()
+            .any(|a| (a.height(), a.width()) != (height, width))
+        {
+            return Err(CellError::InvalidValue("arrays of the same size".into()));
+        }
+
+        let mut rows = vec![];
+        for y in 0..height {
+            let mut row = vec![];
+            for x in 0..width {
+                let values = arrays
+                    .iter()
+                    .map(|a| a.rows()[y][x].clone().into())
+                    .collect();
+                row.push(single_value(self.call(lambda, values, origin, env, scope))?);
+            }
+            rows.push(row);
+        }
+
+        Ok(Array::new(rows).unwrap().into_value().into())
+    }
+
+    // `REDUCE(initial, array, lambda(accumulator, value))`,
+    // `SCAN` returns all of the intermediate accumulators
+    fn reduce(
+        &self,
+        args: &[Expr],
+        origin: Position,
+        env: &mut Env,
+        scope: &Scope,
+        scan: bool,
+    ) -> Result<Expr, CellError> {
+        check_arity(args, 3, 3)?;
+        let array = arg::<Array>(args, 1)?;
+        arg::<Lambda>(args, 2)?;
+
+        let mut accumulator = args[0].clone();
+        let mut rows = vec![];
+        for row in array.rows() {
+            let mut scanned = vec![];
+            for value in row {
+                accumulator = self.call(
+                    &args[2],
+                    vec![accumulator, value.clone().into()],
+                    origin,
+                    env,
+                    scope,
+                );
+                if let Expr::Err(e) = accumulator {
+                    return Err(e);
+                }
+                if scan {
+                    scanned.push(single_value(accumulator.clone())?);
+                }
+            }
+            rows.push(scanned);
+        }
+
+        if scan {
+            Ok(Array::new(rows).unwrap().into_value().into())
+        } else {
+            Ok(accumulator)
+        }
+    }
+
+    // `BYROW(array, lambda(row))`, `BYCOL` passes columns instead
+    fn by_row(
+        &self,
+        args: &[Expr],
+        origin: Position,
+        env: &mut Env,
+        scope: &Scope,
+        columns: bool,
+    ) -> Result<Expr, CellError> {
+        check_arity(args, 2, 2)?;
+        let mut array = arg::<Array>(args, 0)?;
+        arg::<Lambda>(args, 1)?;
+        if columns {
+            array = array.transpose();
+        }
+
+        let values = array
+            .into_rows()
+            .into_iter()
+            .map(|row| {
+                let row = Array::new(vec![row]).unwrap();
+                let row = if columns { row.transpose() } else { row };
+                single_value(self.call(&args[1], vec![row.into_value().into()], origin, env, scope))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let array = Array::column(values).unwrap();
+        let array = if columns { array.transpose() } else { array };
+        Ok(array.into_value().into())
+    }
+}
+
+// `LAMBDA(param1, param2, ..., calculation)`
+fn create_lambda(args: &[Expr], scope: &Scope) -> Result<Expr, CellError> {
+    check_arity(args, 1, MAX_ARGS)?;
+    let params = (0..args.len() - 1)
+        .map(|i| name(args, i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Lambda {
+        params,
+        body: args[args.len() - 1].clone(),
+        scope: scope.clone(),
+    }
+    .into())
+}
+
+/// Registers help of special forms, so they are listed with other operators
+///
+/// The registered operators are never called, special forms are evaluated
+/// before operators are looked up
+pub fn register_special_forms(registry: &mut OpRegistry) {
+    let forms = [
+        (
+            "LET",
+            vec![
+                ("name", "Name", ParamKind::Required),
+                ("value", "Any", ParamKind::Required),
+                ("names_values_calculation", "Any", ParamKind::Variadic),
+            ],
+            "Binds names to values, which can be used in the calculation given as the last argument",
+            "=LET(x, SUM(A1, B1), MULTIPLY(x, x))",
+        ),
+        (
+            "LAMBDA",
+            vec![
+                ("params", "Name", ParamKind::Variadic),
+                ("calculation", "Any", ParamKind::Required),
+            ],
+            "Creates a function of `params`, which can be called or passed to MAP, REDUCE, SCAN, BYROW and BYCOL",
+            "=LAMBDA(x, SUM(x, 1))(A1)",
+        ),
+        (
+            "MAP",
+            vec![
+                ("arrays", "Array", ParamKind::Variadic),
+                ("lambda", "Lambda", ParamKind::Required),
+            ],
+            "Calls `lambda` with values at the same position of each array, arrays have to be of the same size",
+            "=MAP(A1#, LAMBDA(x, MULTIPLY(x, 2)))",
+        ),
+        (
+            "REDUCE",
+            vec![
+                ("initial", "Any", ParamKind::Required),
+                ("array", "Array", ParamKind::Required),
+                ("lambda", "Lambda", ParamKind::Required),
+            ],
+            "Accumulates values of an array by calling `lambda(accumulator, value)`, starting with `initial`",
+            "=REDUCE(0, A1#, LAMBDA(acc, x, SUM(acc, x)))",
+        ),
+        (
+            "SCAN",
+            vec![
+                ("initial", "Any", ParamKind::Required),
+                ("array", "Array", ParamKind::Required),
+                ("lambda", "Lambda", ParamKind::Required),
+            ],
+            "Same as REDUCE, but returns an array of all intermediate accumulators",
+            "=SCAN(0, A1#, LAMBDA(acc, x, SUM(acc, x)))",
+        ),
+        (
+            "BYROW",
+            vec![
+                ("array", "Array", ParamKind::Required),
+                ("lambda", "Lambda", ParamKind::Required),
+            ],
+            "Calls `lambda` with each row of an array, returns a column of results",
+            "=BYROW(A1#, LAMBDA(row, SUM(row)))",
+        ),
+        (
+            "BYCOL",
+            vec![
+                ("array", "Array", ParamKind::Required),
+                ("lambda", "Lambda", ParamKind::Required),
+            ],
+            "Calls `lambda` with each column of an array, returns a row of results",
+            "=BYCOL(A1#, LAMBDA(column, SUM(column)))",
+        ),
+    ];
+
+    for (name, params, description, example) in forms {
+        let help = registry.register_raw(
+            name,
+            Box::new(|_, _| Err(vec![CellError::InvalidValue("a special form".into())])),
+        );
+        for (param, type_name, kind) in params {
+            help.param(param, type_name, kind);
+        }
+        help.description(description).example(example);
+    }
+}
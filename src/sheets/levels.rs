@@ -0,0 +1,215 @@
+//! Contains evaluation of formulas in the order of the cells they reference
+//!
+//! Formulas are split into levels by the cells they reference: formulas of the first level
+//! don't reference other formulas, formulas of the next level only reference formulas
+//! of the previous levels and so on, so referenced formulas are evaluated before
+//! the formulas referencing them instead of within them. With the `parallel` feature,
+//! formulas of a level are evaluated in parallel.
+//!
+//! Formulas on reference cycles are not evaluated, they are [CellError::CircularRef],
+//! spilling is evaluated afterwards one cell after another.
+//!
+//! The results are the same in parallel as one after another, except for [super::Limits],
+//! which are checked by each thread on it's own
+
+use std::collections::HashMap;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use super::{CellError, Env, Expr, Position, Sheet};
+
+fn is_formula(expr: &Expr) -> bool {
+    !matches!(expr, Expr::Value(_) | Expr::Err(_))
+}
+
+// cells referenced within `expr`, cells referenced by defined functions are not known,
+// those are evaluated within the formulas which reference them
+fn references(expr: &Expr, refs: &mut Vec<Position>) {
+    match expr {
+        Expr::Ref(r) | Expr::SpillRef(r) => refs.push(*r),
+        Expr::Form(op_info) => op_info.args.iter().for_each(|e| references(e, refs)),
+        Expr::Call(callee, args) => {
+            references(callee, refs);
+            args.iter().for_each(|e| references(e, refs));
+        }
+        _ => {}
+    }
+}
+
+// formulas which are not `done` and are on reference cycles or between them,
+// formulas which are only referencing those are left out
+fn cycles(refs: &[Vec<usize>], done: &[bool]) -> Vec<usize> {
+    let mut left = done.iter().map(|d| !d).collect::<Vec<_>>();
+    let mut referenced = vec![0usize; refs.len()];
+    for i in (0..refs.len()).filter(|&i| left[i]) {
+        refs[i]
+            .iter()
+            .filter(|&&r| left[r])
+            .for_each(|&r| referenced[r] += 1);
+    }
+
+    let mut unreferenced = (0..refs.len())
+        .filter(|&i| left[i] && referenced[i] == 0)
+        .collect::<Vec<_>>();
+    while let Some(i) = unreferenced.pop() {
+        left[i] = false;
+        for &r in refs[i].iter().filter(|&&r| left[r]) {
+            referenced[r] -= 1;
+            if referenced[r] == 0 {
+                unreferenced.push(r);
+            }
+        }
+    }
+
+    (0..refs.len()).filter(|&i| left[i]).collect()
+}
+
+impl Sheet {
+    /// Evaluates formulas level by level, formulas of a level don't depend on each other
+    pub(super) fn resolve_levels(&mut self, env: &mut Env) {
+        let (mut formulas, mut found) = (vec![], vec![]);
+        for (pos, expr) in self.cells.iter().filter(|(_, e)| is_formula(e)) {
+            let mut refs = vec![];
+            references(expr, &mut refs);
+            formulas.push(pos);
+            found.push(refs);
+        }
+
+        // formulas are numbered in the order of the cells
+        let numbers = formulas
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| (*pos, i))
+            .collect::<HashMap<_, _>>();
+        let refs = found
+            .iter()
+            .map(|refs| {
+                let mut refs = refs
+                    .iter()
+                    .filter_map(|r| numbers.get(r).copied())
+                    .collect::<Vec<_>>();
+                refs.sort_unstable();
+                refs.dedup();
+                refs
+            })
+            .collect::<Vec<_>>();
+        let mut dependents = vec![vec![]; formulas.len()];
+        for (i, refs) in refs.iter().enumerate() {
+            refs.iter().for_each(|&r| dependents[r].push(i));
+        }
+
+        // referenced formulas which are not evaluated yet
+        let mut pending = refs.iter().map(Vec::len).collect::<Vec<_>>();
+        let mut done = vec![false; formulas.len()];
+        let mut level = (0..formulas.len())
+            .filter(|&i| pending[i] == 0)
+            .collect::<Vec<_>>();
+        loop {
+            if level.is_empty() {
+                level = cycles(&refs, &done);
+                if level.is_empty() {
+                    break;
+                }
+                for &i in &level {
+                    self.set_unchecked(formulas[i], CellError::CircularRef.into());
+                }
+            } else {
+                self.resolve_level(env, level.iter().map(|&i| formulas[i]).collect());
+            }
+
+            level.iter().for_each(|&i| done[i] = true);
+            let mut next = vec![];
+            for &d in level.iter().flat_map(|&i| &dependents[i]) {
+                pending[d] -= 1;
+                if pending[d] == 0 && !done[d] {
+                    next.push(d);
+                }
+            }
+            next.sort_unstable();
+            level = next;
+        }
+    }
+
+    fn resolve_level(&mut self, env: &mut Env, level: Vec<Position>) {
+        #[cfg(feature = "parallel")]
+        if env.parallel {
+            let (sheet, ops, budget) = (&*self, env.ops, &env.budget);
+            let resolved = level
+                .into_par_iter()
+                .map(|pos| {
+                    let mut env = Env::new(ops, budget.clone());
+                    let expr = sheet.resolve_on_pos(pos, pos, &mut env);
+                    (pos, expr, env.budget)
+                })
+                .collect::<Vec<_>>();
+
+            let base = env.budget.clone();
+            for (pos, expr, budget) in resolved {
+                env.budget.merge(&base, &budget);
+                if let Some(expr) = expr {
+                    self.set_unchecked(pos, expr);
+                }
+            }
+            return;
+        }
+
+        for pos in level {
+            self.resolve_on_pos(pos, pos, env);
+        }
+        self.write_resolved(env);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{RawCellData, RawSheet};
+    use crate::sheets::{operators, EvalOptions, Sheet};
+
+    #[test]
+    fn same_as_sequential() {
+        let formulas = [
+            vec!["1", "=SUM(A1, 1)", "=MULTIPLY(B1, C2)", "=SEQUENCE(2)", ""],
+            vec!["=A3", "=B2", "=SUM(A1, B1)", "", "=SUM(D1#)"],
+            vec![
+                "=A2",
+                "=LET(x, C2, MULTIPLY(x, x))",
+                "=D2",
+                "=SUM(E2, E1)",
+                "x",
+            ],
+            vec![
+                "=LAMBDA(x, SUM(x, A1))(B3)",
+                "=SORT({3,1,2})",
+                "",
+                "=FILTER(D1#, GT(D1#, 1))",
+                "=C1",
+            ],
+        ];
+        let raw = || RawSheet {
+            id: "sheet-test".to_owned(),
+            data: formulas
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|f| match f.is_empty() {
+                            true => RawCellData::Empty,
+                            false => RawCellData::String(f.to_string()),
+                        })
+                        .collect()
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        };
+
+        let ops = operators::get_default_op_map();
+        let parallel = Sheet::from(raw()).resolve_refs(&ops);
+        let options = EvalOptions {
+            sequential: true,
+            ..Default::default()
+        };
+        let sequential = Sheet::from(raw()).resolve_refs_with(&ops, &options);
+
+        assert_eq!(parallel, sequential);
+    }
+}
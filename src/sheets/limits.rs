@@ -0,0 +1,321 @@
+//! Contains limits of evaluation, so that a malicious or just a huge sheet
+//! can't blow the stack or run unbounded
+//!
+//! Cells which exceed a limit evaluate to [CellError::Limit],
+//! once the steps or the time run out, all of the remaining cells do
+
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+use super::{CellError, Expr};
+
+/// Nesting of formulas which can be parsed, deeper formulas are not parsed at all
+/// and evaluate to [CellError::Limit] regardless of [Limits::max_depth]
+pub const MAX_PARSE_DEPTH: usize = 100;
+
+const DEPTH: CellError = CellError::Limit(Cow::Borrowed("nesting depth"));
+const NODES: CellError = CellError::Limit(Cow::Borrowed("formula size"));
+const CELLS: CellError = CellError::Limit(Cow::Borrowed("evaluated cells"));
+const STEPS: CellError = CellError::Limit(Cow::Borrowed("evaluation steps"));
+const TIME: CellError = CellError::Limit(Cow::Borrowed("evaluation time"));
+
+/// Part of [super::EvalOptions]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Most nested evaluations within a formula, counting nested formulas
+    /// and calls of lambdas, and most formulas of cells evaluated within each other
+    ///
+    /// Referenced formulas are evaluated before the formulas referencing them,
+    /// so cells are only evaluated within each other through references
+    /// within defined functions, see [super::defined]
+    pub max_depth: usize,
+    /// Most formulas, references and values within a single cell
+    pub max_nodes: usize,
+    /// Most formulas evaluated in a single pass over the sheet,
+    /// the sheet is evaluated again when arrays spill
+    pub max_cells: usize,
+    /// Most expressions evaluated in the whole sheet
+    pub max_steps: u64,
+    /// Time the whole sheet can take to evaluate
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    /// Limits which a well-behaved sheet won't hit, without a timeout
+    fn default() -> Self {
+        Self {
+            max_depth: 256,
+            max_nodes: 10_000,
+            max_cells: 1_000_000,
+            max_steps: 100_000_000,
+            timeout: None,
+        }
+    }
+}
+
+/// What is left of [Limits] during evaluation of a sheet
+#[derive(Debug, Clone)]
+pub(super) struct Budget {
+    limits: Limits,
+    depth: usize,
+    // formulas of cells being evaluated within each other
+    nested: usize,
+    cells: usize,
+    steps: u64,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub(super) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            depth: 0,
+            nested: 0,
+            cells: 0,
+            steps: 0,
+            deadline: limits.timeout.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Has to be followed by [Budget::exit] if it succeeds
+    pub(super) fn enter(&mut self) -> Result<(), CellError> {
+        if self.steps >= self.limits.max_steps {
+            return Err(STEPS);
+        }
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(TIME);
+        }
+        if self.depth >= self.limits.max_depth {
+            return Err(DEPTH);
+        }
+
+        self.steps += 1;
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub(super) fn exit(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Starts evaluating a formula of a cell, the depth of which is counted from zero,
+    /// has to be followed by [Budget::exit_cell] with the returned depth if it succeeds
+    pub(super) fn enter_cell(&mut self) -> Result<usize, CellError> {
+        if self.nested >= self.limits.max_depth {
+            return Err(DEPTH);
+        }
+
+        self.nested += 1;
+        Ok(std::mem::take(&mut self.depth))
+    }
+
+    pub(super) fn exit_cell(&mut self, depth: usize) {
+        self.nested -= 1;
+        self.depth = depth;
+    }
+
+    /// Checks if a formula of a cell can be evaluated
+    pub(super) fn cell(&mut self, expr: &Expr) -> Result<(), CellError> {
+        if self.cells >= self.limits.max_cells {
+            return Err(CELLS);
+        }
+        if nodes(expr, self.limits.max_nodes) > self.limits.max_nodes {
+            return Err(NODES);
+        }
+
+        self.cells += 1;
+        Ok(())
+    }
+
+    pub(super) fn start_pass(&mut self) {
+        self.cells = 0;
+    }
+
+    /// Adds what `forked` has used since it was cloned from `base`
+    #[cfg(feature = "parallel")]
+    pub(super) fn merge(&mut self, base: &Budget, forked: &Budget) {
+        self.steps += forked.steps - base.steps;
+        self.cells += forked.cells - base.cells;
+    }
+}
+
+// counts nodes of `expr`, stops counting after `max`
+fn nodes(expr: &Expr, max: usize) -> usize {
+    let (callee, args) = match expr {
+        Expr::Form(op_info) => (None, &op_info.args[..]),
+        Expr::Call(callee, args) => (Some(&**callee), &args[..]),
+        _ => (None, &[][..]),
+    };
+
+    callee
+        .into_iter()
+        .chain(args)
+        .fold(1, |n, e| if n > max { n } else { n + nodes(e, max) })
+}
+
+/// Checks nesting of parentheses and braces outside of strings of a formula,
+/// so that parsing it doesn't blow the stack
+///
+/// Calls of a result, e.g. `LAMBDA(x, x)(1)`, are nested in the callee
+pub(super) fn check_parse_depth(formula: &str) -> Result<(), CellError> {
+    if !formula.starts_with('=') {
+        return Ok(());
+    }
+
+    let mut depth = 0usize;
+    let mut in_str = false;
+    let mut chars = formula.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_str = !in_str,
+            '(' | '{' if !in_str => {
+                depth += 1;
+                if depth > MAX_PARSE_DEPTH {
+                    return Err(DEPTH);
+                }
+            }
+            ')' if !in_str && chars.peek() == Some(&'(') => {}
+            ')' | '}' if !in_str => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::RawCellData;
+    use crate::sheets::tests::resolve_row;
+    use crate::sheets::{operators, EvalOptions, Sheet};
+    use crate::types::num::Num;
+
+    fn resolve(formulas: &[&str], limits: Limits) -> Vec<Expr> {
+        // limits are checked by each thread on it's own when evaluating in parallel
+        let options = EvalOptions {
+            limits,
+            sequential: true,
+            ..Default::default()
+        };
+        resolve_row(vec![], formulas, &operators::get_default_op_map(), &options)
+    }
+
+    fn nested(depth: usize) -> String {
+        format!("={}1{}", "SUM(".repeat(depth), ")".repeat(depth))
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let limits = Limits {
+            max_depth: 50,
+            ..Default::default()
+        };
+        let (deep, too_deep, unparsable) = (nested(49), nested(50), nested(MAX_PARSE_DEPTH + 1));
+        assert_eq!(
+            resolve(&[&deep, &too_deep, &unparsable], limits),
+            vec![Num::I(1).into(), DEPTH.into(), DEPTH.into()]
+        );
+
+        let calls = format!("=LAMBDA(x, x){}", "(1)".repeat(MAX_PARSE_DEPTH));
+        assert_eq!(check_parse_depth(&calls), Err(DEPTH));
+        assert_eq!(
+            check_parse_depth(&format!("=\"{}\"", "(".repeat(300))),
+            Ok(())
+        );
+
+        // cycles which don't go through the evaluated cell
+        let circular = Expr::from(CellError::CircularRef);
+        assert_eq!(
+            resolve(&["=B1", "=C1", "=B1"], Limits::default()),
+            vec![circular.clone(), circular.clone(), circular]
+        );
+    }
+
+    #[test]
+    fn references_dont_nest() {
+        // every cell of the column references the one above it, `A300` is `=SUM(A299, 1)`
+        let chain = |options: &EvalOptions| {
+            let sheet = Sheet {
+                id: "sheet-test".to_owned(),
+                cells: (1..=300)
+                    .map(|y| match y {
+                        1 => vec![Num::I(1).into()],
+                        y => vec![RawCellData::String(format!("=SUM(A{}, 1)", y - 1)).into()],
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
+            };
+            let ops = operators::get_default_op_map();
+            sheet.resolve_refs_with(&ops, options).cells.into_rows()
+        };
+
+        let expected = (1..=300)
+            .map(|n| vec![Num::I(n).into()])
+            .collect::<Vec<Vec<Expr>>>();
+        let sequential = EvalOptions {
+            sequential: true,
+            ..Default::default()
+        };
+        assert_eq!(chain(&sequential), expected);
+        assert_eq!(chain(&EvalOptions::default()), expected);
+    }
+
+    #[test]
+    fn nodes_and_cells() {
+        let limits = Limits {
+            max_nodes: 3,
+            max_cells: 3,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            resolve(
+                &["=SUM(1, 2)", "=SUM(1, 2, 3)", "=A1", "x", "=A1", "=A1"],
+                limits
+            ),
+            vec![
+                Num::I(3).into(),
+                NODES.into(),
+                Num::I(3).into(),
+                "x".to_owned().into(),
+                Num::I(3).into(),
+                CELLS.into(),
+            ]
+        );
+
+        // arrays can't hold more values than cells evaluated
+        assert_eq!(
+            resolve(&["=SEQUENCE(3)", "=SEQUENCE(2, 2)"], limits),
+            vec![
+                Num::I(1).into(),
+                CellError::Limit("array size".into()).into()
+            ]
+        );
+    }
+
+    #[test]
+    fn steps_and_time() {
+        let limits = Limits {
+            max_steps: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve(&["=SUM(1, 2)", "=SUM(1, 2)", "x"], limits),
+            vec![Num::I(3).into(), STEPS.into(), "x".to_owned().into()]
+        );
+
+        let limits = Limits {
+            timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve(&["=SUM(1, 2)", "x"], limits),
+            vec![TIME.into(), "x".to_owned().into()]
+        );
+    }
+}
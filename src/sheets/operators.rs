@@ -1,10 +1,24 @@
 //! Contains implementations of operators
 
+pub mod arrays;
+pub mod dates;
+pub mod help;
+pub mod math;
+pub mod registry;
+#[cfg(feature = "scripting")]
+pub mod script;
+
+use crate::types::array::Array;
 use crate::types::box_value::BoxValue;
 
 use super::*;
 use std::ops::RangeInclusive;
 
+pub use help::OpHelp;
+pub use registry::{OpRegistry, ParamKind};
+
+use registry::{FromArg, NonEmpty};
+
 /// A Type designed to handle type conversions (and errors when converting them)
 /// and handle'ing arity of operators
 #[derive(Debug)]
@@ -16,9 +30,9 @@ struct MyHandler<T> {
 
 
 // helper function
-// finds all type downcasting errors within specified range
+// finds all type conversion errors within specified range
 // NOTE: it does not handle argument arity
-fn find_type_errors<'a, T: Value + Clone>(
+fn find_type_errors<'a, T: FromArg>(
     info: &'a OpInfo,
     range: RangeInclusive<usize>,
     type_name: &'static str,
@@ -32,15 +46,26 @@ fn find_type_errors<'a, T: Value + Clone>(
         .map(|(u, e): (_, &Expr)| {
             (
                 u,
-                e.clone()
-                    .unwrap_value()
-                    .move_inner()
-                    .downcast_ref::<T>()
-                    .map(|v: &T| v.clone()),
+                T::from_arg(e.unwrap_value_ref()),
             )
         })
         .filter(|(_, e)| e.is_none())
-        .map(move |(u, _)| CellError::ArgError(u, Box::new(CellError::TypeMismatch(type_name))))
+        .map(move |(u, _)| CellError::ArgError(u, Box::new(CellError::TypeMismatch(type_name.into()))))
+}
+
+// arguments with arrays flattened into their values, row by row,
+// each value is paired with the index of the argument it comes from
+fn flatten_arrays(args: &[Expr]) -> Vec<(usize, Expr)> {
+    args.iter()
+        .enumerate()
+        .flat_map(|(u, e)| match e {
+            Expr::Value(v) => match v.downcast_ref::<Array>() {
+                Some(array) => array.iter().map(|(_, _, v)| (u, v.clone().into())).collect(),
+                None => vec![(u, e.clone())],
+            },
+            _ => vec![(u, e.clone())],
+        })
+        .collect()
 }
 
 impl MyHandler<()> {
@@ -60,25 +85,28 @@ impl MyHandler<()> {
 }
 
 impl<T> MyHandler<T> {
-    /// Checks if constant amount of arguments exist within the `L..=U` range
+    /// Checks if `N` arguments exist starting from the `L`-th one,
+    /// i.e. within the `L..=(L + N - 1)` range
     ///
     /// If you want to also check if the underlying types within the `Expr`
     /// use `handle_type_*` functions
-    fn handle_const<const L: usize, const U: usize>(
+    fn handle_const<const L: usize, const N: usize>(
         mut self: MyHandler<T>,
-    ) -> Result<MyHandler<([BoxValue; U - L + 1], T)>, Vec<CellError>> {
+    ) -> Result<MyHandler<([BoxValue; N], T)>, Vec<CellError>> {
+        let u = L + N - 1;
+
         // handle arg count errors
-        let len = (&self).op_info.args.len();
-        if len < (U + 1) {
-            (&mut self)
+        let len = self.op_info.args.len();
+        if len < (u + 1) {
+            self
                 .err_state
-                .push(CellError::InvalidArgCount(L..=U, len))
+                .push(CellError::InvalidArgCount(L..=u, len))
         }
 
-        if self.err_state.len() > 0 {
+        if !self.err_state.is_empty() {
             Err(self.err_state)
         } else {
-            let new_inner: [BoxValue; U - L + 1] = self.op_info.args[L..=U]
+            let new_inner: [BoxValue; N] = self.op_info.args[L..=u]
                 .iter()
                 .map(|e| e.clone().unwrap_value().clone())
                 .collect::<Vec<BoxValue>>()
@@ -93,34 +121,37 @@ impl<T> MyHandler<T> {
         }
     }
 
-    /// Type-checks arguments within the specified range `L..=U`
+    /// Type-checks `N` arguments starting from the `L`-th one,
+    /// i.e. within the `L..=(L + N - 1)` range
     ///
     /// NOTE: because it handles a constant-length array
-    /// if where are less than `U` arguments
+    /// if where are less than `L + N` arguments
     /// Err([Cell::InvalidArgCount]) will be returned amongst other errors
     /// if you want to handle non-constant amount of arguments use [handle_type]
-    fn handle_type_const<V: Value + Clone, const L: usize, const U: usize>(
+    fn handle_type_const<V: FromArg, const L: usize, const N: usize>(
         mut self: MyHandler<T>,
         type_name: &'static str,
-    ) -> Result<MyHandler<([V; U - L + 1], T)>, Vec<CellError>> {
+    ) -> Result<MyHandler<([V; N], T)>, Vec<CellError>> {
+        let u = L + N - 1;
+
         // handle arg count errors
-        let len = (&self).op_info.args.len();
-        if len < U + 1 {
-            (&mut self)
+        let len = self.op_info.args.len();
+        if len < u + 1 {
+            self
                 .err_state
-                .push(CellError::InvalidArgCount(L..=U, len))
+                .push(CellError::InvalidArgCount(L..=u, len))
         }
 
         let op_info_ref = &self.op_info;
         let self_err_mut = &mut self.err_state;
-        self_err_mut.extend(find_type_errors::<V>(op_info_ref, L..=U, type_name));
+        self_err_mut.extend(find_type_errors::<V>(op_info_ref, L..=u, type_name));
 
-        if self.err_state.len() > 0 {
+        if !self.err_state.is_empty() {
             Err(self.err_state)
         } else {
-            let new_inner: [V; U - L + 1] = (&self.op_info.args[L..=U])
+            let new_inner: [V; N] = self.op_info.args[L..=u]
                 .iter()
-                .map(|e: &Expr| -> V { e.unwrap_downcast_ref::<V>().clone() })
+                .map(|e: &Expr| -> V { V::from_arg(e.unwrap_value_ref()).unwrap() })
                 .collect::<Vec<_>>()
                 .try_into()
                 .unwrap();
@@ -133,33 +164,36 @@ impl<T> MyHandler<T> {
         }
     }
 
-    /// Type-checks arguments within the specified range
+    /// Type-checks arguments within the specified range,
+    /// arrays are flattened into their values
     ///
     /// NOTE: This method allows for there to less arguments
     /// than in the specified range.
     ///
     /// If you want strictly the amount of arguments in
     /// the specified range use [handle_type_const]
-    fn handle_type_variadic<V: Value + Clone>(
+    fn handle_type_variadic<V: FromArg>(
         mut self: MyHandler<T>,
         range: RangeInclusive<usize>,
         type_name: &'static str,
     ) -> Result<MyHandler<(Vec<V>, T)>, Vec<CellError>> {
+        let values = flatten_arrays(&self.op_info.args)
+            .into_iter()
+            .filter(|(u, e)| range.contains(u) && !e.is_err())
+            .map(|(u, e)| (u, V::from_arg(e.unwrap_value_ref())))
+            .collect::<Vec<_>>();
+
         self.err_state.extend(
-            find_type_errors::<V>(&(&self).op_info, range.clone(), type_name).collect::<Vec<_>>(),
+            values
+                .iter()
+                .filter(|(_, v)| v.is_none())
+                .map(|(u, _)| CellError::ArgError(*u, Box::new(CellError::TypeMismatch(type_name.into())))),
         );
 
-        if self.err_state.len() > 0 {
+        if !self.err_state.is_empty() {
             Err(self.err_state)
         } else {
-            let new_inner: Vec<V> = self
-                .op_info
-                .args
-                .iter()
-                .skip(*range.start())
-                .take(*range.end() - *range.start() + 1)
-                .map(|e: &Expr| -> V { e.unwrap_downcast_ref::<V>().clone() })
-                .collect::<Vec<_>>();
+            let new_inner: Vec<V> = values.into_iter().map(|(_, v)| v.unwrap()).collect();
 
             Ok(MyHandler {
                 err_state: self.err_state,
@@ -177,10 +211,16 @@ impl<T> MyHandler<T> {
 // ()
 // (Vec<Num>, ())
 
-const MAX_ARGS: usize = u32::MAX as usize;
-pub type Operator = Box<dyn Fn(&mut Sheet, &mut OpInfo) -> Result<Expr, Vec<CellError>>>;
+pub(super) const MAX_ARGS: usize = u32::MAX as usize;
+/// Operators are shared by sheets which are evaluated in parallel
+pub type Operator = Box<dyn Fn(&Sheet, &mut OpInfo) -> Result<Expr, Vec<CellError>> + Send + Sync>;
+
+pub fn get_default_op_map() -> OpRegistry {
+    get_op_map_with_clock(dates::SystemClock)
+}
 
-pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
+/// Same as [get_default_op_map], but `TODAY` and `NOW` read the time from `clock`
+pub fn get_op_map_with_clock(clock: impl dates::Clock + 'static) -> OpRegistry {
     let sum: Operator = Box::new(|_, info: &mut OpInfo| {
         Ok(MyHandler::new(info.clone())
             .handle_type_variadic::<Num>(0..=MAX_ARGS, "Num")?
@@ -203,7 +243,7 @@ pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
 
     let div: Operator = Box::new(|_, info| {
         let [l, r] = MyHandler::new(info.clone())
-            .handle_type_const::<Num, 0, 1>("Num")?
+            .handle_type_const::<Num, 0, 2>("Num")?
             .finish()
             .0;
 
@@ -216,7 +256,7 @@ pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
 
     let gt: Operator = Box::new(|_, info| {
         let [l, r] = MyHandler::new(info.clone())
-            .handle_const::<0, 1>()?
+            .handle_const::<0, 2>()?
             .finish()
             .0;
         if l.type_id() != r.type_id() {
@@ -228,7 +268,7 @@ pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
 
     let eq: Operator = Box::new(|_, info| {
         let [l, r] = MyHandler::new(info.clone())
-            .handle_const::<0, 1>()?
+            .handle_const::<0, 2>()?
             .finish()
             .0;
 
@@ -242,7 +282,7 @@ pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
     let not: Operator = Box::new(|_, info| {
         Ok({
             let [bool] = MyHandler::new(info.clone())
-                .handle_type_const::<bool, 0, 0>("Boolean")?
+                .handle_type_const::<bool, 0, 1>("Boolean")?
                 .finish()
                 .0;
 
@@ -256,7 +296,7 @@ pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
             .finish()
             .0
             .into_iter()
-            .fold(true, |n1, n2| n1 && n2)
+            .all(|b| b)
             .into())
     });
 
@@ -266,13 +306,13 @@ pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
             .finish()
             .0
             .into_iter()
-            .fold(false, |n1, n2| n1 || n2)
+            .any(|b| b)
             .into())
     });
 
     let r#if: Operator = Box::new(|_, info| {
         let ([arg1, arg2], ([cond], ())) = MyHandler::new(info.clone())
-            .handle_type_const::<bool, 0, 0>("Boolean")?
+            .handle_type_const::<bool, 0, 1>("Boolean")?
             .handle_const::<1, 2>()?
             .finish();
 
@@ -295,16 +335,86 @@ pub fn get_default_op_map<'a>() -> HashMap<&'a str, Operator> {
             .into())
     });
 
-    HashMap::from([
-        ("SUM", sum),
-        ("MULTIPLY", mul),
-        ("DIVIDE", div),
-        ("GT", gt),
-        ("EQ", eq),
-        ("NOT", not),
-        ("AND", and),
-        ("OR", or),
-        ("IF", r#if),
-        ("CONCAT", concat),
-    ])
+    let mut registry = OpRegistry::new();
+
+    registry
+        .register("AVERAGE", |nums: NonEmpty<Num>| {
+            if nums.is_empty() {
+                return Err(CellError::DivByZero);
+            }
+
+            let count = Num::I(nums.len() as i64);
+            Ok(nums.0.into_iter().fold(Num::I(0), |n1, n2| n1 + n2) / count)
+        })
+        .params(["nums"])
+        .description("Calculates the mean of all numbers, empty cells are skipped")
+        .example("=AVERAGE(A1, B1, C1)");
+
+    registry
+        .register_raw("SUM", sum)
+        .param("nums", "Num", ParamKind::Variadic)
+        .description("Adds all numbers together")
+        .example("=SUM(1, 2, A1)");
+    registry
+        .register_raw("MULTIPLY", mul)
+        .param("nums", "Num", ParamKind::Variadic)
+        .description("Multiplies all numbers together")
+        .example("=MULTIPLY(2, A1)");
+    registry
+        .register_raw("DIVIDE", div)
+        .param("dividend", "Num", ParamKind::Required)
+        .param("divisor", "Num", ParamKind::Required)
+        .description("Divides `dividend` by `divisor`, fails if `divisor` is zero")
+        .example("=DIVIDE(A1, 5)");
+    registry
+        .register_raw("GT", gt)
+        .param("left", "Any", ParamKind::Required)
+        .param("right", "Any", ParamKind::Required)
+        .description("Checks if `left` is greater than `right`, both have to be of the same type")
+        .example("=GT(A1, 4.9)");
+    registry
+        .register_raw("EQ", eq)
+        .param("left", "Any", ParamKind::Required)
+        .param("right", "Any", ParamKind::Required)
+        .description("Checks if `left` is equal to `right`, both have to be of the same type")
+        .example("=EQ(A1, B1)");
+    registry
+        .register_raw("NOT", not)
+        .param("bool", "Boolean", ParamKind::Required)
+        .description("Negates a boolean")
+        .example("=NOT(A1)");
+    registry
+        .register_raw("AND", and)
+        .param("bools", "Boolean", ParamKind::Variadic)
+        .description("Checks if all booleans are true")
+        .example("=AND(true, A2)");
+    registry
+        .register_raw("OR", or)
+        .param("bools", "Boolean", ParamKind::Variadic)
+        .description("Checks if at least one boolean is true")
+        .example("=OR(false, A3)");
+    registry
+        .register_raw("IF", r#if)
+        .param("cond", "Boolean", ParamKind::Required)
+        .param("then", "Any", ParamKind::Required)
+        .param("else", "Any", ParamKind::Required)
+        .description("Returns `then` if `cond` is true, `else` otherwise")
+        .example("=IF(EQ(A1, B1), \"Equal\", \"Not equal\")");
+    registry
+        .register_raw("CONCAT", concat)
+        .param("strings", "String", ParamKind::Variadic)
+        .description("Joins all strings together")
+        .example("=CONCAT(\"Hello, \", A1)");
+
+    dates::register_date_ops(&mut registry, clock);
+    math::register_math_ops(&mut registry);
+    arrays::register_array_ops(&mut registry);
+    lambda::register_special_forms(&mut registry);
+
+    registry
+        .alias("MUL", "MULTIPLY")
+        .alias("PRODUCT", "MULTIPLY")
+        .alias("DIV", "DIVIDE");
+
+    registry
 }
@@ -0,0 +1,449 @@
+//! Contains operators which take or return arrays
+//!
+//! Arrays returned by formulas spill into neighbouring cells,
+//! a 1x1 result is returned as a plain value.
+//! Lookup operators take arrays as tables, e.g. `=VLOOKUP("b", {"a",1;"b",2}, 2)`
+
+use std::cmp::Ordering;
+
+use super::registry::{FromArg, IntoOperator, OpRegistry, ParamKind};
+use crate::sheets::CellError;
+use crate::types::array::Array;
+use crate::types::box_value::BoxValue;
+use crate::types::num::Num;
+
+/// Any other value is treated as a 1x1 array
+impl FromArg for Array {
+    const TYPE_NAME: &'static str = "Array";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        Some(
+            value
+                .downcast_ref::<Array>()
+                .cloned()
+                .unwrap_or_else(|| Array::single(value.clone())),
+        )
+    }
+}
+
+const INCLUDE_SHAPE: &str = "`include` as a column as high or a row as wide as the array";
+
+// `FILTER` also accepts numbers, where zero is false
+fn is_included(value: &BoxValue) -> Result<bool, CellError> {
+    value
+        .downcast_ref::<bool>()
+        .copied()
+        .or_else(|| value.downcast_ref::<Num>().map(|n| !n.is_zero()))
+        .ok_or(CellError::TypeMismatch(<bool as FromArg>::TYPE_NAME.into()))
+}
+
+fn result(rows: Vec<Vec<BoxValue>>) -> Result<BoxValue, CellError> {
+    Array::new(rows)
+        .map(Array::into_value)
+        .ok_or(CellError::EmptyArray)
+}
+
+// converts a 1-based index into a 0-based one, if it's within `1..=len`
+fn index(i: &Num, len: usize) -> Option<usize> {
+    usize::try_from(i.trunc())
+        .ok()
+        .filter(|i| (1..=len).contains(i))
+        .map(|i| i - 1)
+}
+
+// an array can't hold more values than the cells evaluated in a pass over the sheet
+fn sequence(
+    max_cells: usize,
+) -> impl Fn(Num, Option<Num>, Option<Num>, Option<Num>) -> Result<BoxValue, CellError> {
+    move |rows, columns, start, step| {
+        let (rows, columns) = (rows.trunc(), columns.map_or(1, |c| c.trunc()));
+        if rows < 1 || columns < 1 {
+            return Err(CellError::InvalidValue(
+                "a positive number of rows and columns".into(),
+            ));
+        }
+        if rows
+            .checked_mul(columns)
+            .is_none_or(|n| n > max_cells as i64)
+        {
+            return Err(CellError::Limit("array size".into()));
+        }
+
+        let start = start.unwrap_or(Num::I(1));
+        let step = step.unwrap_or(Num::I(1));
+        result(
+            (0..rows)
+                .map(|y| {
+                    (0..columns)
+                        .map(|x| (start.clone() + step.clone() * Num::I(y * columns + x)).into())
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Registers `SEQUENCE`, `SORT`, `FILTER`, `UNIQUE`, `TRANSPOSE`,
+/// `INDEX`, `MATCH` and `VLOOKUP`
+pub fn register_array_ops(registry: &mut OpRegistry) {
+    registry
+        .register_raw(
+            "SEQUENCE",
+            Box::new(|sheet, info| sequence(info.limits.max_cells).into_operator()(sheet, info)),
+        )
+        .param("rows", "Num", ParamKind::Required)
+        .param("columns", "Num", ParamKind::Optional)
+        .param("start", "Num", ParamKind::Optional)
+        .param("step", "Num", ParamKind::Optional)
+        .description("Creates an array of numbers from `start` (default 1) increasing by `step` (default 1), row by row")
+        .example("=SEQUENCE(3, 2, 10, 5)");
+
+    registry
+        .register("SORT", |array: Array, index: Option<Num>, order: Option<Num>| {
+            let index = index.map_or(1, |i| i.trunc());
+            if index < 1 || index as usize > array.width() {
+                return Err(CellError::InvalidValue("a sort index within the array".into()));
+            }
+
+            let descending = match order.map_or(1, |o| o.trunc()) {
+                1 => false,
+                -1 => true,
+                _ => return Err(CellError::InvalidValue("a sort order of 1 or -1".into())),
+            };
+
+            // blanks are last in both orders
+            let column = index as usize - 1;
+            let mut rows = array.into_rows();
+            rows.sort_by(|r1, r2| {
+                let (v1, v2) = (&r1[column], &r2[column]);
+                let ordering = type_order(v1)
+                    .cmp(&type_order(v2))
+                    .then_with(|| v1.partial_cmp(v2).unwrap_or(Ordering::Equal));
+                let blank = |v: &BoxValue| matches!(v, BoxValue::Empty(_));
+                if descending && !blank(v1) && !blank(v2) {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+
+            result(rows)
+        })
+        .params(["array", "index", "order"])
+        .description("Sorts rows of an array by the column `index` (default 1), `order` is 1 for ascending (default) or -1 for descending")
+        .example("=SORT(A1#, 2)");
+
+    registry
+        .register("FILTER", |array: Array, include: Array, if_empty: Option<BoxValue>| {
+            let (height, width) = (include.height(), include.width());
+            let include = include
+                .rows()
+                .iter()
+                .flatten()
+                .map(is_included)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // a column filters rows, a row filters columns
+            let rows = if width == 1 && height == array.height() {
+                array
+                    .into_rows()
+                    .into_iter()
+                    .zip(&include)
+                    .filter(|(_, keep)| **keep)
+                    .map(|(row, _)| row)
+                    .collect()
+            } else if height == 1 && width == array.width() {
+                array
+                    .into_rows()
+                    .into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .zip(&include)
+                            .filter(|(_, keep)| **keep)
+                            .map(|(v, _)| v)
+                            .collect()
+                    })
+                    .collect()
+            } else {
+                return Err(CellError::InvalidValue(INCLUDE_SHAPE.into()));
+            };
+
+            match (result(rows), if_empty) {
+                (Err(CellError::EmptyArray), Some(if_empty)) => Ok(if_empty),
+                (result, _) => result,
+            }
+        })
+        .params(["array", "include", "if_empty"])
+        .description("Keeps rows (or columns) of an array for which `include` is true or a non-zero number, `if_empty` is returned if nothing is kept")
+        .example("=FILTER(A1#, B1#, \"none\")");
+
+    registry
+        .register("UNIQUE", |array: Array| {
+            let mut rows: Vec<Vec<BoxValue>> = vec![];
+            for row in array.into_rows() {
+                if !rows.contains(&row) {
+                    rows.push(row);
+                }
+            }
+
+            result(rows)
+        })
+        .params(["array"])
+        .description("Removes duplicate rows of an array, keeping the first ones")
+        .example("=UNIQUE(A1#)");
+
+    registry
+        .register("TRANSPOSE", |array: Array| array.transpose().into_value())
+        .params(["array"])
+        .description("Swaps rows and columns of an array")
+        .example("=TRANSPOSE(SEQUENCE(3))");
+
+    registry
+        .register("INDEX", |array: Array, row: Num, column: Option<Num>| {
+            // a single row is indexed by the only index given
+            let (row, column) = match column {
+                Some(column) => (row, column),
+                None if array.height() == 1 => (Num::I(1), row),
+                None => (row, Num::I(1)),
+            };
+
+            index(&row, array.height())
+                .zip(index(&column, array.width()))
+                .map(|(y, x)| array.rows()[y][x].clone())
+                .ok_or(CellError::InvalidValue("a row and column within the array".into()))
+        })
+        .params(["array", "row", "column"])
+        .description("Returns the value at `row` and `column` (default 1) of an array, counting from 1")
+        .example("=INDEX({1,2;3,4}, 2, 1)");
+
+    registry
+        .register("MATCH", |value: BoxValue, array: Array| {
+            let values = match (array.height(), array.width()) {
+                (1, _) => array.into_rows().remove(0),
+                (_, 1) => array.into_rows().into_iter().flatten().collect(),
+                _ => return Err(CellError::InvalidValue("a single row or column".into())),
+            };
+
+            values
+                .iter()
+                .position(|v| *v == value)
+                .map(|i| Num::I(i as i64 + 1))
+                .ok_or(CellError::NotFound)
+        })
+        .params(["value", "array"])
+        .description("Returns the position of the first value equal to `value` in a single row or column, counting from 1")
+        .example("=MATCH(\"b\", {\"a\",\"b\",\"c\"})");
+
+    registry
+        .register("VLOOKUP", |value: BoxValue, table: Array, column: Num| {
+            let column = index(&column, table.width())
+                .ok_or(CellError::InvalidValue("a column within the table".into()))?;
+
+            table
+                .rows()
+                .iter()
+                .find(|row| row[0] == value)
+                .map(|row| row[column].clone())
+                .ok_or(CellError::NotFound)
+        })
+        .params(["value", "table", "column"])
+        .description("Finds the first row of a table whose first value is equal to `value` and returns its value in `column`, counting from 1")
+        .example("=VLOOKUP(A1, {1,\"one\";2,\"two\"}, 2)");
+}
+
+// values of different types are sorted like in Excel: numbers, texts, booleans,
+// then values of other types, which are left in place if they are not comparable, and blanks
+fn type_order(value: &BoxValue) -> u8 {
+    match value {
+        BoxValue::Num(_) => 0,
+        BoxValue::String(_) => 1,
+        BoxValue::Bool(_) => 2,
+        BoxValue::Other(_) => 3,
+        BoxValue::Empty(_) => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::RawCellData;
+    use crate::sheets::tests::resolve_rows;
+    use crate::sheets::{operators, EvalOptions, Expr};
+    use crate::types::empty::Empty;
+
+    // `A1` is -1, `B1` is 0 and the formula is placed in `A2`,
+    // returns cells of the formula's row and below, where the result spills
+    fn resolve(formula: &str) -> Vec<Vec<Expr>> {
+        resolve_rows(
+            vec![vec![RawCellData::Int(-1), RawCellData::Int(0)]],
+            &[&format!("={}", formula)],
+            &operators::get_default_op_map(),
+            &EvalOptions::default(),
+        )
+    }
+
+    fn nums(rows: &[&[i64]]) -> Vec<Vec<Expr>> {
+        rows.iter()
+            .map(|row| row.iter().map(|i| Num::I(*i).into()).collect())
+            .collect()
+    }
+
+    fn error(e: CellError) -> Vec<Vec<Expr>> {
+        vec![vec![CellError::FormError(vec![e]).into()]]
+    }
+
+    #[test]
+    fn sequence() {
+        assert_eq!(resolve("SEQUENCE(1)"), nums(&[&[1]]));
+        assert_eq!(resolve("SEQUENCE(3)"), nums(&[&[1], &[2], &[3]]));
+        assert_eq!(
+            resolve("SEQUENCE(2, 3, 10, 5)"),
+            nums(&[&[10, 15, 20], &[25, 30, 35]])
+        );
+        assert_eq!(
+            resolve("SEQUENCE(B1, 3)"),
+            error(CellError::InvalidValue("a positive number of rows and columns".into()))
+        );
+        let too_large = vec![vec![CellError::Limit("array size".into()).into()]];
+        assert_eq!(resolve("SEQUENCE(100000000000)"), too_large);
+        assert_eq!(resolve("SEQUENCE(4294967296, 4294967296)"), too_large);
+    }
+
+    #[test]
+    fn transpose() {
+        assert_eq!(
+            resolve("TRANSPOSE(SEQUENCE(2, 3))"),
+            nums(&[&[1, 4], &[2, 5], &[3, 6]])
+        );
+        assert_eq!(resolve("TRANSPOSE(7)"), nums(&[&[7]]));
+    }
+
+    #[test]
+    fn sort_and_unique() {
+        assert_eq!(
+            resolve("SORT(SEQUENCE(3, 2), 2, A1)"),
+            nums(&[&[5, 6], &[3, 4], &[1, 2]])
+        );
+        assert_eq!(
+            resolve("SORT(SORT(SEQUENCE(3), 1, A1))"),
+            nums(&[&[1], &[2], &[3]])
+        );
+        assert_eq!(
+            resolve("UNIQUE(SEQUENCE(4, 1, 3, B1))"),
+            nums(&[&[3]])
+        );
+        assert_eq!(
+            resolve("UNIQUE(TRANSPOSE(SEQUENCE(1, 3, 2, B1)))"),
+            nums(&[&[2]])
+        );
+        let mixed = |values: Vec<BoxValue>| -> Vec<Vec<Expr>> {
+            values.into_iter().map(|v| vec![Expr::Value(v)]).collect()
+        };
+        // the fourth value is the blank cell `B2`
+        let values = "MAP(SEQUENCE(7), LAMBDA(i, IF(EQ(i, 4), B2, INDEX({\"b\";2;true;0;\"a\";1;false}, i))))";
+        let sorted = vec![
+            Num::I(1).into(),
+            Num::I(2).into(),
+            "a".into(),
+            "b".into(),
+            false.into(),
+            true.into(),
+            Empty.into(),
+        ];
+        assert_eq!(resolve(&format!("SORT({})", values)), mixed(sorted.clone()));
+        let mut descending = sorted;
+        descending[..6].reverse();
+        assert_eq!(
+            resolve(&format!("SORT({}, 1, A1)", values)),
+            mixed(descending)
+        );
+        assert_eq!(
+            resolve("SORT(SEQUENCE(3, 2), 3)"),
+            error(CellError::InvalidValue(
+                "a sort index within the array".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn filter() {
+        assert_eq!(
+            resolve("FILTER(SEQUENCE(4), SEQUENCE(4, 1, 0))"),
+            nums(&[&[2], &[3], &[4]])
+        );
+        assert_eq!(
+            resolve("FILTER(SEQUENCE(2, 3), SEQUENCE(1, 3, 0, 1))"),
+            nums(&[&[2, 3], &[5, 6]])
+        );
+        assert_eq!(resolve("FILTER(5, B1, \"none\")"), vec![vec!["none".to_owned().into()]]);
+        assert_eq!(resolve("FILTER(5, B1)"), error(CellError::EmptyArray));
+        assert_eq!(
+            resolve("FILTER(SEQUENCE(2, 2), SEQUENCE(3))"),
+            error(CellError::InvalidValue(INCLUDE_SHAPE.into()))
+        );
+        // the shape of `include` decides, not only its length
+        assert_eq!(
+            resolve("FILTER(SEQUENCE(2), TRANSPOSE(SEQUENCE(2)))"),
+            error(CellError::InvalidValue(INCLUDE_SHAPE.into()))
+        );
+        assert_eq!(resolve("FILTER({1,2;3,4}, {1,0})"), nums(&[&[1], &[3]]));
+        assert_eq!(resolve("FILTER({1,2;3,4}, {0;1})"), nums(&[&[3, 4]]));
+    }
+
+    #[test]
+    fn lookups() {
+        let one = |formula: &str| resolve(formula).swap_remove(0).swap_remove(0);
+
+        assert_eq!(one("INDEX({1,2;3,4}, 2, 1)"), Num::I(3).into());
+        assert_eq!(one("INDEX({1,2,3}, 3)"), Num::I(3).into());
+        assert_eq!(one("INDEX(SEQUENCE(3), 2)"), Num::I(2).into());
+        assert_eq!(
+            one("INDEX({1,2;3,4}, 3, 1)"),
+            CellError::FormError(vec![CellError::InvalidValue(
+                "a row and column within the array".into()
+            )])
+            .into()
+        );
+
+        assert_eq!(one("MATCH(\"c\", {\"a\",\"b\",\"c\"})"), Num::I(3).into());
+        assert_eq!(one("MATCH(2, {1;2;3})"), Num::I(2).into());
+        assert_eq!(
+            one("MATCH(\"d\", {\"a\",\"b\"})"),
+            CellError::FormError(vec![CellError::NotFound]).into()
+        );
+
+        let table = "{1,\"one\";2,\"two\";3,\"three\"}";
+        assert_eq!(
+            one(&format!("VLOOKUP(2, {}, 2)", table)),
+            "two".to_owned().into()
+        );
+        assert_eq!(
+            one(&format!("VLOOKUP(B1, {}, 2)", table)),
+            CellError::FormError(vec![CellError::NotFound]).into()
+        );
+        assert_eq!(
+            one(&format!("VLOOKUP(1, {}, 3)", table)),
+            CellError::FormError(vec![CellError::InvalidValue("a column within the table".into())])
+                .into()
+        );
+    }
+
+    #[test]
+    fn array_literals_spill() {
+        assert_eq!(resolve("{1,2;3,4}"), nums(&[&[1, 2], &[3, 4]]));
+        assert_eq!(resolve("SORT({3;1;2})"), nums(&[&[1], &[2], &[3]]));
+    }
+
+    #[test]
+    fn spilled_rows_are_extended() {
+        assert_eq!(
+            resolve("SEQUENCE(2, 2)"),
+            vec![
+                vec![Num::I(1).into(), Num::I(2).into()],
+                vec![Num::I(3).into(), Num::I(4).into()],
+            ]
+        );
+        // `B1` is not blank, but it's above the formula
+        assert_ne!(resolve("SEQUENCE(1, 2)")[0][1], Empty.into());
+    }
+}
@@ -0,0 +1,369 @@
+//! Contains date operators
+//!
+//! `TODAY` and `NOW` read the current time from a [Clock],
+//! so evaluation can be made deterministic with [FixedClock]
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use chrono::{Datelike, Local, Months, NaiveDate, NaiveDateTime, TimeDelta, Weekday};
+
+use super::registry::{FromArg, OpRegistry};
+use crate::sheets::CellError;
+use crate::types::box_value::BoxValue;
+use crate::types::date::{Date, DateTime};
+use crate::types::num::Num;
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// Reads the local time of the system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// Always returns the same time
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub NaiveDateTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> NaiveDateTime {
+        self.0
+    }
+}
+
+/// [DateTime] is truncated to its date
+impl FromArg for Date {
+    const TYPE_NAME: &'static str = "Date";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        value
+            .downcast_ref::<Date>()
+            .copied()
+            .or_else(|| value.downcast_ref::<DateTime>().map(|dt| Date(dt.0.date())))
+    }
+}
+
+/// [Date] is converted to its midnight
+impl FromArg for DateTime {
+    const TYPE_NAME: &'static str = "DateTime";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        value.downcast_ref::<DateTime>().copied().or_else(|| {
+            value
+                .downcast_ref::<Date>()
+                .map(|d| DateTime(d.0.and_hms_opt(0, 0, 0).unwrap()))
+        })
+    }
+}
+
+fn add_months(date: NaiveDate, months: i64) -> Option<NaiveDate> {
+    let abs = Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+    if months < 0 {
+        date.checked_sub_months(abs)
+    } else {
+        date.checked_add_months(abs)
+    }
+}
+
+// `None` in the last month of dates which can be represented
+fn end_of_month(date: NaiveDate) -> Option<NaiveDate> {
+    add_months(date.with_day(1)?, 1)?.pred_opt()
+}
+
+fn is_weekend(date: NaiveDate) -> bool {
+    matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+// amount of whole months between two dates, `start <= end`
+fn whole_months(start: NaiveDate, end: NaiveDate) -> i64 {
+    let months = (end.year() - start.year()) as i64 * 12 + end.month() as i64
+        - start.month() as i64;
+    if end.day() < start.day() {
+        months - 1
+    } else {
+        months
+    }
+}
+
+const INVALID_DATE: CellError = CellError::InvalidValue(Cow::Borrowed("a valid date"));
+
+/// Registers `DATE`, `TODAY`, `NOW`, `YEAR`, `MONTH`, `DAY`, `WEEKDAY`, `EDATE`,
+/// `EOMONTH`, `DATEDIF`, `NETWORKDAYS` and `DATEVALUE`
+pub fn register_date_ops(registry: &mut OpRegistry, clock: impl Clock + 'static) {
+    let clock: Arc<dyn Clock> = Arc::new(clock);
+
+    registry
+        .register("DATE", |year: Num, month: Num, day: Num| {
+            // overflowing months and days roll over to the next year or month
+            NaiveDate::from_ymd_opt(i32::try_from(year.trunc()).map_err(|_| INVALID_DATE)?, 1, 1)
+                .and_then(|d| add_months(d, month.trunc() - 1))
+                .and_then(|d| d.checked_add_signed(TimeDelta::try_days(day.trunc() - 1)?))
+                .map(Date)
+                .ok_or(INVALID_DATE)
+        })
+        .params(["year", "month", "day"])
+        .description("Creates a date, months and days out of range roll over")
+        .example("=DATE(2023, 7, 17)");
+
+    let today_clock = clock.clone();
+    registry
+        .register("TODAY", move || Date(today_clock.now().date()))
+        .description("Returns the current date")
+        .example("=TODAY()");
+
+    let now_clock = clock;
+    registry
+        .register("NOW", move || DateTime(now_clock.now()))
+        .description("Returns the current date and time")
+        .example("=NOW()");
+
+    registry
+        .register("YEAR", |date: Date| Num::I(date.0.year() as i64))
+        .params(["date"])
+        .description("Returns the year of a date")
+        .example("=YEAR(A1)");
+
+    registry
+        .register("MONTH", |date: Date| Num::I(date.0.month() as i64))
+        .params(["date"])
+        .description("Returns the month of a date, from 1 to 12")
+        .example("=MONTH(A1)");
+
+    registry
+        .register("DAY", |date: Date| Num::I(date.0.day() as i64))
+        .params(["date"])
+        .description("Returns the day of month of a date, from 1 to 31")
+        .example("=DAY(A1)");
+
+    registry
+        .register("WEEKDAY", |date: Date, kind: Option<Num>| {
+            let weekday = date.0.weekday();
+            match kind.map(|k| k.trunc()).unwrap_or(1) {
+                1 => Ok(Num::I(weekday.number_from_sunday() as i64)),
+                2 => Ok(Num::I(weekday.number_from_monday() as i64)),
+                3 => Ok(Num::I(weekday.num_days_from_monday() as i64)),
+                _ => Err(CellError::InvalidValue("a weekday numbering of 1, 2 or 3".into())),
+            }
+        })
+        .params(["date", "kind"])
+        .description(
+            "Returns the day of week of a date, numbered Sunday=1..Saturday=7 for `kind` 1 (default), \
+             Monday=1..Sunday=7 for 2 and Monday=0..Sunday=6 for 3",
+        )
+        .example("=WEEKDAY(A1, 2)");
+
+    registry
+        .register("EDATE", |date: Date, months: Num| {
+            add_months(date.0, months.trunc()).map(Date).ok_or(INVALID_DATE)
+        })
+        .params(["date", "months"])
+        .description("Adds months to a date, the day is clamped to the end of the month")
+        .example("=EDATE(A1, 1)");
+
+    registry
+        .register("EOMONTH", |date: Date, months: Num| {
+            add_months(date.0, months.trunc())
+                .and_then(end_of_month)
+                .map(Date)
+                .ok_or(INVALID_DATE)
+        })
+        .params(["date", "months"])
+        .description("Returns the last day of the month `months` after the date")
+        .example("=EOMONTH(A1, 0)");
+
+    registry
+        .register("DATEDIF", |start: Date, end: Date, unit: String| {
+            let (start, end) = (start.0, end.0);
+            if start > end {
+                return Err(CellError::InvalidValue("a start date before the end date".into()));
+            }
+
+            let months = whole_months(start, end);
+            Ok(Num::I(match &unit.to_uppercase()[..] {
+                "Y" => months / 12,
+                "M" => months,
+                "D" => (end - start).num_days(),
+                "YM" => months % 12,
+                "MD" => (end - add_months(start, months).unwrap()).num_days(),
+                "YD" => (end - add_months(start, months / 12 * 12).unwrap()).num_days(),
+                _ => return Err(CellError::InvalidValue("a unit of Y, M, D, YM, MD or YD".into())),
+            }))
+        })
+        .params(["start", "end", "unit"])
+        .description(
+            "Calculates the difference between two dates in whole years (Y), months (M) or days (D), \
+             YM, MD and YD ignore years, months or both",
+        )
+        .example("=DATEDIF(A1, B1, \"M\")");
+
+    registry
+        .register("NETWORKDAYS", |start: Date, end: Date, holidays: Vec<Date>| {
+            let (sign, start, end) = if start <= end {
+                (1, start.0, end.0)
+            } else {
+                (-1, end.0, start.0)
+            };
+
+            // whole weeks have 5 working days, the days left start on the weekday of `start`
+            let days = (end - start).num_days() + 1;
+            let first = start.weekday().num_days_from_monday() as i64;
+            let left = (first..first + days % 7).filter(|d| d % 7 < 5).count() as i64;
+            let holidays = holidays
+                .iter()
+                .map(|d| d.0)
+                .filter(|d| (start..=end).contains(d) && !is_weekend(*d))
+                .collect::<BTreeSet<_>>();
+
+            Num::I(sign * (days / 7 * 5 + left - holidays.len() as i64))
+        })
+        .params(["start", "end", "holidays"])
+        .description("Counts working days (Monday to Friday) between two dates inclusively, skipping holidays")
+        .example("=NETWORKDAYS(A1, B1, C1)");
+
+    registry
+        .register("DATEVALUE", |text: String| {
+            Date::parse_iso(text.trim()).ok_or(INVALID_DATE)
+        })
+        .params(["text"])
+        .description("Converts an ISO-8601 date text (YYYY-MM-DD) to a date")
+        .example("=DATEVALUE(\"2023-07-17\")");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::RawCellData;
+    use crate::sheets::tests::resolve_row;
+    use crate::sheets::{EvalOptions, Expr};
+    use crate::types::date::Duration;
+
+    fn resolve(formulas: &[&str]) -> Vec<Expr> {
+        let mut registry = OpRegistry::new();
+        register_date_ops(
+            &mut registry,
+            FixedClock(
+                NaiveDate::from_ymd_opt(2023, 7, 17)
+                    .unwrap()
+                    .and_hms_opt(8, 30, 0)
+                    .unwrap(),
+            ),
+        );
+
+        let dates = vec![vec![
+            RawCellData::String("2023-01-31".to_owned()),
+            RawCellData::String("2024-03-15".to_owned()),
+            RawCellData::String("2023-07-19".to_owned()),
+            RawCellData::String("P1DT2H".to_owned()),
+            RawCellData::Int(-2),
+        ]];
+        resolve_row(dates, formulas, &registry, &EvalOptions::default())
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> Expr {
+        Date(NaiveDate::from_ymd_opt(y, m, d).unwrap()).into()
+    }
+
+    #[test]
+    fn literals_and_clock() {
+        assert_eq!(
+            resolve(&["=A1", "=D1", "=TODAY()", "=NOW()"]),
+            vec![
+                date(2023, 1, 31),
+                Duration(TimeDelta::try_hours(26).unwrap()).into(),
+                date(2023, 7, 17),
+                DateTime::parse_iso("2023-07-17T08:30:00").unwrap().into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn date_parts() {
+        assert_eq!(
+            resolve(&[
+                "=DATE(2023, 14, 35)",
+                "=YEAR(A1)",
+                "=MONTH(A1)",
+                "=DAY(NOW())",
+                "=WEEKDAY(TODAY())",
+                "=WEEKDAY(TODAY(), 2)",
+                "=WEEKDAY(TODAY(), 3)",
+                "=DATEVALUE(\"2023-02-29\")",
+            ]),
+            vec![
+                date(2024, 3, 6),
+                Num::I(2023).into(),
+                Num::I(1).into(),
+                Num::I(17).into(),
+                Num::I(2).into(),
+                Num::I(1).into(),
+                Num::I(0).into(),
+                CellError::FormError(vec![INVALID_DATE]).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn month_arithmetic() {
+        assert_eq!(
+            resolve(&[
+                "=EDATE(A1, 1)",
+                "=EDATE(A1, E1)",
+                "=EOMONTH(A1, 1)",
+                "=EOMONTH(DATE(2024, 2, 10), 0)",
+                "=EOMONTH(DATE(262142, 12, 1), 0)",
+            ]),
+            vec![
+                date(2023, 2, 28),
+                date(2022, 11, 30),
+                date(2023, 2, 28),
+                date(2024, 2, 29),
+                CellError::FormError(vec![INVALID_DATE]).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn differences() {
+        assert_eq!(
+            resolve(&[
+                "=DATEDIF(A1, B1, \"Y\")",
+                "=DATEDIF(A1, B1, \"M\")",
+                "=DATEDIF(A1, B1, \"D\")",
+                "=DATEDIF(A1, B1, \"YM\")",
+                "=DATEDIF(A1, B1, \"MD\")",
+                "=DATEDIF(B1, A1, \"D\")",
+                "=NETWORKDAYS(TODAY(), DATE(2023, 7, 31))",
+                "=NETWORKDAYS(TODAY(), DATE(2023, 7, 31), C1)",
+                "=NETWORKDAYS(DATE(2023, 7, 23), TODAY())",
+                // repeated, weekend and later holidays don't count
+                "=NETWORKDAYS(TODAY(), DATE(2023, 7, 31), C1, C1, DATE(2023, 7, 22), DATE(2023, 8, 1))",
+                "=NETWORKDAYS(DATE(1, 1, 1), DATE(262000, 1, 1))",
+            ]),
+            vec![
+                Num::I(1).into(),
+                Num::I(13).into(),
+                Num::I(409).into(),
+                Num::I(1).into(),
+                Num::I(15).into(),
+                CellError::FormError(vec![CellError::InvalidValue(
+                    "a start date before the end date".into()
+                )])
+                .into(),
+                Num::I(11).into(),
+                Num::I(10).into(),
+                Num::I(-5).into(),
+                Num::I(10).into(),
+                Num::I(68_352_265).into(),
+            ]
+        );
+    }
+}
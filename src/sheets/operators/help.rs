@@ -0,0 +1,212 @@
+//! Contains help metadata of operators, which is used to
+//! describe them to the user and to suggest names of existing ones
+
+use std::fmt::Display;
+
+use super::registry::{ParamKind, Signature};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamHelp {
+    pub name: String,
+    pub type_name: &'static str,
+    pub kind: ParamKind,
+}
+
+impl Display for ParamHelp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ParamKind::Required => write!(f, "{}: {}", self.name, self.type_name),
+            ParamKind::Optional => write!(f, "[{}: {}]", self.name, self.type_name),
+            ParamKind::Variadic => write!(f, "{}: {}...", self.name, self.type_name),
+        }
+    }
+}
+
+/// Describes an operator: its signature, what it does and how to use it
+///
+/// The setters return `&mut Self` so they can be chained right after
+/// [super::OpRegistry::register]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpHelp {
+    pub name: String,
+    pub params: Vec<ParamHelp>,
+    pub description: String,
+    pub examples: Vec<String>,
+    /// Filled in by [super::OpRegistry::alias]
+    pub aliases: Vec<String>,
+}
+
+impl OpHelp {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Parameters are named `arg1`, `arg2`, ... until renamed with [OpHelp::params]
+    pub fn from_signature(name: &str, signature: &Signature) -> Self {
+        Self {
+            params: signature
+                .params
+                .iter()
+                .enumerate()
+                .map(|(i, p)| ParamHelp {
+                    name: format!("arg{}", i + 1),
+                    type_name: p.type_name,
+                    kind: p.kind,
+                })
+                .collect(),
+            ..Self::new(name)
+        }
+    }
+
+    pub fn description(&mut self, description: &str) -> &mut Self {
+        self.description = description.to_owned();
+        self
+    }
+
+    /// Renames parameters in order
+    pub fn params<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) -> &mut Self {
+        self.params
+            .iter_mut()
+            .zip(names)
+            .for_each(|(p, name)| p.name = name.to_owned());
+        self
+    }
+
+    /// Appends a parameter, used for operators registered without a [Signature]
+    pub fn param(&mut self, name: &str, type_name: &'static str, kind: ParamKind) -> &mut Self {
+        self.params.push(ParamHelp {
+            name: name.to_owned(),
+            type_name,
+            kind,
+        });
+        self
+    }
+
+    pub fn example(&mut self, example: &str) -> &mut Self {
+        self.examples.push(example.to_owned());
+        self
+    }
+
+    /// e.g. `ROUND(x: Num, [digits: Num])`
+    pub fn signature(&self) -> String {
+        format!(
+            "{}({})",
+            self.name,
+            self.params
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    /// Checks if `filter` is contained in the name, an alias or the description,
+    /// ignoring case
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+        self.name.to_lowercase().contains(&filter)
+            || self
+                .aliases
+                .iter()
+                .any(|a| a.to_lowercase().contains(&filter))
+            || self.description.to_lowercase().contains(&filter)
+    }
+}
+
+/// Prints the full help of an operator
+impl Display for OpHelp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.signature())?;
+
+        if !self.aliases.is_empty() {
+            writeln!(f, "Aliases: {}", self.aliases.join(", "))?;
+        }
+
+        if !self.description.is_empty() {
+            writeln!(f, "\n{}", self.description)?;
+        }
+
+        if !self.examples.is_empty() {
+            writeln!(f, "\nExamples:")?;
+            self.examples
+                .iter()
+                .try_for_each(|e| writeln!(f, "    {}", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Levenshtein distance between two strings, ignoring case
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.to_lowercase().chars().collect::<Vec<_>>();
+    let b = b.to_lowercase().chars().collect::<Vec<_>>();
+
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
+/// Finds the closest name to `name` amongst `names`,
+/// names which are too different are not suggested
+pub fn closest_name<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    names
+        .map(|n| (edit_distance(name, n), n))
+        .filter(|(d, _)| *d <= max_distance)
+        // ties are broken by name in order to be deterministic
+        .min()
+        .map(|(_, n)| n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheets::operators::registry::IntoOperator;
+    use crate::types::num::Num;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("SUM", "SUM"), 0);
+        assert_eq!(edit_distance("SUM", "sum"), 0);
+        assert_eq!(edit_distance("SUMM", "SUM"), 1);
+        assert_eq!(edit_distance("MULTIPY", "MULTIPLY"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "AND"), 3);
+    }
+
+    #[test]
+    fn test_closest_name() {
+        let names = ["SUM", "MULTIPLY", "DIVIDE", "NOT", "AND", "OR"];
+
+        assert_eq!(closest_name("SUMM", names.into_iter()), Some("SUM"));
+        assert_eq!(closest_name("DIVDE", names.into_iter()), Some("DIVIDE"));
+        assert_eq!(closest_name("CONCATENATE", names.into_iter()), None);
+    }
+
+    #[test]
+    fn test_signature() {
+        let mut help = OpHelp::from_signature(
+            "JOIN",
+            &<fn(String, Option<Num>, Vec<String>) -> String as IntoOperator<_>>::signature(),
+        );
+        help.params(["sep", "limit", "parts"]);
+
+        assert_eq!(
+            help.signature(),
+            "JOIN(sep: String, [limit: Num], parts: String...)"
+        );
+    }
+}
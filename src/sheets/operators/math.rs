@@ -0,0 +1,247 @@
+//! Contains integer math operators,
+//! their results are promoted to big integers when they do not fit into an `i64`
+
+use std::borrow::Cow;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+
+use super::registry::OpRegistry;
+use crate::sheets::CellError;
+use crate::types::num::Num;
+
+const NON_NEGATIVE: CellError = CellError::InvalidValue(Cow::Borrowed("a non-negative integer"));
+
+/// The largest `n` of `FACT`, `COMBIN` and `PERMUT`, `FACT(10000)` has 35660 digits
+const MAX_FACTORIAL: u32 = 10_000;
+/// The largest arguments and results of `GCD` and `LCM`, larger than `FACT(10000)`
+const MAX_BITS: u64 = 1 << 17;
+
+// fractional part is truncated
+fn non_negative(n: &Num) -> Result<BigInt, CellError> {
+    n.to_big_int()
+        .filter(|b| !b.is_negative())
+        .ok_or(NON_NEGATIVE)
+}
+
+// products of larger numbers take too long to calculate
+fn factorial_arg(n: &Num) -> Result<BigInt, CellError> {
+    let n = non_negative(n)?;
+    match n > BigInt::from(MAX_FACTORIAL) {
+        true => Err(CellError::NumTooLarge),
+        false => Ok(n),
+    }
+}
+
+fn within_bits(b: BigInt) -> Result<BigInt, CellError> {
+    match b.bits() > MAX_BITS {
+        true => Err(CellError::NumTooLarge),
+        false => Ok(b),
+    }
+}
+
+// product of all integers in `from..=to`
+fn product(from: &BigInt, to: &BigInt) -> BigInt {
+    std::iter::successors(Some(from.clone()), |b| Some(b + 1u8))
+        .take_while(|b| b <= to)
+        .fold(BigInt::one(), |acc, b| acc * b)
+}
+
+fn integers(nums: Vec<Num>) -> Result<Vec<BigInt>, CellError> {
+    nums.iter()
+        .map(|n| non_negative(n).and_then(within_bits))
+        .collect()
+}
+
+/// Registers `FACT`, `COMBIN`, `PERMUT`, `GCD` and `LCM`
+pub fn register_math_ops(registry: &mut OpRegistry) {
+    registry
+        .register("FACT", |n: Num| {
+            Ok::<_, CellError>(Num::from_big(product(&BigInt::one(), &factorial_arg(&n)?)))
+        })
+        .params(["n"])
+        .description("Calculates the factorial of `n`, which can be at most 10000")
+        .example("=FACT(25)");
+
+    registry
+        .register("COMBIN", |n: Num, k: Num| {
+            let (n, k) = (factorial_arg(&n)?, non_negative(&k)?);
+            if k > n {
+                return Err(CellError::InvalidValue("`k` not greater than `n`".into()));
+            }
+
+            // n! / (k! * (n - k)!), reducing `k` to the smaller half first
+            let k = k.clone().min(&n - &k);
+            Ok(Num::from_big(
+                product(&(&n - &k + 1u8), &n) / product(&BigInt::one(), &k),
+            ))
+        })
+        .params(["n", "k"])
+        .description("Counts combinations of `k` items out of `n` items, `n` can be at most 10000")
+        .example("=COMBIN(60, 30)");
+
+    registry
+        .register("PERMUT", |n: Num, k: Num| {
+            let (n, k) = (factorial_arg(&n)?, non_negative(&k)?);
+            if k > n {
+                return Err(CellError::InvalidValue("`k` not greater than `n`".into()));
+            }
+
+            // n! / (n - k)!
+            Ok(Num::from_big(product(&(&n - &k + 1u8), &n)))
+        })
+        .params(["n", "k"])
+        .description("Counts permutations of `k` items out of `n` items, `n` can be at most 10000")
+        .example("=PERMUT(30, 20)");
+
+    registry
+        .register("GCD", |nums: Vec<Num>| {
+            Ok::<_, CellError>(Num::from_big(
+                integers(nums)?
+                    .into_iter()
+                    .fold(BigInt::zero(), |acc, b| acc.gcd(&b)),
+            ))
+        })
+        .params(["nums"])
+        .description("Calculates the greatest common divisor of integers")
+        .example("=GCD(24, 36, A1)");
+
+    registry
+        .register("LCM", |nums: Vec<Num>| {
+            Ok::<_, CellError>(Num::from_big(
+                integers(nums)?
+                    .into_iter()
+                    .try_fold(BigInt::one(), |acc, b| within_bits(acc.lcm(&b)))?,
+            ))
+        })
+        .params(["nums"])
+        .description("Calculates the least common multiple of integers")
+        .example("=LCM(4, 6, A1)");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::data::RawCellData;
+    use crate::sheets::tests::resolve_row;
+    use crate::sheets::{operators, EvalOptions, Expr};
+
+    fn resolve(formulas: &[&str]) -> Vec<Expr> {
+        let values = vec![vec![RawCellData::Int(-3), RawCellData::Float(5.9)]];
+        let ops = operators::get_default_op_map();
+        resolve_row(values, formulas, &ops, &EvalOptions::default())
+    }
+
+    fn big(s: &str) -> Expr {
+        Num::B(BigInt::from_str(s).unwrap()).into()
+    }
+
+    #[test]
+    fn factorials() {
+        assert_eq!(
+            resolve(&[
+                "=FACT(0)",
+                "=FACT(B1)",
+                "=FACT(20)",
+                "=FACT(25)",
+                "=FACT(A1)"
+            ]),
+            vec![
+                Num::I(1).into(),
+                Num::I(120).into(),
+                Num::I(2432902008176640000).into(),
+                big("15511210043330985984000000"),
+                CellError::FormError(vec![NON_NEGATIVE]).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn combinatorics() {
+        assert_eq!(
+            resolve(&[
+                "=COMBIN(5, 2)",
+                "=COMBIN(60, 30)",
+                "=COMBIN(200, 100)",
+                "=PERMUT(5, 2)",
+                "=PERMUT(30, 20)",
+                "=COMBIN(2, 5)",
+                "=DIVIDE(FACT(25), FACT(24))",
+            ]),
+            vec![
+                Num::I(10).into(),
+                Num::I(118264581564861424).into(),
+                big("90548514656103281165404177077484163874504589675413336841320"),
+                Num::I(20).into(),
+                big("73096577329197271449600000"),
+                CellError::FormError(vec![CellError::InvalidValue(
+                    "`k` not greater than `n`".into()
+                )])
+                .into(),
+                Num::I(25).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn divisors() {
+        assert_eq!(
+            resolve(&[
+                "=GCD(24, 36, 60)",
+                "=GCD(0, 7)",
+                "=LCM(4, 6, B1)",
+                "=LCM(FACT(25), 7)",
+                "=GCD(A1, 3)",
+            ]),
+            vec![
+                Num::I(12).into(),
+                Num::I(7).into(),
+                Num::I(60).into(),
+                big("15511210043330985984000000"),
+                CellError::FormError(vec![NON_NEGATIVE]).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn sum_overflows_into_big_int() {
+        assert_eq!(
+            resolve(&["=SUM(9223372036854775807, 1)", "=MULTIPLY(FACT(20), 21)"]),
+            vec![big("9223372036854775808"), big("51090942171709440000"),]
+        );
+    }
+
+    #[test]
+    fn rejects_too_large_arguments() {
+        // 40000 digits have more than `MAX_BITS` bits, 39000 digits don't
+        let gcd = format!("=GCD({}, 2)", "9".repeat(40_000));
+        let lcm = format!("=LCM({}, 1{})", "9".repeat(39_000), "0".repeat(999));
+        assert_eq!(
+            resolve(&[
+                "=FACT(1000000000000000000)",
+                "=FACT(10001)",
+                "=COMBIN(100000000, 2)",
+                "=PERMUT(1000000000000000000, 2)",
+                &gcd,
+                &lcm,
+            ]),
+            vec![CellError::NumTooLarge.into(); 6]
+        );
+        assert!(matches!(&resolve(&["=FACT(10000)"])[0], Expr::Value(v) if v.is::<Num>()));
+    }
+
+    #[test]
+    fn big_int_floats_overflow_into_errors() {
+        assert_eq!(
+            resolve(&[
+                "=SUM(FACT(1000), 0.5)",
+                "=MULTIPLY(FACT(200), 1.5)",
+                "=AVERAGE(FACT(1000), 1.5)",
+            ]),
+            vec![CellError::NotFinite.into(); 3]
+        );
+    }
+}
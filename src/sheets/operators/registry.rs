@@ -0,0 +1,660 @@
+//! Contains [OpRegistry] and the declarative operator registration API
+//!
+//! Operators can be registered from plain closures, e.g.
+//! `registry.register("CLAMP", |x: Num, lo: Num, hi: Num| ...)`,
+//! the arity and type checks are derived from the closure's signature:
+//! - a [FromArg] parameter (`Num`, `String`, `bool`, `BoxValue`) is required
+//! - an `Option<T>` parameter is optional
+//! - a `Vec<T>` parameter takes all of the remaining arguments (variadic),
+//!   arrays among them are flattened into their values
+//! - a [NonEmpty] parameter is variadic too, but skips empty cells
+//!
+//! Every registered operator also carries [OpHelp], which can be filled in
+//! by chaining setters after registration:
+//! `registry.register(..).params(["x", "lo", "hi"]).description("..")`
+//!
+//! Names of operators are case-insensitive and operators
+//! can be referred to by aliases, see [OpRegistry::alias]
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use derive_more::Deref;
+
+use super::help::{closest_name, OpHelp};
+use super::{flatten_arrays, Operator, MAX_ARGS};
+use crate::sheets::{CellError, Expr, OpInfo};
+use crate::types::{box_value::BoxValue, empty::Empty, num::Num, text::Text, value::Value};
+
+struct RegisteredOp {
+    op: Operator,
+    help: OpHelp,
+}
+
+/// Holds all operators which can be used in formulas
+#[derive(Default)]
+pub struct OpRegistry {
+    // keys are uppercase names
+    ops: HashMap<String, RegisteredOp>,
+    // uppercase alias -> uppercase name
+    aliases: HashMap<String, String>,
+}
+
+fn key(name: &str) -> String {
+    name.to_uppercase()
+}
+
+impl OpRegistry {
+    /// Creates a registry without any operators
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an operator whose arity and argument types
+    /// are derived from the signature of `f`
+    ///
+    /// Replaces an operator if one with the same name was already registered
+    pub fn register<Args, F: IntoOperator<Args>>(&mut self, name: &str, f: F) -> &mut OpHelp {
+        let help = OpHelp::from_signature(name, &F::signature());
+        self.insert(name, f.into_operator(), help)
+    }
+
+    /// Registers an already boxed operator,
+    /// which has to do it's own arity and type checking
+    ///
+    /// Parameters are not known for such operators,
+    /// they can be documented with [OpHelp::param]
+    pub fn register_raw(&mut self, name: &str, op: Operator) -> &mut OpHelp {
+        self.insert(name, op, OpHelp::new(name))
+    }
+
+    fn insert(&mut self, name: &str, op: Operator, help: OpHelp) -> &mut OpHelp {
+        let key = key(name);
+        self.ops.insert(key.clone(), RegisteredOp { op, help });
+        &mut self.ops.get_mut(&key).unwrap().help
+    }
+
+    /// Makes the operator `name` also available as `alias`
+    ///
+    /// # Panics
+    /// If there is no operator named `name`
+    pub fn alias(&mut self, alias: &str, name: &str) -> &mut Self {
+        let name = key(name);
+        let registered = self
+            .ops
+            .get_mut(&name)
+            .unwrap_or_else(|| panic!("can not alias a non-existent operator {}", name));
+
+        registered.help.aliases.push(alias.to_owned());
+        self.aliases.insert(key(alias), name);
+        self
+    }
+
+    // looks up by name first and only then by alias,
+    // so registering an operator shadows an alias with the same name
+    fn find(&self, name: &str) -> Option<&RegisteredOp> {
+        let key = key(name);
+        self.ops
+            .get(&key)
+            .or_else(|| self.aliases.get(&key).and_then(|n| self.ops.get(n)))
+    }
+
+    /// Looks up an operator by its name or alias, ignoring case
+    pub fn get(&self, name: &str) -> Option<&Operator> {
+        self.find(name).map(|r| &r.op)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    /// Returns uppercase names of all operators, aliases are not included
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.ops.keys().map(String::as_str)
+    }
+
+    pub fn help(&self, name: &str) -> Option<&OpHelp> {
+        self.find(name).map(|r| &r.help)
+    }
+
+    /// Returns help of all operators sorted by name
+    pub fn helps(&self) -> Vec<&OpHelp> {
+        let mut helps = self.ops.values().map(|r| &r.help).collect::<Vec<_>>();
+        helps.sort_by(|h1, h2| h1.name.cmp(&h2.name));
+        helps
+    }
+
+    /// Returns help of operators whose name or description contains `filter`
+    pub fn search(&self, filter: &str) -> Vec<&OpHelp> {
+        self.helps()
+            .into_iter()
+            .filter(|h| h.matches(filter))
+            .collect()
+    }
+
+    /// Suggests the name (or alias) of an existing operator closest to `name`
+    pub fn suggest(&self, name: &str) -> Option<&str> {
+        closest_name(name, self.names().chain(self.aliases.keys().map(String::as_str)))
+    }
+
+    /// Creates an error for a missing operator, suggesting the closest existing one
+    pub fn no_op_found(&self, name: &str) -> CellError {
+        CellError::NoOpFound(name.to_owned(), self.suggest(name).map(str::to_owned))
+    }
+}
+
+impl<const N: usize> From<[(&str, Operator); N]> for OpRegistry {
+    fn from(value: [(&str, Operator); N]) -> Self {
+        let mut registry = Self::new();
+        value.into_iter().for_each(|(name, op)| {
+            registry.register_raw(name, op);
+        });
+        registry
+    }
+}
+
+impl std::fmt::Debug for OpRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.ops.keys()).finish()
+    }
+}
+
+/// Implemented for types which a single argument can be converted into
+pub trait FromArg: Sized + Clone + std::fmt::Debug + 'static {
+    /// Used in [CellError::TypeMismatch]
+    const TYPE_NAME: &'static str;
+
+    fn from_arg(value: &BoxValue) -> Option<Self>;
+}
+
+/// [Empty] is coerced to `0`
+impl FromArg for Num {
+    const TYPE_NAME: &'static str = "Num";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        value
+            .downcast_ref::<Num>()
+            .cloned()
+            .or_else(|| value.downcast_ref::<Empty>().map(|_| Num::I(0)))
+    }
+}
+
+/// [Empty] is coerced to `""`
+impl FromArg for String {
+    const TYPE_NAME: &'static str = "String";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        value
+            .downcast_ref::<Text>()
+            .map(|s| s.to_string())
+            .or_else(|| value.downcast_ref::<Empty>().map(|_| String::new()))
+    }
+}
+
+impl FromArg for bool {
+    const TYPE_NAME: &'static str = "Boolean";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        value.downcast_ref::<bool>().copied()
+    }
+}
+
+/// Accepts a value of any type
+impl FromArg for BoxValue {
+    const TYPE_NAME: &'static str = "Any";
+
+    fn from_arg(value: &BoxValue) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    Required,
+    Optional,
+    Variadic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParamInfo {
+    pub kind: ParamKind,
+    pub type_name: &'static str,
+}
+
+/// Implemented for types which can be used as parameters of registered closures
+pub trait Param: Sized {
+    const INFO: ParamInfo;
+
+    /// Takes argument(s) starting at `*idx` and advances it,
+    /// type errors are pushed to `errs`
+    ///
+    /// Returns `None` if any of the taken arguments could not be converted
+    fn extract(args: &[Expr], idx: &mut usize, errs: &mut Vec<CellError>) -> Option<Self>;
+}
+
+// converts a single argument, errors within the argument itself
+// are not reported as they are already collected by `arg_errors`
+fn extract_one<T: FromArg>(arg: &Expr, idx: usize, errs: &mut Vec<CellError>) -> Option<T> {
+    match arg {
+        Expr::Value(v) => {
+            let converted = T::from_arg(v);
+            if converted.is_none() {
+                errs.push(CellError::ArgError(
+                    idx,
+                    Box::new(CellError::TypeMismatch(T::TYPE_NAME.into())),
+                ));
+            }
+            converted
+        }
+        Expr::Err(_) => None,
+        _ => {
+            errs.push(CellError::ArgError(
+                idx,
+                Box::new(CellError::TypeMismatch(T::TYPE_NAME.into())),
+            ));
+            None
+        }
+    }
+}
+
+impl<T: FromArg> Param for T {
+    const INFO: ParamInfo = ParamInfo {
+        kind: ParamKind::Required,
+        type_name: T::TYPE_NAME,
+    };
+
+    fn extract(args: &[Expr], idx: &mut usize, errs: &mut Vec<CellError>) -> Option<Self> {
+        let i = *idx;
+        *idx += 1;
+        extract_one(args.get(i)?, i, errs)
+    }
+}
+
+impl<T: FromArg> Param for Option<T> {
+    const INFO: ParamInfo = ParamInfo {
+        kind: ParamKind::Optional,
+        type_name: T::TYPE_NAME,
+    };
+
+    fn extract(args: &[Expr], idx: &mut usize, errs: &mut Vec<CellError>) -> Option<Self> {
+        let i = *idx;
+        *idx += 1;
+        match args.get(i) {
+            Some(arg) => extract_one(arg, i, errs).map(Some),
+            None => Some(None),
+        }
+    }
+}
+
+impl<T: FromArg> Param for Vec<T> {
+    const INFO: ParamInfo = ParamInfo {
+        kind: ParamKind::Variadic,
+        type_name: T::TYPE_NAME,
+    };
+
+    fn extract(args: &[Expr], idx: &mut usize, errs: &mut Vec<CellError>) -> Option<Self> {
+        let start = *idx;
+        *idx = args.len().max(start);
+        flatten_arrays(args)
+            .iter()
+            .filter(|(i, _)| *i >= start)
+            .map(|(i, arg)| extract_one(arg, *i, errs))
+            // not short-circuiting in order to collect all type errors
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// A variadic parameter which skips arguments holding [Empty],
+/// instead of coercing them
+#[derive(Debug, Clone, PartialEq, Deref)]
+pub struct NonEmpty<T>(pub Vec<T>);
+
+impl<T: FromArg> Param for NonEmpty<T> {
+    const INFO: ParamInfo = <Vec<T> as Param>::INFO;
+
+    fn extract(args: &[Expr], idx: &mut usize, errs: &mut Vec<CellError>) -> Option<Self> {
+        let start = *idx;
+        *idx = args.len().max(start);
+        flatten_arrays(args)
+            .iter()
+            .filter(|(i, _)| *i >= start)
+            .filter(|(_, arg)| {
+                !matches!(arg, Expr::Value(v) if v.downcast_ref::<Empty>().is_some())
+            })
+            .map(|(i, arg)| extract_one(arg, *i, errs))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect::<Option<_>>()
+            .map(NonEmpty)
+    }
+}
+
+/// Describes parameters of a registered operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<ParamInfo>,
+}
+
+impl Signature {
+    /// # Panics
+    /// If an optional parameter is followed by a required one
+    /// or if a variadic parameter is not the last one
+    pub fn new(params: Vec<ParamInfo>) -> Self {
+        params.windows(2).for_each(|w| {
+            assert!(
+                w[0].kind != ParamKind::Variadic,
+                "a variadic parameter must be the last one"
+            );
+            assert!(
+                !(w[0].kind == ParamKind::Optional && w[1].kind == ParamKind::Required),
+                "a required parameter can not follow an optional one"
+            );
+        });
+
+        Self { params }
+    }
+
+    /// Returns the range of argument count this signature accepts
+    pub fn arity(&self) -> RangeInclusive<usize> {
+        let required = self
+            .params
+            .iter()
+            .filter(|p| p.kind == ParamKind::Required)
+            .count();
+
+        let max = match self.params.last() {
+            Some(ParamInfo {
+                kind: ParamKind::Variadic,
+                ..
+            }) => MAX_ARGS,
+            _ => self.params.len(),
+        };
+
+        required..=max
+    }
+
+    /// Collects errors of arguments themselves and arity errors
+    pub fn check(&self, info: &OpInfo) -> Vec<CellError> {
+        let mut errs = arg_errors(info);
+
+        let arity = self.arity();
+        if !arity.contains(&info.args.len()) {
+            errs.push(CellError::InvalidArgCount(arity, info.args.len()));
+        }
+
+        errs
+    }
+}
+
+/// Wraps errors within arguments into [CellError::ArgError]
+pub fn arg_errors(info: &OpInfo) -> Vec<CellError> {
+    info.args
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.is_err())
+        .map(|(u, e)| CellError::ArgError(u, Box::new(e.unwrap_err_ref().clone())))
+        .collect()
+}
+
+/// Implemented for types which registered closures can return
+pub trait IntoOpResult {
+    fn into_op_result(self) -> Result<Expr, Vec<CellError>>;
+}
+
+impl<V: Value> IntoOpResult for V {
+    fn into_op_result(self) -> Result<Expr, Vec<CellError>> {
+        Ok(self.into())
+    }
+}
+
+impl IntoOpResult for String {
+    fn into_op_result(self) -> Result<Expr, Vec<CellError>> {
+        Ok(self.into())
+    }
+}
+
+impl IntoOpResult for BoxValue {
+    fn into_op_result(self) -> Result<Expr, Vec<CellError>> {
+        Ok(self.into())
+    }
+}
+
+impl IntoOpResult for Expr {
+    fn into_op_result(self) -> Result<Expr, Vec<CellError>> {
+        Ok(self)
+    }
+}
+
+impl<T: IntoOpResult> IntoOpResult for Result<T, CellError> {
+    fn into_op_result(self) -> Result<Expr, Vec<CellError>> {
+        self.map_err(|e| vec![e])?.into_op_result()
+    }
+}
+
+impl<T: IntoOpResult> IntoOpResult for Result<T, Vec<CellError>> {
+    fn into_op_result(self) -> Result<Expr, Vec<CellError>> {
+        self?.into_op_result()
+    }
+}
+
+/// Implemented for closures which can be registered with [OpRegistry::register]
+///
+/// `Args` is a tuple of the closure's parameter types,
+/// it only exists to distinguish implementations for different arities
+pub trait IntoOperator<Args> {
+    fn signature() -> Signature;
+
+    fn into_operator(self) -> Operator;
+}
+
+macro_rules! impl_into_operator {
+    ($($param:ident $arg:ident),*) => {
+        impl<F, R, $($param,)*> IntoOperator<($($param,)*)> for F
+        where
+            F: Fn($($param),*) -> R + Send + Sync + 'static,
+            R: IntoOpResult,
+            $($param: Param,)*
+        {
+            fn signature() -> Signature {
+                Signature::new(vec![$($param::INFO),*])
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn into_operator(self) -> Operator {
+                let signature = <Self as IntoOperator<($($param,)*)>>::signature();
+
+                Box::new(move |_, info: &mut OpInfo| {
+                    let mut errs = signature.check(info);
+
+                    let mut idx = 0;
+                    $(let $arg = $param::extract(&info.args, &mut idx, &mut errs);)*
+
+                    if !errs.is_empty() {
+                        return Err(errs);
+                    }
+
+                    // every `extract` returns `Some` if there are no errors
+                    self($($arg.unwrap()),*).into_op_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_operator!();
+impl_into_operator!(P1 a1);
+impl_into_operator!(P1 a1, P2 a2);
+impl_into_operator!(P1 a1, P2 a2, P3 a3);
+impl_into_operator!(P1 a1, P2 a2, P3 a3, P4 a4);
+impl_into_operator!(P1 a1, P2 a2, P3 a3, P4 a4, P5 a5);
+impl_into_operator!(P1 a1, P2 a2, P3 a3, P4 a4, P5 a5, P6 a6);
+impl_into_operator!(P1 a1, P2 a2, P3 a3, P4 a4, P5 a5, P6 a6, P7 a7);
+impl_into_operator!(P1 a1, P2 a2, P3 a3, P4 a4, P5 a5, P6 a6, P7 a7, P8 a8);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheets::tests::resolve_row;
+    use crate::sheets::EvalOptions;
+
+    fn resolve(registry: &OpRegistry, formula: &str) -> Expr {
+        resolve_row(vec![], &[formula], registry, &EvalOptions::default()).remove(0)
+    }
+
+    fn clamp_registry() -> OpRegistry {
+        let mut registry = OpRegistry::new();
+        registry
+            .register("CLAMP", |x: Num, lo: Num, hi: Num| {
+                if lo > hi {
+                    Err(CellError::TypeMismatch("lo <= hi".into()))
+                } else if x < lo {
+                    Ok(lo)
+                } else if x > hi {
+                    Ok(hi)
+                } else {
+                    Ok(x)
+                }
+            })
+            .params(["x", "lo", "hi"])
+            .description("Limits `x` to the `lo..=hi` range")
+            .example("=CLAMP(5, 1, 3)");
+        registry
+    }
+
+    #[test]
+    fn register_fixed_arity() {
+        let registry = clamp_registry();
+
+        assert_eq!(resolve(&registry, "=CLAMP(5, 1, 3)"), Num::I(3).into());
+        assert_eq!(resolve(&registry, "=CLAMP(2.5, 1, 3)"), Num::F(2.5).into());
+        assert_eq!(
+            resolve(&registry, "=CLAMP(2, 3, 1)"),
+            CellError::FormError(vec![CellError::TypeMismatch("lo <= hi".into())]).into()
+        );
+    }
+
+    #[test]
+    fn register_reports_arity_and_type_errors() {
+        let registry = clamp_registry();
+
+        assert_eq!(
+            resolve(&registry, "=CLAMP(5, 1)"),
+            CellError::FormError(vec![CellError::InvalidArgCount(3..=3, 2)]).into()
+        );
+        assert_eq!(
+            resolve(&registry, "=CLAMP(5, \"1\", A5)"),
+            CellError::FormError(vec![
+                CellError::ArgError(2, Box::new(CellError::InvalidReference((0, 4).into()))),
+                CellError::ArgError(1, Box::new(CellError::TypeMismatch("Num".into()))),
+            ])
+            .into()
+        );
+    }
+
+    #[test]
+    fn register_optional_and_variadic() {
+        let mut registry = OpRegistry::new();
+        registry.register("ROUND", |x: Num, digits: Option<Num>| {
+            let factor = 10f64.powi(digits.unwrap_or(Num::I(0)).trunc() as i32);
+            let x: f64 = x.into();
+            Num::F((x * factor).round() / factor)
+        });
+        registry.register("JOIN", |sep: String, parts: Vec<String>| parts.join(&sep));
+
+        assert_eq!(resolve(&registry, "=ROUND(2.345)"), Num::F(2.0).into());
+        assert_eq!(resolve(&registry, "=ROUND(2.345, 1)"), Num::F(2.3).into());
+        assert_eq!(
+            resolve(&registry, "=ROUND()"),
+            CellError::FormError(vec![CellError::InvalidArgCount(1..=2, 0)]).into()
+        );
+        assert_eq!(
+            resolve(&registry, "=JOIN(\"-\", \"a\", \"b\", \"c\")"),
+            "a-b-c".to_owned().into()
+        );
+        assert_eq!(resolve(&registry, "=JOIN(\"-\")"), "".to_owned().into());
+        assert_eq!(
+            resolve(&registry, "=JOIN(\"-\", \"a\", 1, true)"),
+            CellError::FormError(vec![
+                CellError::ArgError(2, Box::new(CellError::TypeMismatch("String".into()))),
+                CellError::ArgError(3, Box::new(CellError::TypeMismatch("String".into()))),
+            ])
+            .into()
+        );
+    }
+
+    #[test]
+    fn signature_arity() {
+        assert_eq!(
+            <fn(Num, Option<Num>) -> Num as IntoOperator<_>>::signature().arity(),
+            1..=2
+        );
+        assert_eq!(
+            <fn(String, Vec<String>) -> String as IntoOperator<_>>::signature().arity(),
+            1..=MAX_ARGS
+        );
+    }
+
+    #[test]
+    fn register_with_help() {
+        let registry = clamp_registry();
+        let help = registry.help("CLAMP").unwrap();
+
+        assert_eq!(help.signature(), "CLAMP(x: Num, lo: Num, hi: Num)");
+        assert_eq!(
+            help.to_string(),
+            "CLAMP(x: Num, lo: Num, hi: Num)\n\
+             \n\
+             Limits `x` to the `lo..=hi` range\n\
+             \n\
+             Examples:\n    \
+             =CLAMP(5, 1, 3)\n"
+        );
+        assert_eq!(registry.search("range").len(), 1);
+        assert_eq!(registry.search("CONCAT").len(), 0);
+    }
+
+    #[test]
+    fn no_op_found_suggests_closest() {
+        let registry = clamp_registry();
+
+        assert_eq!(
+            resolve(&registry, "=CLMAP(5, 1, 3)"),
+            CellError::NoOpFound("CLMAP".to_owned(), Some("CLAMP".to_owned())).into()
+        );
+        assert_eq!(
+            resolve(&registry, "=AVERAGE(5, 1, 3)"),
+            CellError::NoOpFound("AVERAGE".to_owned(), None).into()
+        );
+        assert_eq!(
+            registry.no_op_found("CLMAP").to_string(),
+            "#ERROR: Could not find an operation named CLMAP, did you mean CLAMP?"
+        );
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let mut registry = clamp_registry();
+        registry.alias("Limit", "clamp");
+
+        assert_eq!(resolve(&registry, "=clamp(5, 1, 3)"), Num::I(3).into());
+        assert_eq!(resolve(&registry, "=LIMIT(5, 1, 3)"), Num::I(3).into());
+        assert_eq!(resolve(&registry, "=limit(0, 1, 3)"), Num::I(1).into());
+        assert_eq!(registry.help("limit").unwrap().name, "CLAMP");
+        assert_eq!(registry.help("CLAMP").unwrap().aliases, vec!["Limit"]);
+        assert_eq!(registry.suggest("LIMT"), Some("LIMIT"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn alias_requires_existing_operator() {
+        OpRegistry::new().alias("MUL", "MULTIPLY");
+    }
+
+    #[test]
+    #[should_panic]
+    fn signature_rejects_variadic_before_required() {
+        OpRegistry::new().register("BAD", |_: Vec<Num>, x: Num| x);
+    }
+}
@@ -0,0 +1,323 @@
+//! Contains operators defined by [Rhai](https://rhai.rs) scripts,
+//! available with the `scripting` feature
+//!
+//! Every public function of a script is registered as an operator of the same name, e.g.
+//! `fn TAX(amount, rate) { if amount > 1000 { amount * rate } else { 0 } }`
+//!
+//! Arguments are converted from [BoxValue]s: numbers into `INT`s or `FLOAT`s,
+//! strings, booleans, blank cells into `()`, arrays into arrays of rows
+//! and any other value into its text. Returned values are converted back the same way,
+//! a flat array becomes a single row. A script signals an error with `throw`.
+//!
+//! Scripts run sandboxed: they can't import modules and each call
+//! is limited by [ScriptLimits]
+
+use std::path::Path;
+use std::sync::Arc;
+
+use rhai::{
+    module_resolvers::DummyModuleResolver, CallFnOptions, Dynamic, Engine, EvalAltResult, FnAccess,
+};
+use thiserror::Error;
+
+use super::registry::{arg_errors, OpRegistry};
+use super::ParamKind;
+use crate::sheets::{CellError, Expr};
+use crate::types::{array::Array, box_value::BoxValue, empty::Empty, num::Num, text::Text};
+
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("Could not read the script: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not compile the script: {0}")]
+    Compile(String),
+    #[error("Function {0} is already defined")]
+    AlreadyDefined(String),
+}
+
+/// Limits of a single call of a scripted operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptLimits {
+    /// Roughly the number of evaluated expressions and statements
+    pub max_operations: u64,
+    pub max_call_levels: usize,
+    /// Nesting of expressions, checked when a script is compiled
+    pub max_expr_depth: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 100_000,
+            max_call_levels: 32,
+            max_expr_depth: 64,
+            max_string_size: 10_000,
+            max_array_size: 10_000,
+        }
+    }
+}
+
+fn sandboxed_engine(limits: &ScriptLimits) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(limits.max_operations)
+        .set_max_call_levels(limits.max_call_levels)
+        .set_max_expr_depths(limits.max_expr_depth, limits.max_expr_depth)
+        .set_max_string_size(limits.max_string_size)
+        .set_max_array_size(limits.max_array_size)
+        .on_print(|_| {})
+        .on_debug(|_, _, _| {});
+    engine.disable_symbol("eval");
+    engine
+}
+
+fn to_dynamic(value: &BoxValue) -> Dynamic {
+    if let Some(num) = value.downcast_ref::<Num>() {
+        match num {
+            Num::I(i) => Dynamic::from_int(*i),
+            num => Dynamic::from_float(num.into()),
+        }
+    } else if let Some(s) = value.downcast_ref::<Text>() {
+        s.as_str().into()
+    } else if let Some(b) = value.downcast_ref::<bool>() {
+        Dynamic::from_bool(*b)
+    } else if value.downcast_ref::<Empty>().is_some() {
+        Dynamic::UNIT
+    } else if let Some(array) = value.downcast_ref::<Array>() {
+        let rows = array
+            .rows()
+            .iter()
+            .map(|row| row.iter().map(to_dynamic).collect::<rhai::Array>().into())
+            .collect::<rhai::Array>();
+        rows.into()
+    } else {
+        value.to_string().into()
+    }
+}
+
+fn from_dynamic(value: Dynamic) -> Result<BoxValue, CellError> {
+    let unsupported = || CellError::ScriptError(format!("Unsupported result {}", value));
+
+    if let Ok(i) = value.as_int() {
+        Ok(Num::I(i).into())
+    } else if let Ok(f) = value.as_float() {
+        Ok(Num::F(f).into())
+    } else if let Ok(b) = value.as_bool() {
+        Ok(b.into())
+    } else if value.is_unit() {
+        Ok(Empty.into())
+    } else if value.is_string() {
+        Ok(value.into_string().unwrap().into())
+    } else if value.is_array() {
+        let values = value.clone().into_array().unwrap();
+        let rows = if values.iter().all(Dynamic::is_array) {
+            values
+                .into_iter()
+                .map(|row| {
+                    row.into_array()
+                        .unwrap()
+                        .into_iter()
+                        .map(from_dynamic)
+                        .collect()
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![values
+                .into_iter()
+                .map(from_dynamic)
+                .collect::<Result<_, _>>()?]
+        };
+        Ok(Array::new(rows).ok_or_else(unsupported)?.into())
+    } else {
+        Err(unsupported())
+    }
+}
+
+fn into_cell_error(err: EvalAltResult) -> CellError {
+    match err {
+        EvalAltResult::ErrorRuntime(value, _) => CellError::ScriptError(value.to_string()),
+        err => CellError::ScriptError(err.to_string()),
+    }
+}
+
+/// Registers all public functions of a script as operators
+///
+/// Returns names of the registered operators
+pub fn register_script(
+    registry: &mut OpRegistry,
+    source: &str,
+    limits: &ScriptLimits,
+) -> Result<Vec<String>, ScriptError> {
+    let engine = Arc::new(sandboxed_engine(limits));
+    let ast = Arc::new(
+        engine
+            .compile(source)
+            .map_err(|e| ScriptError::Compile(e.to_string()))?,
+    );
+
+    let functions = ast
+        .iter_functions()
+        .filter(|f| f.access == FnAccess::Public)
+        .map(|f| {
+            let params = f.params.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            (f.name.to_owned(), params)
+        })
+        .collect::<Vec<_>>();
+
+    if let Some((name, _)) = functions.iter().find(|(name, _)| registry.contains(name)) {
+        return Err(ScriptError::AlreadyDefined(name.clone()));
+    }
+
+    for (name, params) in &functions {
+        let (engine, ast, fn_name, arity) =
+            (engine.clone(), ast.clone(), name.clone(), params.len());
+        let help = registry.register_raw(
+            name,
+            Box::new(move |_, info| {
+                if info.args.len() != arity {
+                    return Err(vec![CellError::InvalidArgCount(
+                        arity..=arity,
+                        info.args.len(),
+                    )]);
+                }
+
+                let errs = arg_errors(info);
+                if !errs.is_empty() {
+                    return Err(errs);
+                }
+
+                let args = info
+                    .args
+                    .iter()
+                    .map(|arg| to_dynamic(arg.unwrap_value_ref()))
+                    .collect::<Vec<_>>();
+                let result = engine
+                    .call_fn_with_options::<Dynamic>(
+                        CallFnOptions::new().eval_ast(false),
+                        &mut rhai::Scope::new(),
+                        &ast,
+                        &fn_name,
+                        args,
+                    )
+                    .map_err(|e| into_cell_error(*e))
+                    .and_then(from_dynamic);
+
+                result.map(Expr::from).map_err(|e| vec![e])
+            }),
+        );
+        for param in params {
+            help.param(param, "Any", ParamKind::Required);
+        }
+        help.description("Defined in a script");
+    }
+
+    Ok(functions.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Same as [register_script], but the script is read from the file at `path`
+pub fn register_script_file(
+    registry: &mut OpRegistry,
+    path: impl AsRef<Path>,
+    limits: &ScriptLimits,
+) -> Result<Vec<String>, ScriptError> {
+    register_script(registry, &std::fs::read_to_string(path)?, limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheets::tests::resolve_row;
+    use crate::sheets::{operators, EvalOptions};
+
+    const SCRIPT: &str = r#"
+        fn TAX(amount, rate) {
+            if amount > 1000 { amount * rate } else { 0 }
+        }
+
+        fn GREET(name) { "Hello, " + name }
+
+        fn ROW_SUMS(rows) { rows.map(|row| row.reduce(|acc, x| acc + x, 0)) }
+
+        fn FAIL() { throw "not today" }
+
+        fn SPIN() { loop {} }
+
+        private fn helper() { 1 }
+    "#;
+
+    fn resolve(ops: &OpRegistry, formulas: &[&str]) -> Vec<Expr> {
+        resolve_row(vec![], formulas, ops, &EvalOptions::default())
+    }
+
+    #[test]
+    fn scripted_operators() {
+        let mut ops = operators::get_default_op_map();
+        let names = register_script(&mut ops, SCRIPT, &ScriptLimits::default()).unwrap();
+        assert!(!names.contains(&"helper".to_owned()));
+
+        assert_eq!(
+            resolve(
+                &ops,
+                &[
+                    "=TAX(2000, 0.5)",
+                    "=tax(10, 0.5)",
+                    "=GREET(\"world\")",
+                    "=TAX(1)",
+                    "=FAIL()",
+                    "=ROW_SUMS({1,2;3,4})",
+                ]
+            ),
+            vec![
+                Num::F(1000.0).into(),
+                Num::I(0).into(),
+                "Hello, world".to_owned().into(),
+                CellError::FormError(vec![CellError::InvalidArgCount(2..=2, 1)]).into(),
+                CellError::FormError(vec![CellError::ScriptError("not today".to_owned())]).into(),
+                Num::I(3).into(),
+                Num::I(7).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let mut ops = operators::get_default_op_map();
+        let limits = ScriptLimits {
+            max_operations: 1000,
+            ..Default::default()
+        };
+        register_script(&mut ops, SCRIPT, &limits).unwrap();
+
+        let result = resolve(&ops, &["=SPIN()"]).remove(0);
+        assert!(matches!(
+            result,
+            Expr::Err(CellError::FormError(errs))
+                if matches!(&errs[..], [CellError::ScriptError(msg)] if msg.contains("operations"))
+        ));
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        let mut ops = operators::get_default_op_map();
+        let limits = ScriptLimits::default();
+
+        assert!(matches!(
+            register_script(&mut ops, "fn sum(x) { x }", &limits),
+            Err(ScriptError::AlreadyDefined(name)) if name == "sum"
+        ));
+        assert!(matches!(
+            register_script(&mut ops, "fn F(x) {", &limits),
+            Err(ScriptError::Compile(_))
+        ));
+
+        // modules can't be imported
+        register_script(&mut ops, "fn F() { import \"fs\" as fs; 1 }", &limits).unwrap();
+        assert!(matches!(
+            resolve(&ops, &["=F()"]).remove(0),
+            Expr::Err(CellError::FormError(errs)) if matches!(errs[0], CellError::ScriptError(_))
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+//! Contains options which change how a sheet is evaluated
+
+use rust_decimal::RoundingStrategy;
+
+use super::{Expr, Limits, Sheet};
+use crate::types::{array::Array, num::Num};
+
+/// Passed to [Sheet::resolve_refs_with],
+/// the default options are used by [Sheet::resolve_refs]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvalOptions {
+    /// If set, floats are evaluated as exact decimals
+    pub decimal: Option<DecimalMode>,
+    pub limits: Limits,
+    /// If set, independent cells are evaluated one after another
+    /// even with the `parallel` feature
+    pub sequential: bool,
+}
+
+/// Evaluates floats as exact decimals, which is what money calculations need,
+/// e.g. `=SUM(0.1, 0.2)` is `0.30` instead of `0.30000000000000004`
+///
+/// Intermediate results are exact (up to 28 decimal digits), once the sheet is evaluated
+/// all of it's non-integer numbers, constants as well as computed values,
+/// are rounded to `scale` digits with `rounding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalMode {
+    pub scale: u32,
+    pub rounding: RoundingStrategy,
+}
+
+impl Default for DecimalMode {
+    /// Two digits with banker's rounding
+    fn default() -> Self {
+        Self {
+            scale: 2,
+            rounding: RoundingStrategy::MidpointNearestEven,
+        }
+    }
+}
+
+// applies `f` on every number within an expression, including arguments of formulas
+fn map_nums(expr: &mut Expr, f: &impl Fn(Num) -> Num) {
+    match expr {
+        Expr::Value(v) => {
+            if let Some(n) = v.downcast_mut::<Num>() {
+                *n = f(n.clone());
+            } else if let Some(array) = v.downcast_mut::<Array>() {
+                array
+                    .values_mut()
+                    .filter_map(|v| v.downcast_mut::<Num>())
+                    .for_each(|n| *n = f(n.clone()));
+            }
+        }
+        Expr::Form(op_info) => op_info.args.iter_mut().for_each(|e| map_nums(e, f)),
+        Expr::Call(callee, args) => {
+            map_nums(callee, f);
+            args.iter_mut().for_each(|e| map_nums(e, f));
+        }
+        Expr::Ref(_) | Expr::SpillRef(_) | Expr::Name(_) | Expr::Err(_) => {}
+    }
+}
+
+impl DecimalMode {
+    /// Converts all float literals into decimals
+    pub fn to_decimals(&self, sheet: &mut Sheet) {
+        sheet.cells.values_mut().for_each(|e| {
+            map_nums(e, &|n| match n {
+                Num::F(_) => n.to_decimal().map(Num::D).unwrap_or(n),
+                _ => n,
+            })
+        });
+    }
+
+    /// Rounds all decimals to `scale` digits, including constants of the sheet
+    ///
+    /// Floats left after evaluation (e.g. from dividing integers)
+    /// are converted to decimals and rounded too, integers are left as they are
+    pub fn round(&self, sheet: &mut Sheet) {
+        sheet.cells.values_mut().for_each(|e| {
+            map_nums(e, &|n| match (&n, n.to_decimal()) {
+                (Num::F(_) | Num::D(_), Some(d)) => {
+                    let mut d = d.round_dp_with_strategy(self.scale, self.rounding);
+                    d.rescale(self.scale);
+                    Num::D(d)
+                }
+                _ => n,
+            })
+        });
+    }
+}
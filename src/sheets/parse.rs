@@ -7,13 +7,17 @@ use nom::{
         complete::{tag, take_while_m_n},
     },
     character::complete::digit1,
-    combinator::{map, not},
+    combinator::{all_consuming, map, map_opt, not, opt, recognize},
     error::VerboseError,
-    multi::many0,
-    sequence::{pair, tuple},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, tuple},
 };
 
-use super::{CellError, Expr, OpInfo, Position};
+use num_bigint::BigInt;
+
+use super::{CellError, Expr, Limits, OpInfo, Position};
+use crate::types::array::Array;
+use crate::types::date::{Date, DateTime, Duration};
 use crate::types::num::Num;
 
 type VerboseResult<I, O, E> = Result<(I, O), nom::Err<VerboseError<E>>>;
@@ -40,8 +44,12 @@ fn parse_float(i: &str) -> VerboseResult<&str, Num, &'_ str> {
 }
 
 fn parse_int(i: &str) -> VerboseResult<&str, Num, &'_ str> {
-    map(pair(digit1, not(tag("."))), |(s, _): (&str, _)| {
-        Num::I(s.parse().unwrap())
+    // integers which don't fit into an `i64` are big integers
+    map_opt(pair(digit1, not(tag("."))), |(s, _): (&str, _)| {
+        s.parse()
+            .map(Num::I)
+            .or_else(|_| s.parse::<BigInt>().map(Num::B))
+            .ok()
     })(i)
 }
 
@@ -57,18 +65,23 @@ fn parse_ref(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
 
     let numbers1 = map(digit1, |s: &str| s.parse::<usize>().unwrap());
 
-    map(tuple((letter, numbers1)), |(x, y)| {
+    // `A1#` references an array spilled from `A1`
+    map(tuple((letter, numbers1, opt(tag("#")))), |(x, y, spill)| {
         let pos = Position {
-            x: (x as u8 - b'A') as usize,
-            y: y,
+            x: (x - b'A') as usize,
+            y,
         };
         if pos.y == 0 {
             CellError::ParseError.into()
         } else {
-            Expr::Ref(Position {
+            let pos = Position {
                 y: pos.y - 1,
                 ..pos
-            })
+            };
+            match spill {
+                Some(_) => Expr::SpillRef(pos),
+                None => Expr::Ref(pos),
+            }
         }
     })(i)
 }
@@ -86,19 +99,74 @@ fn parse_bool(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
     })(i)
 }
 
+// array constants, e.g. `{1,2;3,4}`, rows are separated by `;` and values by `,`
+// all rows have to be of the same length
+fn parse_array(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
+    let literal = map(
+        tuple((
+            take_while(|c| c == ' '),
+            alt((parse_bool, parse_num, parse_str)),
+            take_while(|c| c == ' '),
+        )),
+        |(_, expr, _)| expr.unwrap_value(),
+    );
+
+    map_opt(
+        delimited(
+            tag("{"),
+            separated_list1(tag(";"), separated_list1(tag(","), literal)),
+            tag("}"),
+        ),
+        |rows| Array::new(rows).map(Expr::from),
+    )(i)
+}
+
+// function names are identifiers: a letter followed by letters, digits, dots or underscores
+fn parse_fn_name(i: &str) -> VerboseResult<&str, &str, &'_ str> {
+    recognize(pair(
+        take_while_m_n(1, 1, |c: char| c.is_ascii_alphabetic()),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_'),
+    ))(i)
+}
+
 /// TODO: this solution is recursive and thus has the ability to blow up the stack on some large data, maybe fix this?
 // TODO: whitespace
 fn parse_fn(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
-    let name = take_while(|c| c != '(');
+    let name = parse_fn_name;
 
-    let parse_all = alt((parse_bool, parse_num, parse_ref, parse_str, parse_fn));
+    let form = map(pair(name, parse_args), |(name, args)| {
+        Expr::Form(OpInfo {
+            name: name.into(),
+            args,
+            limits: Limits::default(),
+        })
+    });
+
+    // the result of a formula can be called, e.g. `LAMBDA(x, x)(1)`
+    map(pair(form, many0(parse_args)), |(form, calls)| {
+        calls
+            .into_iter()
+            .fold(form, |callee, args| Expr::Call(Box::new(callee), args))
+    })(i)
+}
+
+fn parse_args(i: &str) -> VerboseResult<&str, Vec<Expr>, &'_ str> {
+    let parse_all = alt((
+        parse_bool,
+        parse_num,
+        parse_ref,
+        parse_str,
+        parse_array,
+        parse_fn,
+        parse_name,
+    ));
 
     let list_elem = map(
         pair(parse_all, take_while(|c| c == ' ' || c == ',')),
         |(expr, _)| expr,
     );
 
-    let args = map(
+    map(
         tuple((
             pair(
                 tag("("), 
@@ -108,20 +176,71 @@ fn parse_fn(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
             tag(")"),
         )),
         |(_, exprs, _)| exprs,
-    );
+    )(i)
+}
 
-    map(pair(name, args), |(name, args)| {
-        Expr::Form(OpInfo {
-            name: name.to_owned(),
-            args,
-        })
+// names bound by `LET` and lambda parameters, they can't look like cell references
+fn parse_name(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
+    map(pair(parse_fn_name, not(tag("("))), |(name, _)| {
+        Expr::Name(name.to_owned())
     })(i)
 }
 
+// ISO-8601 dates, date-times and durations, the whole input has to match
+fn parse_date(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
+    Date::parse_iso(i)
+        .map(Expr::from)
+        .or_else(|| DateTime::parse_iso(i).map(Expr::from))
+        .or_else(|| Duration::parse_iso(i).map(Expr::from))
+        .map(|expr| ("", expr))
+        .ok_or(nom::Err::Error(VerboseError {
+            errors: vec![(i, nom::error::VerboseErrorKind::Context("date"))],
+        }))
+}
+
+/// Parses a definition of a function, e.g. `MARKUP(cost, price) = DIVIDE(price, cost)`,
+/// into its name, parameters and body
+pub fn parse_definition(i: &str) -> VerboseResult<&str, (String, Vec<String>, Expr), &'_ str> {
+    let spaces = |i| take_while(|c| c == ' ')(i);
+
+    // parameters can't look like cell references
+    let param = map_opt(parse_fn_name, |name: &str| match parse_ref(name) {
+        Ok(_) => None,
+        _ => Some(name.to_owned()),
+    });
+    let params = delimited(
+        tag("("),
+        separated_list0(tag(","), delimited(spaces, param, spaces)),
+        tag(")"),
+    );
+
+    let body = alt((
+        parse_bool,
+        parse_num,
+        parse_ref,
+        parse_str,
+        parse_array,
+        parse_fn,
+        parse_name,
+    ));
+
+    map(
+        all_consuming(tuple((
+            spaces,
+            parse_fn_name,
+            params,
+            delimited(spaces, tag("="), spaces),
+            body,
+            spaces,
+        ))),
+        |(_, name, params, _, body, _)| (name.to_owned(), params, body),
+    )(i)
+}
+
 pub fn parse_entry(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
     match i.starts_with('=') {
         false => map(
-            alt((parse_bool, |s: &str| {
+            alt((parse_bool, parse_date, |s: &str| {
                 Ok(("", Expr::Value(s.to_owned().into())))
             })),
             |expr| expr,
@@ -129,7 +248,14 @@ pub fn parse_entry(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
         true => map(
             pair(
                 tag("="),
-                alt::<_, _, _, _>((parse_bool, parse_num, parse_ref, parse_str, parse_fn)),
+                alt::<_, _, _, _>((
+                    parse_bool,
+                    parse_num,
+                    parse_ref,
+                    parse_str,
+                    parse_array,
+                    parse_fn,
+                )),
             ),
             |(_, expr)| expr,
         )(i),
@@ -139,11 +265,12 @@ pub fn parse_entry(i: &str) -> VerboseResult<&str, Expr, &'_ str> {
 #[cfg(test)]
 mod tests {
     use crate::sheets::{parse::*, *};
+    use crate::types::text::Text;
 
     impl Expr {
         fn as_value_unchecked(&self) -> &dyn Value {
             match self {
-                Expr::Value(v) => v.as_ref(),
+                Expr::Value(v) => v.as_value(),
                 _ => panic!("Not a Value"),
             }
         }
@@ -157,13 +284,25 @@ mod tests {
         assert_eq!(parsed.0, "");
 
         assert_eq!(
-            parsed.1.as_value_unchecked().downcast_ref::<String>(),
+            parsed.1.as_value_unchecked().downcast_ref::<Text>(),
             Expr::Value("lol".to_owned().into())
                 .as_value_unchecked()
                 .downcast_ref()
         );
     }
 
+    #[test]
+    fn big_int_literals() {
+        assert_eq!(
+            parse_entry("=99999999999999999999").unwrap().1,
+            Num::B("99999999999999999999".parse().unwrap()).into()
+        );
+        assert_eq!(
+            parse_num("9223372036854775807").unwrap().1,
+            Num::I(i64::MAX).into()
+        );
+    }
+
     #[test]
     fn test_num() {
         assert_eq!(("", Num::I(531).into()), parse_num("531").unwrap());
@@ -181,22 +320,24 @@ mod tests {
                 .expect("test with fn does not fail")
                 .1,
             Expr::Form(OpInfo {
-                name: "SUM".to_owned(),
+                name: "SUM".into(),
                 args: vec![
                     Expr::Ref(Position { x: 0, y: 0 }),
                     Expr::Value(Num::I(52).into())
-                ]
+                ],
+                limits: Limits::default(),
             })
         );
 
         assert_eq!(
             parse_entry("=SUM(A1,52)").unwrap().1,
             Expr::Form(OpInfo {
-                name: "SUM".to_owned(),
+                name: "SUM".into(),
                 args: vec![
                     Expr::Ref(Position { x: 0, y: 0 }),
                     Expr::Value(Num::I(52).into())
-                ]
+                ],
+                limits: Limits::default(),
             })
         );
     }
@@ -206,19 +347,138 @@ mod tests {
         assert_eq!(
             parse_entry("=SUM(A1,MUL(5, B2))").unwrap().1,
             Expr::Form(OpInfo {
-                name: "SUM".to_owned(),
+                name: "SUM".into(),
                 args: vec![
                     Expr::Ref(Position { x: 0, y: 0 }),
                     Expr::Form(OpInfo {
-                        name: "MUL".to_owned(),
+                        name: "MUL".into(),
                         args: vec![
                             Expr::Value(Num::I(5).into()),
                             Expr::Ref(Position { x: 1, y: 1 })
-                        ]
+                        ],
+                        limits: Limits::default(),
+                    })
+                ],
+                limits: Limits::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_fn_names() {
+        assert_eq!(parse_fn_name("SUM(").unwrap(), ("(", "SUM"));
+        assert_eq!(parse_fn_name("sum(").unwrap(), ("(", "sum"));
+        assert_eq!(parse_fn_name("LOG10(").unwrap(), ("(", "LOG10"));
+        assert_eq!(parse_fn_name("F.DIST_2(").unwrap(), ("(", "F.DIST_2"));
+
+        assert!(parse_fn_name("1SUM(").is_err());
+        assert!(parse_fn_name("_SUM(").is_err());
+        assert!(parse_fn("SU M(1)").is_err());
+        assert!(parse_fn("SUM (1)").is_err());
+        assert!(parse_fn("(1)").is_err());
+        assert_eq!(
+            Expr::from(RawCellData::String("=S-UM(1)".to_owned())),
+            Expr::Err(CellError::ParseError)
+        );
+    }
+
+    #[test]
+    fn parse_spill_refs() {
+        assert_eq!(
+            parse_entry("=SUM(A1#, B2)").unwrap().1,
+            Expr::Form(OpInfo {
+                name: "SUM".into(),
+                args: vec![
+                    Expr::SpillRef(Position { x: 0, y: 0 }),
+                    Expr::Ref(Position { x: 1, y: 1 }),
+                ],
+                limits: Limits::default(),
+            })
+        );
+        assert_eq!(
+            parse_entry("=C3#").unwrap().1,
+            Expr::SpillRef(Position { x: 2, y: 2 })
+        );
+    }
+
+    #[test]
+    fn parse_arrays() {
+        let nums = |rows: &[&[i64]]| -> Expr {
+            Array::new(
+                rows.iter()
+                    .map(|row| row.iter().map(|i| Num::I(*i).into()).collect())
+                    .collect(),
+            )
+            .unwrap()
+            .into()
+        };
+
+        assert_eq!(parse_entry("={1,2,3}").unwrap().1, nums(&[&[1, 2, 3]]));
+        assert_eq!(parse_entry("={1, 2; 3, 4}").unwrap().1, nums(&[&[1, 2], &[3, 4]]));
+        assert_eq!(
+            parse_entry("=SUM({1;2}, A1)").unwrap().1,
+            Expr::Form(OpInfo {
+                name: "SUM".into(),
+                args: vec![nums(&[&[1], &[2]]), Expr::Ref(Position { x: 0, y: 0 })],
+                limits: Limits::default(),
+            })
+        );
+        assert_eq!(
+            parse_entry("={\"a\",true,1.5}").unwrap().1,
+            Array::new(vec![vec![
+                "a".to_owned().into(),
+                true.into(),
+                Num::F(1.5).into()
+            ]])
+            .unwrap()
+            .into()
+        );
+
+        assert!(parse_array("{}").is_err());
+        assert!(parse_array("{1,2;3}").is_err());
+        assert!(parse_array("{A1}").is_err());
+        assert_eq!(
+            Expr::from(RawCellData::String("={1;2".to_owned())),
+            Expr::Err(CellError::ParseError)
+        );
+    }
+
+    #[test]
+    fn parse_names_and_calls() {
+        let name = |n: &str| Expr::Name(n.to_owned());
+
+        assert_eq!(
+            parse_entry("=LET(x, 2, SUM(x, total))").unwrap().1,
+            Expr::Form(OpInfo {
+                name: "LET".into(),
+                args: vec![
+                    name("x"),
+                    Num::I(2).into(),
+                    Expr::Form(OpInfo {
+                        name: "SUM".into(),
+                        args: vec![name("x"), name("total")],
+                        limits: Limits::default(),
                     })
-                ]
+                ],
+                limits: Limits::default(),
             })
         );
+        assert_eq!(
+            parse_entry("=LAMBDA(x, x)(1)(A1)").unwrap().1,
+            Expr::Call(
+                Box::new(Expr::Call(
+                    Box::new(Expr::Form(OpInfo {
+                        name: "LAMBDA".into(),
+                        args: vec![name("x"), name("x")],
+                        limits: Limits::default(),
+                    })),
+                    vec![Num::I(1).into()]
+                )),
+                vec![Expr::Ref(Position { x: 0, y: 0 })]
+            )
+        );
+        assert_eq!(parse_name("x)").unwrap().1, name("x"));
+        assert!(parse_name("SUM(1)").is_err());
     }
 
     #[test]
@@ -226,11 +486,12 @@ mod tests {
         assert_eq!(
             parse_entry("=CONCAT(\"H\", \"i\")").unwrap().1,
             Expr::Form(OpInfo {
-                name: "CONCAT".to_owned(),
+                name: "CONCAT".into(),
                 args: vec![
                     Expr::Value("H".to_owned().into()),
                     Expr::Value("i".to_owned().into()),
-                ]
+                ],
+                limits: Limits::default(),
             })
         );
     }
@@ -240,4 +501,15 @@ mod tests {
         let raw: RawCellData = RawCellData::String("=SUM(".to_owned());
         assert_eq!(Expr::from(raw), Expr::Err(CellError::ParseError))
     }
+
+    #[test]
+    fn parse_huge_durations_as_text() {
+        for text in [
+            "P200000000000D",
+            "PT9223372036854775807S",
+            "P99999999999999999W",
+        ] {
+            assert_eq!(parse_entry(text).unwrap().1, Expr::from(text.to_owned()));
+        }
+    }
 }
@@ -0,0 +1,142 @@
+//! Contains spilling of arrays into neighbouring cells
+//!
+//! A cell which evaluates to an [Array] is an anchor, the values of the array
+//! are written into the cells to the right and below of it. The anchor itself
+//! shows the top left value, the whole array can be referenced with `A1#`.
+//!
+//! Spilling fails with [CellError::Spill] if any of the target cells is not blank
+//! or is already taken by another anchor. Spilling past the bounding box
+//! of a sheet extends it.
+//!
+//! Spilled cells can be referenced before their anchor is evaluated,
+//! so if anything has spilled, the sheet is evaluated again with the spilled
+//! values filled in, until the spilled areas do not change anymore
+
+use std::collections::HashSet;
+
+use super::{CellError, Cells, Env, Expr, Position, Sheet};
+use crate::types::{array::Array, empty::Empty};
+
+// bounds re-evaluation when spills keep changing each other
+const MAX_PASSES: usize = 8;
+
+/// Arrays spilled during one evaluation pass,
+/// a failed spill holds the position of the cell which blocked it
+#[derive(Debug, Default, PartialEq)]
+struct Spills(Vec<(Position, Result<Array, Position>)>);
+
+impl Spills {
+    fn failed(&self, anchor: Position) -> Option<Position> {
+        self.0.iter().find_map(|(p, spill)| match spill {
+            Err(blocker) if *p == anchor => Some(*blocker),
+            _ => None,
+        })
+    }
+
+    // a failed anchor is not evaluated again, otherwise
+    // cells which reference it would have to be evaluated again too
+    fn fill(&self, sheet: &mut Sheet) {
+        for (anchor, spill) in &self.0 {
+            match spill {
+                Ok(array) => {
+                    for (x, y, value) in array.iter().skip(1) {
+                        sheet.set_unchecked(offset(*anchor, x, y), value.clone().into());
+                    }
+                }
+                Err(blocker) => sheet.set_unchecked(*anchor, CellError::Spill(*blocker).into()),
+            }
+        }
+    }
+}
+
+fn offset(pos: Position, x: usize, y: usize) -> Position {
+    (pos.x + x, pos.y + y).into()
+}
+
+fn is_blank(cells: &Cells, pos: Position) -> bool {
+    match cells.get(pos) {
+        Some(Expr::Value(v)) => v.downcast_ref::<Empty>().is_some(),
+        Some(_) => false,
+        None => true,
+    }
+}
+
+impl Sheet {
+    /// Evaluates all cells and spills arrays
+    pub(super) fn resolve_spilling(&mut self, env: &mut Env) {
+        let original = self.cells.clone();
+
+        let mut filled = Spills::default();
+        let mut spills = self.resolve_pass(env, &original, &filled);
+        for _ in 1..MAX_PASSES {
+            if spills == filled {
+                break;
+            }
+
+            self.cells = original.clone();
+            filled = spills;
+            spills = self.resolve_pass(env, &original, &filled);
+        }
+
+        for (anchor, spill) in spills.0 {
+            if let Ok(array) = spill {
+                self.set_unchecked(anchor, array.top_left().clone().into());
+            }
+        }
+    }
+
+    // `original` contains cells before evaluation, `filled` are spills of the previous pass
+    fn resolve_pass(&mut self, env: &mut Env, original: &Cells, filled: &Spills) -> Spills {
+        filled.fill(self);
+        env.budget.start_pass();
+        self.resolve_levels(env);
+
+        let mut spills = Spills::default();
+        let mut taken = HashSet::new();
+        // blank cells of sparse sheets are not stored, those can't spill anyway
+        for (pos, _) in original.iter() {
+            if let Some(blocker) = filled.failed(pos) {
+                spills.0.push((pos, Err(blocker)));
+                continue;
+            }
+
+            let array = match self.resolve_on_pos(pos, pos, env) {
+                Some(Expr::Value(v)) => v.downcast_ref::<Array>().cloned(),
+                _ => None,
+            };
+            self.write_resolved(env);
+            if let Some(array) = array {
+                spills.0.push((pos, self.spill(pos, array, original, &mut taken)));
+            }
+        }
+
+        spills
+    }
+
+    fn spill(
+        &mut self,
+        anchor: Position,
+        array: Array,
+        original: &Cells,
+        taken: &mut HashSet<Position>,
+    ) -> Result<Array, Position> {
+        let blocker = array
+            .iter()
+            .skip(1)
+            .map(|(x, y, _)| offset(anchor, x, y))
+            .find(|pos| taken.contains(pos) || !is_blank(original, *pos));
+
+        if let Some(blocker) = blocker {
+            self.set_unchecked(anchor, CellError::Spill(blocker).into());
+            return Err(blocker);
+        }
+
+        for (x, y, value) in array.iter().skip(1) {
+            let pos = offset(anchor, x, y);
+            taken.insert(pos);
+            self.set_unchecked(pos, value.clone().into());
+        }
+
+        Ok(array)
+    }
+}
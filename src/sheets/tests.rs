@@ -2,9 +2,45 @@
 
 use super::*;
 
+/// Evaluates `formulas` placed in the row below `rows`, returns the evaluated row of them,
+/// tests of operators and limits share it
+pub(super) fn resolve_row(
+    rows: Vec<Vec<RawCellData>>,
+    formulas: &[&str],
+    ops: &OpRegistry,
+    options: &EvalOptions,
+) -> Vec<Expr> {
+    resolve_rows(rows, formulas, ops, options).remove(0)
+}
+
+/// Like [resolve_row], but also returns the rows below `formulas`, where their results spill
+pub(super) fn resolve_rows(
+    rows: Vec<Vec<RawCellData>>,
+    formulas: &[&str],
+    ops: &OpRegistry,
+    options: &EvalOptions,
+) -> Vec<Vec<Expr>> {
+    let y = rows.len();
+    let mut data = rows;
+    data.push(
+        formulas
+            .iter()
+            .map(|f| RawCellData::String(f.to_string()))
+            .collect(),
+    );
+    let sheet: Sheet = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: data.into(),
+    }
+    .into();
+
+    let sheet = sheet.resolve_refs_with(ops, options);
+    sheet.cells.into_rows().split_off(y)
+}
+
 #[test]
 fn parse_then_resolve_with_refs() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -18,11 +54,12 @@ fn parse_then_resolve_with_refs() {
                 RawCellData::String("=A2".to_owned()),
             ],
             vec![RawCellData::Int(6), RawCellData::String("=C2".to_owned())],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -38,45 +75,48 @@ fn parse_then_resolve_with_refs() {
                     Num::I(6).into(),
                     Num::I(6).into(),
                 ],
-                vec![Num::I(6).into(), CellError::InvalidReference((2, 1).into()).into(),]
+                // C2 is a missing trailing cell inside the bounding box
+                vec![Num::I(6).into(), Empty.into(),]
             ]
+            .into()
         }
     )
 }
 
 #[test]
 fn parse_then_resolve_ops_with_consts() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
-        data: vec![vec![RawCellData::String("=SUM(1, 2)".to_owned())]],
+        data: vec![vec![RawCellData::String("=SUM(1, 2)".to_owned())]].into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
         Sheet {
             id: "sheet-test".to_owned(),
-            cells: vec![vec![Num::I(3).into()]]
+            cells: vec![vec![Num::I(3).into()]].into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_forms_with_refs() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
             vec![RawCellData::String("=SUM(A2, B2)".to_owned())],
             vec![RawCellData::Int(6), RawCellData::String("=1".to_owned())],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -86,13 +126,14 @@ fn parse_then_resolve_forms_with_refs() {
                 vec![Num::I(7).into()],
                 vec![Num::I(6).into(), Num::I(1).into(),]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_forms_with_nested_forms_with_refs() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -102,15 +143,16 @@ fn parse_then_resolve_forms_with_nested_forms_with_refs() {
             ],
             vec![RawCellData::Int(6), RawCellData::String("=1".to_owned())],
             vec![
-                RawCellData::String("=6.1".to_owned()).into(),
+                RawCellData::String("=6.1".to_owned()),
                 RawCellData::String("=5".to_owned()),
             ],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
 
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -121,20 +163,21 @@ fn parse_then_resolve_forms_with_nested_forms_with_refs() {
                 vec![Num::I(6).into(), Num::I(1).into(),],
                 vec![Num::F(6.1).into(), Num::I(5).into(),]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_fn_with_errs() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
-        data: vec![vec![RawCellData::String("=SUM(1, A2, \"Hi\")".to_owned())]],
+        data: vec![vec![RawCellData::String("=SUM(1, A2, \"Hi\")".to_owned())]].into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -142,16 +185,17 @@ fn parse_then_resolve_fn_with_errs() {
             id: "sheet-test".to_owned(),
             cells: vec![vec![CellError::FormError(vec![
                 CellError::ArgError(1, Box::new(CellError::InvalidReference((0, 1).into()))),
-                CellError::ArgError(2, Box::new(CellError::TypeMismatch("Num"))),
+                CellError::ArgError(2, Box::new(CellError::TypeMismatch("Num".into()))),
             ])
             .into()]]
+            .into()
         }
     )
 }
 
 #[test]
 fn parse_then_resolve_forms_with_mul() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -162,11 +206,12 @@ fn parse_then_resolve_forms_with_mul() {
             vec![RawCellData::String(
                 "=SUM(MULTIPLY(A1, B1), 2.5)".to_owned(),
             )],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -176,13 +221,14 @@ fn parse_then_resolve_forms_with_mul() {
                 vec![Num::I(4).into(), Num::I(8).into(),],
                 vec![Num::F(34.5).into()]
             ]
+            .into()
         }
     )
 }
 
 #[test]
 fn parse_then_resolve_divide() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -193,11 +239,12 @@ fn parse_then_resolve_divide() {
             vec![RawCellData::String(
                 "=DIVIDE(MULTIPLY(A1, B1), 5)".to_owned(),
             )],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -207,6 +254,7 @@ fn parse_then_resolve_divide() {
                 vec![Num::I(4).into(), Num::I(8).into(),],
                 vec![Num::F(32.0 / 5.0).into()]
             ]
+            .into()
         }
     );
 
@@ -220,11 +268,12 @@ fn parse_then_resolve_divide() {
             vec![RawCellData::String(
                 "=DIVIDE(MULTIPLY(A1, B1), 0)".to_owned(),
             )],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -234,13 +283,14 @@ fn parse_then_resolve_divide() {
                 vec![Num::I(4).into(), Num::I(8).into(),],
                 vec![CellError::FormError(vec![CellError::DivByZero]).into()]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_with_concat() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -255,11 +305,12 @@ fn parse_then_resolve_with_concat() {
                 RawCellData::String("=CONCAT(\"!\")".to_owned()),
                 RawCellData::String("=CONCAT(\"Hello, \", \"World!\")".to_owned()),
             ],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -278,13 +329,14 @@ fn parse_then_resolve_with_concat() {
                     Expr::Value("Hello, World!".to_owned().into()),
                 ]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_not() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -297,11 +349,12 @@ fn parse_then_resolve_not() {
                 RawCellData::String("false".to_owned()),
                 RawCellData::String("=true".to_owned()),
             ],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -315,13 +368,14 @@ fn parse_then_resolve_not() {
                     Expr::Value(true.into()),
                 ]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_gt() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -330,11 +384,12 @@ fn parse_then_resolve_gt() {
                 RawCellData::String("=GT(A1, B1)".to_owned()),
                 RawCellData::String("=GT(B1, 4.9)".to_owned()),
             ],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -347,13 +402,14 @@ fn parse_then_resolve_gt() {
                 ],
                 vec![Expr::Value(false.into()), Expr::Value(true.into()),]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_eq() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -362,11 +418,12 @@ fn parse_then_resolve_eq() {
                 RawCellData::String("=EQ(A1, B1)".to_owned()),
                 RawCellData::String("=EQ(B1, \"String\")".to_owned()),
             ],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -382,13 +439,14 @@ fn parse_then_resolve_eq() {
                     Expr::Err(CellError::FormError(vec![CellError::BinaryTypeMismatch])),
                 ]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_and_not() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -401,11 +459,12 @@ fn parse_then_resolve_and_not() {
                 RawCellData::String("=AND(true, A2)".to_owned()),
                 RawCellData::String("=OR(false, A3)".to_owned()),
             ]
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -425,13 +484,14 @@ fn parse_then_resolve_and_not() {
                     Expr::Value(true.into()),
                 ]
             ]
+            .into()
         }
     );
 }
 
 #[test]
 fn parse_then_resolve_if() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -441,11 +501,12 @@ fn parse_then_resolve_if() {
                 RawCellData::String("=B1".to_owned()),
                 
             ],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -458,6 +519,7 @@ fn parse_then_resolve_if() {
                     Expr::Err(CellError::CircularRef),
                 ],
             ]
+            .into()
         }
     );
 }
@@ -465,7 +527,7 @@ fn parse_then_resolve_if() {
 
 #[test]
 fn parse_then_resolve_circular_ref() {
-    let mut ops = operators::get_default_op_map();
+    let ops = operators::get_default_op_map();
     let raw = RawSheet {
         id: "sheet-test".to_owned(),
         data: vec![
@@ -474,11 +536,12 @@ fn parse_then_resolve_circular_ref() {
                 RawCellData::String("=IF(EQ(A1, B1), \"Equal\", \"Not equal\")".to_owned()),
                 RawCellData::String("=EQ(A2, \"String\")".to_owned()),
             ],
-        ],
+        ]
+        .into(),
     };
 
     let sheet: Sheet = raw.into();
-    let sheet = sheet.resolve_refs(&mut ops);
+    let sheet = sheet.resolve_refs(&ops);
 
     assert_eq!(
         sheet,
@@ -494,6 +557,533 @@ fn parse_then_resolve_circular_ref() {
                     Expr::Value(false.into()),
                 ],
             ]
+            .into()
+        }
+    );
+}
+
+#[test]
+fn parse_then_resolve_case_insensitive_and_aliases() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![vec![
+            RawCellData::String("=sum(1, 2)".to_owned()),
+            RawCellData::String("=Sum(A1, MUL(5, A1))".to_owned()),
+            RawCellData::String("=product(2, 3)".to_owned()),
+            RawCellData::String("=DIV(6, 3)".to_owned()),
+            RawCellData::String("=SUMM(1, 2)".to_owned()),
+        ]]
+        .into(),
+    };
+
+    let sheet: Sheet = raw.into();
+    let sheet = sheet.resolve_refs(&ops);
+
+    assert_eq!(
+        sheet,
+        Sheet {
+            id: "sheet-test".to_owned(),
+            cells: vec![vec![
+                Num::I(3).into(),
+                Num::I(18).into(),
+                Num::I(6).into(),
+                Num::I(2).into(),
+                CellError::NoOpFound("SUMM".to_owned(), Some("SUM".to_owned())).into(),
+            ]]
+            .into()
+        }
+    );
+}
+
+#[test]
+fn parse_then_resolve_empty() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![
+            vec![
+                RawCellData::Int(4),
+                RawCellData::Empty,
+                RawCellData::Int(2),
+                RawCellData::String("".to_owned()),
+            ],
+            vec![
+                RawCellData::String("=SUM(A1, B1, C1)".to_owned()),
+                RawCellData::String("=AVERAGE(A1, B1, C1)".to_owned()),
+                RawCellData::String("=CONCAT(\"a\", B1, D1, \"b\")".to_owned()),
+                RawCellData::String("=B1".to_owned()),
+                RawCellData::String("=EQ(B1, D1)".to_owned()),
+            ],
+            vec![
+                RawCellData::String("=AVERAGE(B1, D2)".to_owned()),
+                RawCellData::String("=E1".to_owned()),
+                RawCellData::String("=A4".to_owned()),
+            ],
+        ]
+        .into(),
+    };
+
+    let sheet: Sheet = raw.into();
+    let sheet = sheet.resolve_refs(&ops);
+
+    assert_eq!(
+        sheet,
+        Sheet {
+            id: "sheet-test".to_owned(),
+            cells: vec![
+                vec![
+                    Num::I(4).into(),
+                    Empty.into(),
+                    Num::I(2).into(),
+                    "".to_owned().into(),
+                ],
+                vec![
+                    Num::I(6).into(),
+                    Num::I(3).into(),
+                    "ab".to_owned().into(),
+                    Empty.into(),
+                    CellError::FormError(vec![CellError::BinaryTypeMismatch]).into(),
+                ],
+                vec![
+                    CellError::FormError(vec![CellError::DivByZero]).into(),
+                    Empty.into(),
+                    CellError::InvalidReference((0, 3).into()).into(),
+                ],
+            ]
+            .into()
         }
     );
-}
\ No newline at end of file
+
+    let serialized: SerdeValue = sheet.into();
+    assert_eq!(serialized["data"][0][1], SerdeValue::Null);
+    assert_eq!(serialized["data"][0][3], SerdeValue::String("".to_owned()));
+}
+
+#[test]
+fn parse_then_resolve_decimals() {
+    let ops = operators::get_default_op_map();
+    let raw = || RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![vec![
+            RawCellData::Float(0.1),
+            RawCellData::String("=SUM(A1, 0.2)".to_owned()),
+            RawCellData::String("=DIVIDE(10, 3)".to_owned()),
+            RawCellData::String("=DIVIDE(0.125, 1)".to_owned()),
+            RawCellData::String("=EQ(B1, 0.3)".to_owned()),
+            // constants are rounded as well, integers are left as they are
+            RawCellData::Float(0.125),
+            RawCellData::Int(2),
+        ]]
+        .into(),
+    };
+
+    let sheet = Sheet::from(raw()).resolve_refs(&ops);
+    assert_eq!(sheet.get((1, 0)).unwrap().clone(), Num::F(0.1 + 0.2).into());
+
+    let options = EvalOptions {
+        decimal: Some(DecimalMode::default()),
+        ..Default::default()
+    };
+    let sheet = Sheet::from(raw()).resolve_refs_with(&ops, &options);
+    let serialized: SerdeValue = sheet.into();
+
+    assert_eq!(
+        serialized["data"][0],
+        serde_json::json!(["0.10", "0.30", "3.33", "0.12", true, "0.12", 2])
+    );
+
+    let options = EvalOptions {
+        decimal: Some(DecimalMode {
+            scale: 1,
+            rounding: rust_decimal::RoundingStrategy::ToZero,
+        }),
+        ..Default::default()
+    };
+    let sheet = Sheet::from(raw()).resolve_refs_with(&ops, &options);
+    let serialized: SerdeValue = sheet.into();
+
+    assert_eq!(
+        serialized["data"][0],
+        serde_json::json!(["0.1", "0.3", "3.3", "0.1", true, "0.1", 2])
+    );
+}
+
+#[test]
+fn parse_then_resolve_spills() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![
+            vec![
+                // references a spilled cell before it's anchor is evaluated
+                RawCellData::String("=C2".to_owned()),
+                RawCellData::String("=SEQUENCE(2, 2)".to_owned()),
+                RawCellData::Empty,
+                RawCellData::String("=TRANSPOSE(B1#)".to_owned()),
+            ],
+            vec![RawCellData::String("=A3".to_owned())],
+            vec![
+                RawCellData::String("=SEQUENCE(1, 2)".to_owned()),
+                RawCellData::String("x".to_owned()),
+            ],
+            vec![RawCellData::String("=UNIQUE(B3#)".to_owned())],
+        ]
+        .into(),
+    };
+
+    let sheet: Sheet = raw.into();
+    let sheet = sheet.resolve_refs(&ops);
+
+    assert_eq!(
+        sheet,
+        Sheet {
+            id: "sheet-test".to_owned(),
+            cells: vec![
+                vec![
+                    Num::I(4).into(),
+                    Num::I(1).into(),
+                    Num::I(2).into(),
+                    Num::I(1).into(),
+                    Num::I(3).into(),
+                ],
+                vec![
+                    CellError::RefError(Box::new(CellError::Spill((1, 2).into())), (0, 2).into())
+                        .into(),
+                    Num::I(3).into(),
+                    Num::I(4).into(),
+                    Num::I(2).into(),
+                    Num::I(4).into(),
+                ],
+                vec![
+                    CellError::Spill((1, 2).into()).into(),
+                    "x".to_owned().into(),
+                ],
+                vec![CellError::FormError(vec![CellError::ArgError(
+                    0,
+                    Box::new(CellError::NoSpill((1, 2).into()))
+                )])
+                .into()],
+            ]
+            .into()
+        }
+    );
+    assert_eq!(
+        CellError::Spill((1, 2).into()).to_string(),
+        "#SPILL!: The array can not spill over non-blank cell B3"
+    );
+    assert_eq!(
+        CellError::NoSpill((1, 2).into()).to_string(),
+        "#ERROR: Cell B3 does not contain a spilled array"
+    );
+}
+
+#[test]
+fn parse_then_resolve_aggregates_of_arrays() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![
+            vec![
+                RawCellData::String("=SUM({1,2;3,4}, 5)".to_owned()),
+                RawCellData::String("=AND({true,false})".to_owned()),
+                RawCellData::String("=OR(A2#)".to_owned()),
+                RawCellData::String("=CONCAT({\"a\";\"b\"}, \"c\")".to_owned()),
+                RawCellData::String("=AVERAGE(SEQUENCE(4))".to_owned()),
+                RawCellData::String("=SUM({1,\"a\"}, true)".to_owned()),
+            ],
+            vec![RawCellData::String("={false,true}".to_owned())],
+        ]
+        .into(),
+    };
+
+    let sheet: Sheet = raw.into();
+    let sheet = sheet.resolve_refs(&ops);
+    let rows = sheet.cells.into_rows();
+
+    assert_eq!(
+        rows[0],
+        vec![
+            Num::I(15).into(),
+            false.into(),
+            true.into(),
+            "abc".to_owned().into(),
+            Num::F(2.5).into(),
+            CellError::FormError(vec![
+                CellError::ArgError(0, Box::new(CellError::TypeMismatch("Num".into()))),
+                CellError::ArgError(1, Box::new(CellError::TypeMismatch("Num".into()))),
+            ])
+            .into(),
+        ]
+    );
+}
+
+#[test]
+fn parse_then_resolve_let_and_lambdas() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![
+            vec![
+                RawCellData::String("=LET(x, 2, y, SUM(x, 1), MULTIPLY(x, y))".to_owned()),
+                RawCellData::String("=LAMBDA(x, SUM(x, 1))(2)".to_owned()),
+                RawCellData::String("=LET(f, LAMBDA(x, MULTIPLY(x, x)), f(3))".to_owned()),
+                RawCellData::String(
+                    "=LET(n, 10, f, LAMBDA(x, SUM(x, n)), LET(n, 0, f(1)))".to_owned(),
+                ),
+                RawCellData::String("=LET(x, 1, LET(x, 2, x))".to_owned()),
+            ],
+            vec![
+                RawCellData::String(
+                    "=REDUCE(0, {1,2;3,4}, LAMBDA(acc, x, SUM(acc, x)))".to_owned(),
+                ),
+                RawCellData::String("=LAMBDA(x, x)(1, 2)".to_owned()),
+                RawCellData::String("=SUM(y, 1)".to_owned()),
+                RawCellData::String("=LAMBDA(x, x)".to_owned()),
+                RawCellData::String("=LET(x, 1, x)(1)".to_owned()),
+            ],
+            vec![RawCellData::String(
+                "=MAP({1,2;3,4}, LAMBDA(x, MULTIPLY(x, 10)))".to_owned(),
+            )],
+            vec![],
+            vec![RawCellData::String(
+                "=SCAN(0, {1,2,3}, LAMBDA(acc, x, SUM(acc, x)))".to_owned(),
+            )],
+            vec![RawCellData::String(
+                "=BYROW({1,2;3,4}, LAMBDA(row, SUM(row)))".to_owned(),
+            )],
+            vec![],
+            vec![RawCellData::String(
+                "=BYCOL({1,2;3,4}, LAMBDA(col, SUM(col)))".to_owned(),
+            )],
+        ]
+        .into(),
+    };
+
+    let sheet: Sheet = raw.into();
+    let sheet = sheet.resolve_refs(&ops);
+    let rows = sheet.cells.into_rows();
+
+    assert_eq!(
+        rows[0],
+        vec![
+            Num::I(6).into(),
+            Num::I(3).into(),
+            Num::I(9).into(),
+            Num::I(11).into(),
+            Num::I(2).into(),
+        ]
+    );
+    assert_eq!(
+        rows[1],
+        vec![
+            Num::I(10).into(),
+            CellError::FormError(vec![CellError::InvalidArgCount(1..=1, 2)]).into(),
+            CellError::FormError(vec![CellError::ArgError(
+                0,
+                Box::new(CellError::UnknownName("y".to_owned()))
+            )])
+            .into(),
+            CellError::UncalledLambda.into(),
+            CellError::FormError(vec![CellError::TypeMismatch("Lambda".into())]).into(),
+        ]
+    );
+    assert_eq!(&rows[2][..2], &[Num::I(10).into(), Num::I(20).into()]);
+    assert_eq!(&rows[3][..2], &[Num::I(30).into(), Num::I(40).into()]);
+    assert_eq!(
+        rows[4],
+        vec![Num::I(1).into(), Num::I(3).into(), Num::I(6).into()]
+    );
+    assert_eq!(
+        (&rows[5][..1], &rows[6][..1]),
+        (&[Num::I(3).into()][..], &[Num::I(7).into()][..])
+    );
+    assert_eq!(rows[7], vec![Num::I(4).into(), Num::I(6).into()]);
+}
+
+#[test]
+fn serialize_unresolved_and_with_formulas() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![
+            vec![
+                RawCellData::Int(5),
+                RawCellData::String("=SUM(A1,2)".to_owned()),
+                RawCellData::String("=SEQUENCE(2)".to_owned()),
+            ],
+            vec![RawCellData::String(
+                "=IF(true, LAMBDA(x, x)(C1#), {\"a\",1;2.5,false})".to_owned(),
+            )],
+        ]
+        .into(),
+    };
+
+    // formulas are written back the way they are parsed
+    let sheet: Sheet = raw.clone().into();
+    assert_eq!(
+        SerdeValue::from(sheet.clone()),
+        serde_json::json!({
+            "id": "sheet-test",
+            "data": [
+                [5, "=SUM(A1, 2)", "=SEQUENCE(2)"],
+                ["=IF(true, LAMBDA(x, x)(C1#), {\"a\",1;2.5,false})"],
+            ],
+        })
+    );
+
+    let sheet = sheet.resolve_refs(&ops);
+    assert_eq!(
+        sheet.to_serde_value_with_formulas(&raw),
+        serde_json::json!({
+            "id": "sheet-test",
+            "data": [
+                [5, {"formula": "=SUM(A1,2)", "value": 7}, {"formula": "=SEQUENCE(2)", "value": 1}],
+                [{"formula": "=IF(true, LAMBDA(x, x)(C1#), {\"a\",1;2.5,false})", "value": 1}, null, 2],
+                [2],
+            ],
+        })
+    );
+
+    assert_eq!(Position { x: 27, y: 9 }.name(), "AB10");
+}
+
+#[test]
+fn serde_round_trip() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: vec![
+            vec![
+                RawCellData::Int(5),
+                RawCellData::String("2023-07-17".to_owned()),
+                RawCellData::String("=SEQUENCE(2)".to_owned()),
+                RawCellData::String("=LET(f, LAMBDA(x, SUM(x, A1)), f(C1#))".to_owned()),
+            ],
+            vec![
+                RawCellData::String("=DIVIDE(A1, 0)".to_owned()),
+                RawCellData::String("=LAMBDA(x, x)(A1)".to_owned()),
+                RawCellData::Empty,
+                RawCellData::String("=NOPE(1)".to_owned()),
+            ],
+        ]
+        .into(),
+    };
+
+    let round_trip = |sheet: &Sheet| {
+        let json = serde_json::to_string(sheet).unwrap();
+        serde_json::from_str::<Sheet>(&json).unwrap()
+    };
+
+    let sheet: Sheet = raw.into();
+    assert_eq!(round_trip(&sheet), sheet);
+
+    let sheet = sheet.resolve_refs(&ops);
+    assert_eq!(round_trip(&sheet), sheet);
+    assert_eq!(
+        serde_json::to_value(sheet.get((0, 1)).unwrap()).unwrap(),
+        serde_json::json!({"Err": {"FormError": ["DivByZero"]}})
+    );
+
+    let error = CellError::InvalidValue("a positive number".into());
+    let json = serde_json::to_string(&error).unwrap();
+    assert_eq!(serde_json::from_str::<CellError>(&json).unwrap(), error);
+}
+
+#[test]
+fn position_names() {
+    for (name, pos) in [
+        ("A1", (0, 0)),
+        ("Z10", (25, 9)),
+        ("AA1", (26, 0)),
+        ("ZZ100000", (701, 99_999)),
+    ] {
+        assert_eq!(Position::from_name(name), Some(pos.into()));
+        assert_eq!(Position::from(pos).name(), name);
+        assert_eq!(Position::from(pos).to_string(), name);
+    }
+
+    for name in ["", "A", "1", "A0", "a1", "A1B", "1A"] {
+        assert_eq!(Position::from_name(name), None);
+    }
+}
+
+#[test]
+fn resolve_sparse_sheets() {
+    let ops = operators::get_default_op_map();
+    let raw = RawSheet {
+        id: "sheet-test".to_owned(),
+        data: RawCells::Named(vec![
+            ((0, 0).into(), RawCellData::Int(2)),
+            (
+                (2, 1).into(),
+                RawCellData::String("=SUM(A1, Z10000)".to_owned()),
+            ),
+            (
+                (1, 2).into(),
+                RawCellData::String("=SEQUENCE(2)".to_owned()),
+            ),
+            (
+                (0, 4).into(),
+                RawCellData::String("=MULTIPLY(B4, D7)".to_owned()),
+            ),
+            ((25, 9999).into(), RawCellData::Int(5)),
+        ]),
+    };
+
+    let sheet = Sheet::from(raw).resolve_refs(&ops);
+    assert!(sheet.cells.is_sparse());
+    assert_eq!((sheet.width(), sheet.cells.height()), (26, 10_000));
+    assert_eq!(sheet.get((2, 1)), Some(&Num::I(7).into()));
+    assert_eq!(sheet.get((1, 3)), Some(&Num::I(2).into()));
+    // D7 is a blank cell inside the bounding box
+    assert_eq!(sheet.get((0, 4)), Some(&Num::I(0).into()));
+    assert_eq!(sheet.get((3, 6)), None);
+    assert_eq!(
+        sheet.cells.iter().count(),
+        6,
+        "only cells which are not blank are stored"
+    );
+}
+
+#[test]
+fn intern_texts_of_a_workbook() {
+    let ops = operators::get_default_op_map();
+    let raw = |id: &str| RawSheet {
+        id: id.to_owned(),
+        data: vec![vec![
+            RawCellData::String("label".to_owned()),
+            RawCellData::String("=CONCAT(A1, \"label\")".to_owned()),
+            RawCellData::String("=concat(A1)".to_owned()),
+        ]]
+        .into(),
+    };
+
+    let mut interner = Interner::new();
+    let first = Sheet::from_raw(raw("first"), &mut interner);
+    let second = Sheet::from_raw(raw("second"), &mut interner);
+    // `label`, `CONCAT` and `concat`
+    assert_eq!(interner.len(), 3);
+
+    let text = |sheet: &Sheet| {
+        sheet
+            .get((0, 0))
+            .unwrap()
+            .unwrap_downcast_ref::<Text>()
+            .clone()
+    };
+    let form = |sheet: &Sheet, x: usize| match sheet.get((x, 0)) {
+        Some(Expr::Form(op)) => op.clone(),
+        e => panic!("not a formula: {:?}", e),
+    };
+    assert!(text(&first).ptr_eq(&text(&second)));
+    assert!(form(&first, 1).name.ptr_eq(&form(&second, 1).name));
+    assert!(form(&first, 1).args[1]
+        .unwrap_downcast_ref::<Text>()
+        .ptr_eq(&text(&first)));
+
+    let resolved = second.resolve_refs(&ops);
+    assert_eq!(resolved.get((1, 0)), Some(&"labellabel".to_owned().into()));
+    assert_eq!(resolved.get((2, 0)), Some(&"label".to_owned().into()));
+    assert_eq!(first.resolve_refs(&ops).cells, resolved.cells);
+}
@@ -6,4 +6,14 @@ pub mod into_serde_value;
 
 pub mod num;
 
-pub mod box_value;
\ No newline at end of file
+pub mod box_value;
+
+pub mod empty;
+
+pub mod text;
+
+pub mod date;
+
+pub mod array;
+
+pub mod registry;
@@ -0,0 +1,168 @@
+//! Contains the array value, which is a 2-D result of a formula
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde_json::value::Value as SerdeValue;
+
+use super::box_value::BoxValue;
+use super::value::Value;
+
+/// A non-empty rectangular array of values, stored row by row
+///
+/// An array returned by a formula spills into the cells
+/// to the right and below of the formula's cell
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Serialize)]
+pub struct Array(Vec<Vec<BoxValue>>);
+
+impl Value for Array {}
+
+impl<'de> Deserialize<'de> for Array {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Array::new(Vec::deserialize(deserializer)?).ok_or_else(|| {
+            D::Error::custom("an array is empty or its rows are of different lengths")
+        })
+    }
+}
+
+impl Array {
+    /// Returns `None` if there are no values or rows are of different lengths
+    pub fn new(rows: Vec<Vec<BoxValue>>) -> Option<Self> {
+        let width = rows.first()?.len();
+        if width == 0 || rows.iter().any(|row| row.len() != width) {
+            return None;
+        }
+
+        Some(Self(rows))
+    }
+
+    /// Creates a single column array
+    pub fn column(values: Vec<BoxValue>) -> Option<Self> {
+        Self::new(values.into_iter().map(|v| vec![v]).collect())
+    }
+
+    /// Creates a 1x1 array
+    pub fn single(value: BoxValue) -> Self {
+        Self(vec![vec![value]])
+    }
+
+    pub fn height(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn width(&self) -> usize {
+        self.0[0].len()
+    }
+
+    pub fn rows(&self) -> &[Vec<BoxValue>] {
+        &self.0
+    }
+
+    pub fn into_rows(self) -> Vec<Vec<BoxValue>> {
+        self.0
+    }
+
+    /// The value which is shown in the cell of the formula
+    pub fn top_left(&self) -> &BoxValue {
+        &self.0[0][0]
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self(
+            (0..self.width())
+                .map(|x| self.0.iter().map(|row| row[x].clone()).collect())
+                .collect(),
+        )
+    }
+
+    /// Iterates over `(x, y, value)` of all values
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, &BoxValue)> {
+        self.0
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, v)| (x, y, v)))
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut BoxValue> {
+        self.0.iter_mut().flatten()
+    }
+
+    /// Unwraps 1x1 arrays into their only value
+    pub fn into_value(mut self) -> BoxValue {
+        if self.height() == 1 && self.width() == 1 {
+            self.0.remove(0).remove(0)
+        } else {
+            self.into()
+        }
+    }
+}
+
+/// Displayed in the array literal syntax, e.g. `{1,2;3,4}`
+impl std::fmt::Display for Array {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows = self
+            .0
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>();
+
+        write!(f, "{{{}}}", rows.join(";"))
+    }
+}
+
+impl From<Array> for SerdeValue {
+    fn from(value: Array) -> Self {
+        SerdeValue::Array(
+            value
+                .0
+                .into_iter()
+                .map(|row| {
+                    SerdeValue::Array(row.into_iter().map(|v| v.into_serde_value()).collect())
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::num::Num;
+
+    fn nums(rows: &[&[i64]]) -> Array {
+        Array::new(
+            rows.iter()
+                .map(|row| row.iter().map(|i| Num::I(*i).into()).collect())
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn shape() {
+        assert!(Array::new(vec![]).is_none());
+        assert!(Array::new(vec![vec![]]).is_none());
+        assert!(Array::new(vec![vec![Num::I(1).into()], vec![]]).is_none());
+
+        let array = nums(&[&[1, 2, 3], &[4, 5, 6]]);
+        assert_eq!((array.width(), array.height()), (3, 2));
+        assert_eq!(array.transpose(), nums(&[&[1, 4], &[2, 5], &[3, 6]]));
+        assert_eq!(array.to_string(), "{1,2,3;4,5,6}");
+        assert_eq!(
+            SerdeValue::from(array),
+            serde_json::json!([[1, 2, 3], [4, 5, 6]])
+        );
+    }
+
+    #[test]
+    fn into_value() {
+        assert_eq!(nums(&[&[7]]).into_value(), Num::I(7).into());
+        assert_eq!(
+            nums(&[&[7, 8]]).into_value(),
+            BoxValue::from(nums(&[&[7, 8]]))
+        );
+    }
+}
@@ -1,47 +1,206 @@
-use derive_more::{Deref, DerefMut};
-// use dyn_eq::DynEq;
-use dyn_ord::{DynOrd, DynEq};
+//! Contains [BoxValue], the value of a cell
+//!
+//! Numbers, texts, booleans and blank cells are stored inline, so that most cells
+//! don't allocate and comparing them doesn't go through `dyn` calls,
+//! values of other types, e.g. arrays, dates or types outside of this crate, are boxed
 
-use super::value::Value;
+use std::any::Any;
+use std::cmp::Ordering;
+use std::ops::{Deref, DerefMut};
 
-#[derive(Debug, Clone, Deref, DerefMut)]
-pub struct BoxValue(Box<dyn Value>);
+use serde_json::Value as SerdeValue;
+
+use super::{empty::Empty, num::Num, text::Text, value::Value};
+
+/// A value of any type implementing [Value], it is viewed as a `dyn Value` through `Deref`
+///
+/// Values of the built-in types are always stored in their own variants,
+/// [BoxValue::Other] never holds them
+#[derive(Debug, Clone)]
+pub enum BoxValue {
+    Num(Num),
+    String(Text),
+    Bool(bool),
+    Empty(Empty),
+    Other(Box<dyn Value>),
+}
 
 impl BoxValue {
+    pub fn as_value(&self) -> &dyn Value {
+        match self {
+            BoxValue::Num(n) => n,
+            BoxValue::String(s) => s,
+            BoxValue::Bool(b) => b,
+            BoxValue::Empty(e) => e,
+            BoxValue::Other(v) => &**v,
+        }
+    }
+
+    pub fn as_value_mut(&mut self) -> &mut dyn Value {
+        match self {
+            BoxValue::Num(n) => n,
+            BoxValue::String(s) => s,
+            BoxValue::Bool(b) => b,
+            BoxValue::Empty(e) => e,
+            BoxValue::Other(v) => &mut **v,
+        }
+    }
+
+    /// Boxes values of the built-in types
     pub fn move_inner(self) -> Box<dyn Value> {
-        self.0
+        match self {
+            BoxValue::Num(n) => Box::new(n),
+            BoxValue::String(s) => Box::new(s),
+            BoxValue::Bool(b) => Box::new(b),
+            BoxValue::Empty(e) => Box::new(e),
+            BoxValue::Other(v) => v,
+        }
+    }
+
+    pub fn into_serde_value(self) -> SerdeValue {
+        match self {
+            BoxValue::Num(n) => n.into(),
+            BoxValue::String(s) => s.into(),
+            BoxValue::Bool(b) => b.into(),
+            BoxValue::Empty(e) => e.into(),
+            BoxValue::Other(v) => v.into_serde_value(),
+        }
+    }
+}
+
+impl Deref for BoxValue {
+    type Target = dyn Value;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_value()
+    }
+}
+
+impl DerefMut for BoxValue {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.as_value_mut()
     }
 }
 
+// takes `value` out of `slot` if it is a `T`, the checks are resolved when compiling
+fn take<T: Value, V: Value>(slot: &mut Option<V>) -> Option<T> {
+    (slot as &mut dyn Any)
+        .downcast_mut::<Option<T>>()
+        .and_then(Option::take)
+}
+
 impl<V> From<V> for BoxValue
 where
     V: Value,
 {
     fn from(value: V) -> Self {
-        BoxValue(Box::new(value))
+        let mut slot = Some(value);
+        if let Some(n) = take::<Num, _>(&mut slot) {
+            BoxValue::Num(n)
+        } else if let Some(s) = take::<Text, _>(&mut slot) {
+            BoxValue::String(s)
+        } else if let Some(b) = take::<bool, _>(&mut slot) {
+            BoxValue::Bool(b)
+        } else if let Some(e) = take::<Empty, _>(&mut slot) {
+            BoxValue::Empty(e)
+        } else {
+            BoxValue::Other(Box::new(slot.unwrap()))
+        }
+    }
+}
+
+impl From<String> for BoxValue {
+    fn from(value: String) -> Self {
+        BoxValue::String(value.into())
+    }
+}
+
+impl From<&str> for BoxValue {
+    fn from(value: &str) -> Self {
+        BoxValue::String(value.into())
     }
 }
 
+/// Values of the built-in types are moved out of their boxes
 impl From<Box<dyn Value>> for BoxValue {
     fn from(value: Box<dyn Value>) -> Self {
-        BoxValue(value)
+        let value = match value.downcast::<Num>() {
+            Ok(n) => return BoxValue::Num(*n),
+            Err(value) => value,
+        };
+        let value = match value.downcast::<Text>() {
+            Ok(s) => return BoxValue::String(*s),
+            Err(value) => value,
+        };
+        let value = match value.downcast::<bool>() {
+            Ok(b) => return BoxValue::Bool(*b),
+            Err(value) => value,
+        };
+        match value.downcast::<Empty>() {
+            Ok(e) => BoxValue::Empty(*e),
+            Err(value) => BoxValue::Other(value),
+        }
     }
 }
 
+/// Values of different types are never equal
 impl PartialEq for BoxValue {
     fn eq(&self, rhs: &BoxValue) -> bool {
-        let self_: &Box<_> = self;
-        let rhs_: &Box<_> = rhs;
-        ((self_.clone()) as Box<dyn DynEq>) == ((rhs_.clone()) as Box<dyn DynEq>)
+        match (self, rhs) {
+            (BoxValue::Num(l), BoxValue::Num(r)) => l == r,
+            (BoxValue::String(l), BoxValue::String(r)) => l == r,
+            (BoxValue::Bool(l), BoxValue::Bool(r)) => l == r,
+            (BoxValue::Empty(_), BoxValue::Empty(_)) => true,
+            (BoxValue::Other(l), BoxValue::Other(r)) => l.as_dyn_eq() == r.as_dyn_eq(),
+            _ => false,
+        }
     }
 }
 
 impl Eq for BoxValue {}
 
+/// Values of different types are not ordered
 impl PartialOrd for BoxValue {
-    fn partial_cmp(&self, rhs: &BoxValue) -> std::option::Option<std::cmp::Ordering> {
-        let self_: Box<dyn DynOrd> = self.0.clone() as Box<dyn DynOrd>;
-        let rhs_: Box<dyn DynOrd> = rhs.0.clone() as Box<dyn DynOrd>;
-        self_.partial_cmp(&rhs_)
+    fn partial_cmp(&self, rhs: &BoxValue) -> Option<Ordering> {
+        match (self, rhs) {
+            (BoxValue::Num(l), BoxValue::Num(r)) => l.partial_cmp(r),
+            (BoxValue::String(l), BoxValue::String(r)) => l.partial_cmp(r),
+            (BoxValue::Bool(l), BoxValue::Bool(r)) => l.partial_cmp(r),
+            (BoxValue::Empty(_), BoxValue::Empty(_)) => Some(Ordering::Equal),
+            (BoxValue::Other(l), BoxValue::Other(r)) => l.as_dyn_ord().partial_cmp(r.as_dyn_ord()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::array::Array;
+
+    #[test]
+    fn stores_built_in_values_inline() {
+        assert!(matches!(
+            BoxValue::from(Num::I(1)),
+            BoxValue::Num(Num::I(1))
+        ));
+        assert!(matches!(BoxValue::from(true), BoxValue::Bool(true)));
+        let boxed: Box<dyn Value> = "a".into();
+        assert!(matches!(BoxValue::from(boxed), BoxValue::String(s) if s == "a"));
+
+        let array = Array::new(vec![vec![Num::I(1).into()]]).unwrap();
+        let value = BoxValue::from(array.clone());
+        assert!(matches!(value, BoxValue::Other(_)));
+        assert_eq!(value.downcast_ref::<Array>(), Some(&array));
+        assert_eq!(BoxValue::from(value.clone().move_inner()), value);
+    }
+
+    #[test]
+    fn compares_values_of_the_same_type() {
+        let one = BoxValue::from(Num::I(1));
+        assert!(one < Num::F(1.5).into());
+        assert_eq!(one.partial_cmp(&"1".to_owned().into()), None);
+        assert_ne!(one, "1".to_owned().into());
+        assert_ne!(BoxValue::from(Empty), "".to_owned().into());
     }
 }
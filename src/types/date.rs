@@ -0,0 +1,231 @@
+//! Contains date, date-time and duration value implementations
+
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use derive_more::{Display, From};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::Value as SerdeValue;
+
+use super::value::Value;
+
+/// A calendar date, displayed as ISO-8601 `YYYY-MM-DD`
+#[derive(Debug, Clone, Copy, Display, From, PartialEq, Eq, PartialOrd, Ord)]
+#[display(fmt = "{}", "_0.format(\"%Y-%m-%d\")")]
+pub struct Date(pub NaiveDate);
+
+/// A date with time of day, displayed as ISO-8601 `YYYY-MM-DDTHH:MM:SS`
+#[derive(Debug, Clone, Copy, Display, From, PartialEq, Eq, PartialOrd, Ord)]
+#[display(fmt = "{}", "_0.format(\"%Y-%m-%dT%H:%M:%S%.f\")")]
+pub struct DateTime(pub NaiveDateTime);
+
+/// An exact amount of time, displayed as ISO-8601 duration, e.g. `P1DT2H30M`
+#[derive(Debug, Clone, Copy, From, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Duration(pub TimeDelta);
+
+impl Value for Date {}
+impl Value for DateTime {}
+impl Value for Duration {}
+
+// serialized the same way as they are displayed
+macro_rules! impl_serde_iso {
+    ($($t:ty),*) => {$(
+        impl Serialize for $t {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $t {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                <$t>::parse_iso(&s)
+                    .ok_or_else(|| D::Error::custom(format!("invalid ISO 8601 value `{}`", s)))
+            }
+        }
+    )*};
+}
+
+impl_serde_iso!(Date, DateTime);
+
+impl Serialize for Duration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// negative durations are displayed with a leading `-`, which isn't parsed from cells
+impl<'de> Deserialize<'de> for Duration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let duration = match s.strip_prefix('-') {
+            Some(positive) => Duration::parse_iso(positive).map(|d| Duration(-d.0)),
+            None => Duration::parse_iso(&s),
+        };
+        duration.ok_or_else(|| D::Error::custom(format!("invalid ISO 8601 duration `{}`", s)))
+    }
+}
+
+impl Date {
+    pub fn parse_iso(s: &str) -> Option<Self> {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(Date)
+    }
+}
+
+impl DateTime {
+    pub fn parse_iso(s: &str) -> Option<Self> {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M"))
+            .ok()
+            .map(DateTime)
+    }
+}
+
+impl Duration {
+    /// Parses durations in `PnW` or `PnDTnHnMnS` format,
+    /// years and months are not supported as their length varies
+    pub fn parse_iso(s: &str) -> Option<Self> {
+        let s = s.strip_prefix('P')?;
+        let (date, time) = s.split_once('T').unwrap_or((s, ""));
+        if date.is_empty() && time.is_empty() {
+            return None;
+        }
+
+        // splits `1D` or `1H2M` into (amount, designator) pairs
+        fn components(s: &str) -> Option<Vec<(i64, char)>> {
+            let mut components = vec![];
+            let mut rest = s;
+            while !rest.is_empty() {
+                let end = rest.find(|c: char| !c.is_ascii_digit())?;
+                let amount = rest[..end].parse().ok()?;
+                let designator = rest[end..].chars().next()?;
+                components.push((amount, designator));
+                rest = &rest[end + 1..];
+            }
+            Some(components)
+        }
+
+        // amounts too large for a duration are not durations, such cells stay text
+        let mut seconds: i64 = 0;
+        for (amount, designator) in components(date)? {
+            let unit = match designator {
+                'W' => 7 * 24 * 60 * 60,
+                'D' => 24 * 60 * 60,
+                _ => return None,
+            };
+            seconds = seconds.checked_add(amount.checked_mul(unit)?)?;
+        }
+        for (amount, designator) in components(time)? {
+            let unit = match designator {
+                'H' => 60 * 60,
+                'M' => 60,
+                'S' => 1,
+                _ => return None,
+            };
+            seconds = seconds.checked_add(amount.checked_mul(unit)?)?;
+        }
+
+        TimeDelta::try_seconds(seconds).map(Duration)
+    }
+}
+
+impl std::fmt::Display for Duration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.0.num_seconds();
+        if total < 0 {
+            write!(f, "-")?;
+        }
+
+        let total = total.unsigned_abs();
+        let (days, hours, minutes, seconds) = (
+            total / 86400,
+            total % 86400 / 3600,
+            total % 3600 / 60,
+            total % 60,
+        );
+
+        write!(f, "P")?;
+        if days > 0 {
+            write!(f, "{}D", days)?;
+        }
+        if hours > 0 || minutes > 0 || seconds > 0 || days == 0 {
+            write!(f, "T")?;
+            if hours > 0 {
+                write!(f, "{}H", hours)?;
+            }
+            if minutes > 0 {
+                write!(f, "{}M", minutes)?;
+            }
+            if seconds > 0 || (hours == 0 && minutes == 0) {
+                write!(f, "{}S", seconds)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Date> for SerdeValue {
+    fn from(value: Date) -> Self {
+        SerdeValue::String(value.to_string())
+    }
+}
+
+impl From<DateTime> for SerdeValue {
+    fn from(value: DateTime) -> Self {
+        SerdeValue::String(value.to_string())
+    }
+}
+
+impl From<Duration> for SerdeValue {
+    fn from(value: Duration) -> Self {
+        SerdeValue::String(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_iso() {
+        let date = Date::parse_iso("2023-07-17").unwrap();
+        assert_eq!(date.to_string(), "2023-07-17");
+
+        let date_time = DateTime::parse_iso("2023-07-17T08:30:00").unwrap();
+        assert_eq!(date_time.to_string(), "2023-07-17T08:30:00");
+        assert_eq!(
+            SerdeValue::from(date_time),
+            SerdeValue::String("2023-07-17T08:30:00".to_owned())
+        );
+
+        assert!(Date::parse_iso("2023-07-17 meeting").is_none());
+        assert!(Date::parse_iso("2023-02-30").is_none());
+    }
+
+    #[test]
+    fn duration_iso() {
+        let parse = |s| Duration::parse_iso(s).map(|d| d.to_string());
+
+        assert_eq!(parse("P1DT2H30M"), Some("P1DT2H30M".to_owned()));
+        assert_eq!(parse("P2W"), Some("P14D".to_owned()));
+        assert_eq!(parse("PT90M"), Some("PT1H30M".to_owned()));
+        assert_eq!(parse("PT0S"), Some("PT0S".to_owned()));
+        assert_eq!(parse("P1Y"), None);
+        assert_eq!(parse("P"), None);
+        assert_eq!(parse("PT1"), None);
+        // too large for a duration
+        assert_eq!(parse("P200000000000D"), None);
+        assert_eq!(parse("PT9223372036854775807S"), None);
+        assert_eq!(parse("P99999999999999999W"), None);
+        assert_eq!(parse("PT9223372036854775807S1S"), None);
+        assert_eq!(
+            Duration(TimeDelta::seconds(-90)).to_string(),
+            "-PT1M30S".to_owned()
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(Date::parse_iso("2023-07-17").unwrap() < Date::parse_iso("2023-07-18").unwrap());
+        assert!(Duration::parse_iso("P1D").unwrap() > Duration::parse_iso("PT23H").unwrap());
+    }
+}
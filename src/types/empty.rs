@@ -0,0 +1,22 @@
+//! Contains the value of an empty cell
+
+use derive_more::Display;
+use serde_json::value::Value as SerdeValue;
+
+use super::value::Value;
+
+/// Value of a blank cell, which is distinct from an empty string
+///
+/// It is coerced to `0` in numeric and to `""` in text contexts
+/// and is skipped by aggregates like `AVERAGE`
+#[derive(Debug, Clone, Copy, Default, Display, PartialEq, Eq, PartialOrd, Ord)]
+#[display(fmt = "")]
+pub struct Empty;
+
+impl Value for Empty {}
+
+impl From<Empty> for SerdeValue {
+    fn from(_: Empty) -> Self {
+        SerdeValue::Null
+    }
+}
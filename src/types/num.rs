@@ -1,60 +1,187 @@
 //! Contains number value implementation
+//!
+//! Promotion rules when mixing variants:
+//! - `I` with `I` stays `I`, unless dividing with a remainder,
+//!   if `+` or `*` overflows the result becomes `B`
+//! - `B` with `I` or `B` stays `B`, results which fit into an `i64` become `I` again
+//! - `D` with `I`, `B` or `F` becomes `D`, floats are converted through their shortest representation,
+//!   if the result does not fit into a decimal it falls back to `F`
+//! - `F` with `I` or `B` becomes `F`
 
+use std::cmp::Ordering;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign};
+use std::str::FromStr;
 
 use derive_more::{self, Display, From};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
 use super::value::Value;
 
 use serde_json::{value::Value as SerdeValue, Number};
 
 impl Value for Num {}
-#[derive(Debug, Clone, Copy, Display, From)]
+/// Decimals and big integers are serialized as text, so that any format holds them exactly
+#[derive(Debug, Clone, Display, From, Serialize, Deserialize)]
 pub enum Num {
     #[display(fmt = "{}", _0.display())]
     F(f64),
     #[display(fmt = "{}", _0.display())]
     I(i64),
+    /// Exact decimal, used when evaluating with [crate::sheets::DecimalMode]
+    #[display(fmt = "{}", _0)]
+    #[serde(with = "as_text")]
+    D(Decimal),
+    /// Integer which does not fit into an `i64`
+    #[display(fmt = "{}", _0)]
+    #[serde(with = "as_text")]
+    B(BigInt),
 }
 
-impl Into<SerdeValue> for Num {
-    fn into(self: Self) -> SerdeValue {
-        SerdeValue::Number(
-            Number::from_f64(match self {
-                Num::F(f) => f,
-                Num::I(i) => i as f64,
-            })
-            .unwrap(),
-        )
+mod as_text {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
     }
 }
 
-impl PartialOrd<Num> for Num {
-    fn partial_cmp(&self, rhs: &Num) -> Option<std::cmp::Ordering> {
+impl Num {
+    /// Keeps big integers which fit into an `i64` as [Num::I]
+    pub fn from_big(b: BigInt) -> Num {
+        b.to_i64().map(Num::I).unwrap_or(Num::B(b))
+    }
+
+    /// Converts to a decimal, `None` if a float is not finite or the number is too large
+    pub fn to_decimal(&self) -> Option<Decimal> {
         match self {
-            Num::I(i1) => match rhs {
-                Num::I(i2) => i1.partial_cmp(&i2),
-                Num::F(f2) => (*i1 as f64).partial_cmp(&f2),
-            },
-            Num::F(f1) => match rhs {
-                Num::F(f2) => f1.partial_cmp(f2),
-                Num::I(i2) => (*f1).partial_cmp(&(*i2 as f64)),
+            Num::I(i) => Some(Decimal::from(*i)),
+            // `to_string` gives the shortest representation which round-trips,
+            // i.e. `0.1` instead of `0.1000000000000000055511151231257827`
+            Num::F(f) => Decimal::from_str(&f.to_string()).ok(),
+            Num::D(d) => Some(*d),
+            Num::B(b) => Decimal::from_str(&b.to_string()).ok(),
+        }
+    }
+
+    /// Converts to an integer, truncating the fractional part,
+    /// `None` if a float is not finite
+    pub fn to_big_int(&self) -> Option<BigInt> {
+        match self {
+            Num::I(i) => Some(BigInt::from(*i)),
+            Num::F(f) => BigInt::from_str(&format!("{:.0}", f.trunc())).ok(),
+            Num::D(d) => BigInt::from_str(&d.trunc().to_string()).ok(),
+            Num::B(b) => Some(b.clone()),
+        }
+    }
+
+    /// Truncates the fractional part, numbers out of range are saturated
+    pub fn trunc(&self) -> i64 {
+        let saturated = |negative: bool| if negative { i64::MIN } else { i64::MAX };
+        match self {
+            Num::I(i) => *i,
+            Num::F(f) => *f as i64,
+            Num::D(d) => d
+                .trunc()
+                .to_i64()
+                .unwrap_or(saturated(d.is_sign_negative())),
+            Num::B(b) => b
+                .to_i64()
+                .unwrap_or(saturated(b.sign() == num_bigint::Sign::Minus)),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Num::I(i) => *i == 0,
+            Num::F(f) => *f == 0.0,
+            Num::D(d) => d.is_zero(),
+            Num::B(b) => b.is_zero(),
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, Num::I(_) | Num::B(_))
+    }
+
+    // applies `op` on decimals if either of the numbers is a decimal,
+    // returns `None` if neither of them is or if the result overflows
+    fn decimal_op(&self, rhs: &Num, op: fn(Decimal, Decimal) -> Option<Decimal>) -> Option<Num> {
+        match (self, rhs) {
+            (Num::D(_), _) | (_, Num::D(_)) => {
+                op(self.to_decimal()?, rhs.to_decimal()?).map(Num::D)
+            }
+            _ => None,
+        }
+    }
+
+    // applies `op` on big integers if both numbers are integers
+    fn big_op(&self, rhs: &Num, op: fn(BigInt, BigInt) -> BigInt) -> Option<Num> {
+        if self.is_integer() && rhs.is_integer() {
+            Some(Num::from_big(op(self.to_big_int()?, rhs.to_big_int()?)))
+        } else {
+            None
+        }
+    }
+}
+
+/// Decimals and big integers are serialized as strings in order to keep all of their digits
+impl From<Num> for SerdeValue {
+    fn from(value: Num) -> Self {
+        match value {
+            // infinities and NaN, which JSON can't hold, are written as text
+            Num::F(f) => Number::from_f64(f)
+                .map(SerdeValue::Number)
+                .unwrap_or_else(|| SerdeValue::String(value.to_string())),
+            Num::I(i) => SerdeValue::Number(Number::from(i)),
+            Num::D(d) => SerdeValue::String(d.to_string()),
+            Num::B(b) => SerdeValue::String(b.to_string()),
+        }
+    }
+}
+
+impl PartialOrd<Num> for Num {
+    fn partial_cmp(&self, rhs: &Num) -> Option<Ordering> {
+        match (self, rhs) {
+            (Num::I(i1), Num::I(i2)) => i1.partial_cmp(i2),
+            _ if self.is_integer() && rhs.is_integer() => {
+                self.to_big_int().partial_cmp(&rhs.to_big_int())
+            }
+            (Num::D(_), _) | (_, Num::D(_)) => match (self.to_decimal(), rhs.to_decimal()) {
+                (Some(d1), Some(d2)) => d1.partial_cmp(&d2),
+                _ => f64::from(self).partial_cmp(&f64::from(rhs)),
             },
+            _ => f64::from(self).partial_cmp(&f64::from(rhs)),
         }
     }
 }
 
 impl PartialEq for Num {
     fn eq(&self, rhs: &Num) -> bool {
-        match self {
-            Num::I(i1) => match rhs {
-                Num::I(i2) => i1 == i2,
-                Num::F(f2) => *i1 as f64 == *f2,
-            },
-            Num::F(f1) => match rhs {
-                Num::F(f2) => f1 == f2,
-                Num::I(i2) => *f1 == (*i2 as f64),
-            },
+        match (self, rhs) {
+            (Num::I(i1), Num::I(i2)) => i1 == i2,
+            (Num::F(f1), Num::F(f2)) => f1 == f2,
+            _ => self.partial_cmp(rhs) == Some(Ordering::Equal),
         }
     }
 }
@@ -65,15 +192,18 @@ impl Add for Num {
     type Output = Num;
 
     fn add(self, rhs: Self) -> Self::Output {
-        match self {
-            Num::I(i1) => match rhs {
-                Num::I(i2) => Num::I(i1 + i2),
-                Num::F(f2) => Num::F(i1 as f64 + f2),
-            },
-            Num::F(f1) => match rhs {
-                Num::F(f2) => Num::F(f1 + f2),
-                Num::I(i2) => Num::F(f1 + (i2 as f64)),
-            },
+        if let Some(d) = self.decimal_op(&rhs, Decimal::checked_add) {
+            return d;
+        }
+
+        match (&self, &rhs) {
+            (Num::I(i1), Num::I(i2)) => i1
+                .checked_add(*i2)
+                .map(Num::I)
+                .unwrap_or_else(|| Num::B(BigInt::from(*i1) + i2)),
+            _ => self
+                .big_op(&rhs, |b1, b2| b1 + b2)
+                .unwrap_or_else(|| Num::F(f64::from(&self) + f64::from(&rhs))),
         }
     }
 }
@@ -82,15 +212,18 @@ impl Mul for Num {
     type Output = Num;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        match self {
-            Num::I(i1) => match rhs {
-                Num::I(i2) => Num::I(i1 * i2),
-                Num::F(f2) => Num::F(i1 as f64 * f2),
-            },
-            Num::F(f1) => match rhs {
-                Num::F(f2) => Num::F(f1 * f2),
-                Num::I(i2) => Num::F(f1 * (i2 as f64)),
-            },
+        if let Some(d) = self.decimal_op(&rhs, Decimal::checked_mul) {
+            return d;
+        }
+
+        match (&self, &rhs) {
+            (Num::I(i1), Num::I(i2)) => i1
+                .checked_mul(*i2)
+                .map(Num::I)
+                .unwrap_or_else(|| Num::B(BigInt::from(*i1) * i2)),
+            _ => self
+                .big_op(&rhs, |b1, b2| b1 * b2)
+                .unwrap_or_else(|| Num::F(f64::from(&self) * f64::from(&rhs))),
         }
     }
 }
@@ -99,20 +232,30 @@ impl Div for Num {
     type Output = Num;
 
     fn div(self, rhs: Self) -> Self::Output {
-        match self {
-            Num::I(i1) => match rhs {
-                Num::I(i2) => {
-                    if i1 % i2 == 0 {
-                        Num::I(i1 / i2)
+        if let Some(d) = self.decimal_op(&rhs, Decimal::checked_div) {
+            return d;
+        }
+
+        match (&self, &rhs) {
+            // `i64::MIN / -1` overflows
+            (Num::I(i1), Num::I(-1)) if *i1 == i64::MIN => Num::B(-BigInt::from(*i1)),
+            (Num::I(i1), Num::I(i2)) => {
+                if i1.checked_rem(*i2) == Some(0) {
+                    Num::I(i1 / i2)
+                } else {
+                    Num::F(*i1 as f64 / *i2 as f64)
+                }
+            }
+            _ => match (self.to_big_int(), rhs.to_big_int()) {
+                (Some(b1), Some(b2)) if self.is_integer() && rhs.is_integer() => {
+                    let (quotient, remainder) = b1.div_rem(&b2);
+                    if remainder.is_zero() {
+                        Num::from_big(quotient)
                     } else {
-                        Num::F(i1 as f64 / i2 as f64)
+                        Num::F(f64::from(&self) / f64::from(&rhs))
                     }
                 }
-                Num::F(f2) => Num::F(i1 as f64 / f2),
-            },
-            Num::F(f1) => match rhs {
-                Num::F(f2) => Num::F(f1 / f2),
-                Num::I(i2) => Num::F(f1 / (i2 as f64)),
+                _ => Num::F(f64::from(&self) / f64::from(&rhs)),
             },
         }
     }
@@ -120,27 +263,141 @@ impl Div for Num {
 
 impl AddAssign for Num {
     fn add_assign(&mut self, rhs: Self) {
-        *self = *self + rhs
+        *self = self.clone() + rhs
     }
 }
 
 impl MulAssign for Num {
     fn mul_assign(&mut self, rhs: Self) {
-        *self = *self * rhs
+        *self = self.clone() * rhs
     }
 }
 
 impl DivAssign for Num {
     fn div_assign(&mut self, rhs: Self) {
-        *self = *self / rhs
+        *self = self.clone() / rhs
     }
 }
 
-impl Into<f64> for Num {
-    fn into(self) -> f64 {
-        match self {
-            Num::F(f) => f,
-            Num::I(i) => i as f64,
+impl From<&Num> for f64 {
+    fn from(value: &Num) -> Self {
+        match value {
+            Num::F(f) => *f,
+            Num::I(i) => *i as f64,
+            Num::D(d) => d.to_f64().unwrap_or(f64::NAN),
+            Num::B(b) => b.to_f64().unwrap_or(f64::NAN),
         }
     }
 }
+
+impl From<Num> for f64 {
+    fn from(value: Num) -> Self {
+        f64::from(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Num {
+        Num::D(Decimal::from_str(s).unwrap())
+    }
+
+    fn big(s: &str) -> Num {
+        Num::B(BigInt::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn non_finite_serialization() {
+        assert_eq!(SerdeValue::from(Num::F(1.5)), serde_json::json!(1.5));
+        assert_eq!(
+            SerdeValue::from(Num::F(f64::INFINITY)),
+            SerdeValue::String(Num::F(f64::INFINITY).to_string())
+        );
+        assert!(SerdeValue::from(Num::F(f64::NAN)).is_string());
+    }
+
+    #[test]
+    fn decimal_promotion() {
+        assert_eq!(dec("0.1") + Num::F(0.2), dec("0.3"));
+        assert!(matches!(dec("0.1") + Num::F(0.2), Num::D(_)));
+        assert!(matches!(Num::I(2) * dec("1.5"), Num::D(_)));
+        assert_eq!(Num::I(2) * dec("1.5"), Num::I(3));
+        assert_eq!(dec("1") / Num::I(3), dec("0.3333333333333333333333333333"));
+        assert!(matches!(Num::F(0.1) + Num::I(1), Num::F(_)));
+
+        // overflowing decimals fall back to floats
+        assert!(matches!(Num::D(Decimal::MAX) * Num::I(2), Num::F(_)));
+    }
+
+    #[test]
+    fn decimal_comparison() {
+        assert!(dec("0.3") > Num::F(0.2));
+        assert!(dec("0.3") < Num::I(1));
+        assert_eq!(dec("2.00"), Num::I(2));
+        assert_eq!(dec("0.3"), Num::F(0.3));
+        assert_ne!(Num::F(0.1) + Num::F(0.2), Num::F(0.3));
+    }
+
+    #[test]
+    fn decimal_serialization() {
+        assert_eq!(
+            SerdeValue::from(dec("0.30")),
+            SerdeValue::String("0.30".to_owned())
+        );
+        assert_eq!(dec("-2.75").trunc(), -2);
+    }
+
+    #[test]
+    fn big_int_promotion() {
+        assert_eq!(Num::I(i64::MAX) + Num::I(1), big("9223372036854775808"));
+        assert_eq!(
+            Num::I(i64::MAX) * Num::I(i64::MAX),
+            big("85070591730234615847396907784232501249")
+        );
+        assert_eq!(Num::I(i64::MIN) / Num::I(-1), big("9223372036854775808"));
+
+        // results which fit are demoted
+        assert!(matches!(
+            big("9223372036854775808") + Num::I(-1),
+            Num::I(i64::MAX)
+        ));
+        assert!(matches!(
+            big("85070591730234615847396907784232501249") / Num::I(i64::MAX),
+            Num::I(i64::MAX)
+        ));
+        assert!(matches!(big("9223372036854775808") / Num::I(3), Num::F(_)));
+        assert!(matches!(
+            big("9223372036854775808") + Num::F(0.5),
+            Num::F(_)
+        ));
+        assert!(matches!(big("9223372036854775808") + dec("0.5"), Num::D(_)));
+    }
+
+    #[test]
+    fn big_int_comparison() {
+        let b = big("9223372036854775808");
+
+        assert!(b > Num::I(i64::MAX));
+        assert!(b < big("9223372036854775809"));
+        assert!(b > Num::F(1e18));
+        assert!(b < Num::F(1e19));
+        assert!(b > dec("9223372036854775807.5"));
+        assert_eq!(b, Num::F(9223372036854775808.0));
+        assert_eq!(Num::from_big(BigInt::from(5)), Num::I(5));
+    }
+
+    #[test]
+    fn big_int_serialization() {
+        assert_eq!(
+            SerdeValue::from(big("85070591730234615847396907784232501249")),
+            SerdeValue::String("85070591730234615847396907784232501249".to_owned())
+        );
+        assert_eq!(
+            SerdeValue::from(Num::I(i64::MAX)).to_string(),
+            "9223372036854775807"
+        );
+        assert_eq!(big("-9223372036854775809").trunc(), i64::MIN);
+    }
+}
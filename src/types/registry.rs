@@ -0,0 +1,203 @@
+//! Contains serialization of [BoxValue]s
+//!
+//! Values of types of this crate are serialized as an enum named after their types,
+//! e.g. `{"Num": {"I": 5}}` in JSON, which any serde format can hold.
+//! Values of other types have to be registered by [register_value] first,
+//! they are serialized as `{"Other": [name, value]}` through [serde_json::Value],
+//! so only self-describing formats, e.g. JSON, CBOR or MessagePack, can hold them
+
+use std::any::TypeId;
+use std::sync::{PoisonError, RwLock};
+
+use downcast_rs::Downcast;
+use serde::{de, de::DeserializeOwned, ser, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value as SerdeValue;
+
+use super::{
+    array::Array,
+    box_value::BoxValue,
+    date::{Date, DateTime, Duration},
+    empty::Empty,
+    num::Num,
+    value::Value,
+};
+use crate::sheets::lambda::Lambda;
+
+struct Registered {
+    name: &'static str,
+    type_id: TypeId,
+    serialize: fn(&dyn Value) -> serde_json::Result<SerdeValue>,
+    deserialize: fn(SerdeValue) -> serde_json::Result<BoxValue>,
+}
+
+static REGISTRY: RwLock<Vec<Registered>> = RwLock::new(Vec::new());
+
+/// Registers a type of values which is not a part of this crate, `name` identifies
+/// its values when they are deserialized, registering a name or a type again replaces it
+pub fn register_value<T: Value + Serialize + DeserializeOwned>(name: &'static str) {
+    let mut registry = REGISTRY.write().unwrap_or_else(PoisonError::into_inner);
+    registry.retain(|r| r.name != name && r.type_id != TypeId::of::<T>());
+    registry.push(Registered {
+        name,
+        type_id: TypeId::of::<T>(),
+        serialize: |value| serde_json::to_value(value.downcast_ref::<T>()),
+        deserialize: |value| serde_json::from_value::<T>(value).map(BoxValue::from),
+    });
+}
+
+// variants of both have to be in the same order, formats like bincode identify them by index
+#[derive(Serialize)]
+#[serde(rename = "Value")]
+enum ValueRef<'a> {
+    Num(&'a Num),
+    Bool(bool),
+    String(&'a str),
+    Empty,
+    Array(&'a Array),
+    Date(&'a Date),
+    DateTime(&'a DateTime),
+    Duration(&'a Duration),
+    Lambda(&'a Lambda),
+    Other(&'static str, SerdeValue),
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Value")]
+enum ValueRepr {
+    Num(Num),
+    Bool(bool),
+    String(String),
+    Empty,
+    Array(Array),
+    Date(Date),
+    DateTime(DateTime),
+    Duration(Duration),
+    Lambda(Lambda),
+    Other(String, SerdeValue),
+}
+
+fn other<E: ser::Error>(value: &dyn Value) -> Result<ValueRef<'_>, E> {
+    Ok(if let Some(array) = value.downcast_ref::<Array>() {
+        ValueRef::Array(array)
+    } else if let Some(date) = value.downcast_ref::<Date>() {
+        ValueRef::Date(date)
+    } else if let Some(date_time) = value.downcast_ref::<DateTime>() {
+        ValueRef::DateTime(date_time)
+    } else if let Some(duration) = value.downcast_ref::<Duration>() {
+        ValueRef::Duration(duration)
+    } else if let Some(lambda) = value.downcast_ref::<Lambda>() {
+        ValueRef::Lambda(lambda)
+    } else {
+        let registry = REGISTRY.read().unwrap_or_else(PoisonError::into_inner);
+        let type_id = Downcast::as_any(value).type_id();
+        let registered = registry
+            .iter()
+            .find(|r| r.type_id == type_id)
+            .ok_or_else(|| E::custom(format!("the type of {:?} is not registered", value)))?;
+        let inner = (registered.serialize)(value).map_err(E::custom)?;
+        ValueRef::Other(registered.name, inner)
+    })
+}
+
+impl Serialize for BoxValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match self {
+            BoxValue::Num(n) => ValueRef::Num(n),
+            BoxValue::Bool(b) => ValueRef::Bool(*b),
+            BoxValue::String(s) => ValueRef::String(s),
+            BoxValue::Empty(_) => ValueRef::Empty,
+            BoxValue::Other(value) => other(&**value)?,
+        };
+
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BoxValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match ValueRepr::deserialize(deserializer)? {
+            ValueRepr::Num(n) => n.into(),
+            ValueRepr::Bool(b) => b.into(),
+            ValueRepr::String(s) => s.into(),
+            ValueRepr::Empty => Empty.into(),
+            ValueRepr::Array(array) => array.into(),
+            ValueRepr::Date(date) => date.into(),
+            ValueRepr::DateTime(date_time) => date_time.into(),
+            ValueRepr::Duration(duration) => duration.into(),
+            ValueRepr::Lambda(lambda) => lambda.into(),
+            ValueRepr::Other(name, inner) => {
+                let registry = REGISTRY.read().unwrap_or_else(PoisonError::into_inner);
+                let registered = registry.iter().find(|r| r.name == name).ok_or_else(|| {
+                    de::Error::custom(format!("the type `{}` is not registered", name))
+                })?;
+                (registered.deserialize)(inner).map_err(de::Error::custom)?
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use derive_more::Display;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Display, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+    struct Money(i64);
+
+    impl Value for Money {}
+
+    impl From<Money> for SerdeValue {
+        fn from(value: Money) -> Self {
+            SerdeValue::String(value.to_string())
+        }
+    }
+
+    fn round_trip(value: BoxValue) -> (SerdeValue, BoxValue) {
+        let json = serde_json::to_value(&value).unwrap();
+        let value = serde_json::from_value(json.clone()).unwrap();
+        (json, value)
+    }
+
+    #[test]
+    fn serializes_values() {
+        let array = Array::new(vec![vec![
+            Num::I(1).into(),
+            "a".to_owned().into(),
+            Empty.into(),
+        ]])
+        .unwrap();
+        let (json, value) = round_trip(array.clone().into());
+        assert_eq!(
+            json,
+            json!({"Array": [[{"Num": {"I": 1}}, {"String": "a"}, "Empty"]]})
+        );
+        assert_eq!(value, array.into());
+
+        let duration = Duration::parse_iso("PT90M").unwrap();
+        let duration = Duration(-duration.0);
+        let (json, value) = round_trip(duration.into());
+        assert_eq!(json, json!({"Duration": "-PT1H30M"}));
+        assert_eq!(value, duration.into());
+
+        let big = Num::from_big(num_bigint::BigInt::from(i64::MAX) * 2);
+        assert_eq!(round_trip(big.clone().into()).1, big.into());
+
+        let ragged = json!({"Array": [[{"Bool": true}], []]});
+        assert!(serde_json::from_value::<BoxValue>(ragged).is_err());
+    }
+
+    #[test]
+    fn serializes_registered_values() {
+        assert!(serde_json::to_value(BoxValue::from(Money(5))).is_err());
+
+        register_value::<Money>("Money");
+        let (json, value) = round_trip(Money(5).into());
+        assert_eq!(json, json!({"Other": ["Money", 5]}));
+        assert_eq!(value, Money(5).into());
+
+        let unknown = json!({"Other": ["Unknown", 5]});
+        assert!(serde_json::from_value::<BoxValue>(unknown).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Contains [Text], the value of text cells, and [Interner],
+//! which makes equal texts of a workbook share their storage
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value as SerdeValue;
+
+use super::value::Value;
+
+/// An immutable string whose copies share the same storage, it derefs to `&str`
+///
+/// Texts sharing storage are equal without comparing their contents,
+/// which is the case for all equal texts from the same [Interner]
+#[derive(Clone)]
+pub struct Text(Arc<str>);
+
+impl Value for Text {}
+
+impl Text {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether both texts share the same storage
+    pub fn ptr_eq(&self, other: &Text) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Address of the storage, which is the same for texts for which [Text::ptr_eq] holds
+    pub fn addr(&self) -> usize {
+        Arc::as_ptr(&self.0) as *const u8 as usize
+    }
+}
+
+impl Deref for Text {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Text {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Text {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Text {
+    fn from(value: &str) -> Self {
+        Text(value.into())
+    }
+}
+
+impl From<String> for Text {
+    fn from(value: String) -> Self {
+        Text(value.into())
+    }
+}
+
+impl From<Text> for String {
+    fn from(value: Text) -> Self {
+        value.0.to_string()
+    }
+}
+
+impl From<Text> for SerdeValue {
+    fn from(value: Text) -> Self {
+        SerdeValue::String(value.into())
+    }
+}
+
+impl PartialEq for Text {
+    fn eq(&self, other: &Text) -> bool {
+        self.ptr_eq(other) || self.0 == other.0
+    }
+}
+
+impl Eq for Text {}
+
+impl PartialEq<str> for Text {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Text {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialOrd for Text {
+    fn partial_cmp(&self, other: &Text) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Text {
+    fn cmp(&self, other: &Text) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+// the same as of `str`, which `Borrow` requires
+impl Hash for Text {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for Text {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Text::from)
+    }
+}
+
+/// Hands out a single [Text] for all equal strings, texts of cells and names of functions
+/// of a workbook are interned by the same interner, see [crate::sheets::Sheet::from_raw]
+///
+/// Interned texts are kept until the interner is dropped
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    texts: HashSet<Text>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, s: &str) -> Text {
+        match self.texts.get(s) {
+            Some(text) => text.clone(),
+            None => self.insert(s.into()),
+        }
+    }
+
+    /// Same as [Interner::intern], but keeps `text` if it is the first of its contents
+    pub fn intern_text(&mut self, text: &Text) -> Text {
+        match self.texts.get(text.as_str()) {
+            Some(text) => text.clone(),
+            None => self.insert(text.clone()),
+        }
+    }
+
+    fn insert(&mut self, text: Text) -> Text {
+        self.texts.insert(text.clone());
+        text
+    }
+
+    /// Number of distinct texts
+    pub fn len(&self) -> usize {
+        self.texts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interns_equal_texts_once() {
+        let mut interner = Interner::new();
+        let a = interner.intern("label");
+        let b = interner.intern_text(&Text::from("label".to_owned()));
+        assert!(a.ptr_eq(&b));
+        assert_eq!(a.addr(), b.addr());
+
+        let other = Text::from("label");
+        assert!(!a.ptr_eq(&other));
+        assert_eq!(a, other);
+        assert!(interner.intern_text(&other).ptr_eq(&a));
+
+        assert_ne!(interner.intern("Label"), a);
+        assert_eq!(interner.len(), 2);
+        assert_eq!(serde_json::to_string(&a).unwrap(), "\"label\"");
+    }
+}
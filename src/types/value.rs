@@ -7,21 +7,51 @@ use dyn_clone::DynClone;
 use dyn_eq::DynEq;
 use dyn_ord;
 
-use super::into_serde_value::IntoSerdeValue;
+use super::{into_serde_value::IntoSerdeValue, text::Text};
 
 #[dyn_clonable::clonable]
 /// This trait is implemented for any type which can be used as a value in a cell
+///
+/// Values are shared between threads evaluating a sheet in parallel
 pub trait Value:
-    Any + Debug + Display + DynClone + DynEq + dyn_ord::DynOrd + Clone + Downcast + IntoSerdeValue
+    Any
+    + Send
+    + Sync
+    + Debug
+    + Display
+    + DynClone
+    + DynEq
+    + dyn_ord::DynOrd
+    + AsDynOrd
+    + Clone
+    + Downcast
+    + IntoSerdeValue
 {
 }
 impl_downcast!(Value);
 
+/// Allows to view a `dyn Value` as a `dyn DynOrd`
+/// without relying on trait upcasting
+pub trait AsDynOrd {
+    fn as_dyn_eq(&self) -> &dyn dyn_ord::DynEq;
+
+    fn as_dyn_ord(&self) -> &dyn dyn_ord::DynOrd;
+}
+
+impl<T: dyn_ord::DynOrd> AsDynOrd for T {
+    fn as_dyn_eq(&self) -> &dyn dyn_ord::DynEq {
+        self
+    }
+
+    fn as_dyn_ord(&self) -> &dyn dyn_ord::DynOrd {
+        self
+    }
+}
+
 impl From<&str> for Box<dyn Value> {
     fn from(value: &str) -> Self {
-        Box::new(value.to_owned())
+        Box::new(Text::from(value))
     }
 }
 
-impl Value for String {}
 impl Value for bool {}
@@ -1,4 +1,5 @@
-use wix_sheets::data::{RawCellData::*, RawData};
+use wix_sheets::data::{read_parts, RawCellData::*, RawCells, RawData, RawPart, ReadError};
+use wix_sheets::sheets::Position;
 
 #[test]
 fn parses() {
@@ -12,9 +13,102 @@ fn parses() {
             .find(|s| s.id == "sheet-12")
             .unwrap()
             .data,
-        vec![
+        RawCells::Rows(vec![
             vec![Float(10.75), Float(10.75), String("=EQ(A1, B2)".into())],
             vec![Float(10.74), Float(10.74), String("=EQ(A2, B2)".into())]
-        ]
+        ])
     );
 }
+
+#[test]
+fn parses_null_as_empty() {
+    let data: RawData = serde_json::from_str(
+        r#"{ "submissionUrl": "", "sheets": [{ "id": "sheet-0", "data": [[1, null, "=A1"]] }] }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        data.sheets[0].data,
+        RawCells::Rows(vec![vec![Int(1), Empty, String("=A1".into())]])
+    );
+}
+
+#[test]
+fn parses_named_cells() {
+    let data: RawData = serde_json::from_str(
+        r#"{ "sheets": [{ "id": "sheet-0", "data": { "C7": "=A1", "A1": 1, "AA1": null } }] }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        data.sheets[0].data,
+        RawCells::Named(vec![
+            (Position { x: 0, y: 0 }, Int(1)),
+            (Position { x: 26, y: 0 }, Empty),
+            (Position { x: 2, y: 6 }, String("=A1".into())),
+        ])
+    );
+
+    let invalid = r#"{ "sheets": [{ "id": "sheet-0", "data": { "1A": 1 } }] }"#;
+    assert!(serde_json::from_str::<RawData>(invalid).is_err());
+
+    let last = r#"{ "sheets": [{ "id": "sheet-0", "data": { "XFD1048576": 1 } }] }"#;
+    assert!(serde_json::from_str::<RawData>(last).is_ok());
+    for beyond in ["XFE1", "A1048577", "A100000000", "ZZZZZZZZZZ1"] {
+        let data = format!(
+            r#"{{ "sheets": [{{ "id": "sheet-0", "data": {{ "{}": 1 }} }}] }}"#,
+            beyond
+        );
+        let error = serde_json::from_str::<RawData>(&data).unwrap_err();
+        assert!(error.to_string().contains("beyond the last cell"));
+    }
+}
+
+#[test]
+fn parses_functions() {
+    let data: RawData = serde_json::from_str(
+        r#"{ "submissionUrl": "", "sheets": [], "functions": ["F(x) = SUM(x, 1)"] }"#,
+    )
+    .unwrap();
+
+    assert_eq!(data.functions, vec!["F(x) = SUM(x, 1)".to_owned()]);
+}
+
+#[test]
+fn reads_parts_one_at_a_time() {
+    let str = include_str!("example.json");
+    let data: RawData = serde_json::from_str(str).unwrap();
+
+    let mut parts = Vec::new();
+    read_parts(str.as_bytes(), |part| {
+        parts.push(part);
+        Ok(())
+    })
+    .unwrap();
+
+    let sheets: Vec<_> = data.sheets.into_iter().map(RawPart::Sheet).collect();
+    assert_eq!(parts[0], RawPart::SubmissionUrl(data.submission_url));
+    assert_eq!(parts[1..], sheets[..]);
+}
+
+#[test]
+fn stops_reading_parts() {
+    let str = r#"{ "functions": ["F(x) = x"], "sheets": [{ "id": "a", "data": [] }, { "id": "b", "data": [] }] }"#;
+    let mut read = 0;
+    let result = read_parts(str.as_bytes(), |part| match part {
+        RawPart::Sheet(sheet) if sheet.id == "a" => Err("stopped".into()),
+        _ => {
+            read += 1;
+            Ok(())
+        }
+    });
+
+    assert!(matches!(result, Err(ReadError::Handler(e)) if e.to_string() == "stopped"));
+    assert_eq!(read, 1);
+
+    let late = r#"{ "sheets": [], "functions": ["F(x) = x"] }"#;
+    assert!(matches!(
+        read_parts(late.as_bytes(), |_| Ok(())),
+        Err(ReadError::FunctionsAfterSheets)
+    ));
+}